@@ -0,0 +1,42 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use ton_block::{BlockIdExt, ShardIdent};
+use ton_types::UInt256;
+
+use ton_node_storage::block_handle_db::BlockHandleDb;
+use ton_node_storage::db::traits::KvcWriteable;
+use ton_node_storage::types::{BlockId, BlockIdHashing};
+
+const BLOCKS: u32 = 1_000_000;
+
+fn block_id(seq_no: u32) -> BlockIdExt {
+    let mut hash = [0; 32];
+    hash[..4].copy_from_slice(&seq_no.to_le_bytes());
+    BlockIdExt::with_params(ShardIdent::masterchain(), seq_no, UInt256::from(hash), UInt256::from(hash))
+}
+
+fn bench_million_block_insert(c: &mut Criterion) {
+    let ids: Vec<_> = (0..BLOCKS).map(block_id).collect();
+    let meta = [0u8; 32];
+
+    let mut group = c.benchmark_group("insert million blocks");
+    group.sample_size(10);
+    for &hashing in &[BlockIdHashing::Sha256, BlockIdHashing::Raw] {
+        group.bench_function(format!("{:?}", hashing), |b| b.iter_batched(
+            || {
+                let dir = tempfile::tempdir().unwrap();
+                let db = BlockHandleDb::with_path(dir.path());
+                (dir, db)
+            },
+            |(_dir, db)| {
+                for id in &ids {
+                    db.put(&BlockId::with_hashing(id.clone(), hashing), &meta).unwrap();
+                }
+            },
+            BatchSize::PerIteration,
+        ));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_million_block_insert);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use ton_node_storage::dynamic_boc_db::DynamicBocDb;
+use ton_node_storage::types::CellId;
+
+mod common;
+
+use common::{build_tree, walk_tree};
+
+const READS: usize = 10_000;
+
+fn bench_read_subtree(c: &mut Criterion) {
+    let tree = build_tree(0, 5);
+    let root_id = CellId::new(tree.repr_hash());
+
+    let mut group = c.benchmark_group("read same subtree 10k times");
+    group.sample_size(10);
+    for &(name, cache_capacity) in &[("without cache", 0), ("with cache", 1024)] {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(DynamicBocDb::with_cache_capacity(dir.path(), cache_capacity));
+        db.put_tree(tree.clone()).unwrap();
+
+        group.bench_function(name, |b| b.iter(|| {
+            for _ in 0..READS {
+                walk_tree(&db.load_dynamic_boc(&root_id).unwrap());
+            }
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_read_subtree);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+use ton_types::{BuilderData, Cell};
+
+/// Builds binary tree of distinct cells with given depth
+pub fn build_tree(data: u32, depth: u32) -> Cell {
+    let mut builder = BuilderData::new();
+    builder.append_u32(data).unwrap();
+    if depth > 0 {
+        builder.checked_append_reference(build_tree(data * 2 + 1, depth - 1)).unwrap();
+        builder.checked_append_reference(build_tree(data * 2 + 2, depth - 1)).unwrap();
+    }
+    builder.into_cell().unwrap()
+}
+
+/// Visits all cells of the tree, loading them, and returns their count
+pub fn walk_tree(cell: &Cell) -> usize {
+    let mut count = 1;
+    for i in 0..cell.references_count() {
+        count += walk_tree(&cell.reference(i).unwrap());
+    }
+    count
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use ton_types::Cell;
+
+use ton_node_storage::dynamic_boc_db::DynamicBocDb;
+use ton_node_storage::types::CellId;
+
+mod common;
+
+use common::{build_tree, walk_tree};
+
+fn bench_load_tree(c: &mut Criterion) {
+    let tree = build_tree(0, 12);
+    let root_id = CellId::new(tree.repr_hash());
+    let dir = tempfile::tempdir().unwrap();
+    let db = Arc::new(DynamicBocDb::with_path(dir.path()));
+    db.put_tree(tree).unwrap();
+
+    // Loaded cells are dropped after each iteration, so every walk reads the database
+    let mut group = c.benchmark_group("walk tree of 8191 cells");
+    group.sample_size(10);
+    group.bench_function("lazy", |b| b.iter(|| {
+        walk_tree(&db.load_dynamic_boc(&root_id).unwrap())
+    }));
+    group.bench_function("eager", |b| b.iter(|| {
+        walk_tree(&Cell::with_cell_impl_arc(db.load_cell_tree(&root_id, None).unwrap()))
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_load_tree);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ton_types::UInt256;
+
+use ton_node_storage::db::rocksdb::RocksDb;
+use ton_node_storage::db::traits::{KvcReadable, KvcWriteable};
+use ton_node_storage::types::CellId;
+
+const CELLS: u32 = 100_000;
+const BATCH: u32 = 1000;
+
+fn cell_id(i: u32) -> CellId {
+    let mut hash = [0; 32];
+    hash[..4].copy_from_slice(&i.to_be_bytes());
+    hash[28..].copy_from_slice(&i.wrapping_mul(2_654_435_761).to_le_bytes());
+    CellId::new(UInt256::from(hash))
+}
+
+fn bench_get_multi(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let db = RocksDb::with_path(dir.path());
+    for i in 0..CELLS {
+        db.put(&cell_id(i), &[i as u8; 128]).unwrap();
+    }
+    db.flush().unwrap();
+    let keys: Vec<_> = (0..BATCH).map(|i| cell_id(i * (CELLS / BATCH) + i % 7)).collect();
+
+    let mut group = c.benchmark_group("fetch 1000 cells");
+    group.bench_function("one get per cell", |b| b.iter(|| {
+        keys.iter()
+            .map(|key| db.try_get(key).unwrap().is_some())
+            .filter(|&found| found)
+            .count()
+    }));
+    group.bench_function("get_multi", |b| b.iter(|| {
+        db.get_multi(&keys).unwrap().iter()
+            .filter(|value| value.is_some())
+            .count()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_get_multi);
+criterion_main!(benches);
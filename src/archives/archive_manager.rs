@@ -6,14 +6,19 @@ use std::sync::Arc;
 
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
 use ton_api::ton::PublicKey;
 use ton_block::BlockIdExt;
 use ton_types::{error, Result, UInt256};
 
+use crate::archives::archive_problem::ArchiveProblem;
 use crate::archives::archive_slice::ArchiveSlice;
 use crate::archives::file_maps::{FileDescription, FileMaps};
 use crate::archives::get_mc_seq_no;
-use crate::archives::package_entry_id::{GetFileNameShort, PackageEntryId};
+use crate::archives::package::PackageEntryStream;
+use crate::archives::package_entry::PackageCompression;
+use crate::archives::package_entry_id::{GetFileNameShort, PackageEntryId, PackageEntryKind};
+use crate::archives::package_entry_meta::PackageEntryMeta;
 use crate::archives::package_id::PackageId;
 use crate::types::BlockHandle;
 
@@ -22,17 +27,64 @@ pub const ARCHIVE_SIZE: usize = 20_000;
 pub const KEY_ARCHIVE_SIZE: usize = 200_000;
 pub const SLICE_SIZE: u32 = 100;
 
+/// Location of entry in archive package
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryLocation {
+    package_id: PackageId,
+    offset: u64,
+    size: u64,
+}
+
+impl EntryLocation {
+    pub const fn package_id(&self) -> &PackageId {
+        &self.package_id
+    }
+
+    /// Offset of the entry relative to package data, i.e. past the package header
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Size of the entry: its header, filename and data
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+}
+
 pub struct ArchiveManager {
     db_root_path: Arc<PathBuf>,
     unapplied_dir: Arc<PathBuf>,
     file_maps: FileMaps,
+    sync_dirs: bool,
+    compression: PackageCompression,
+    create_lock: Mutex<()>,
 }
 
 impl ArchiveManager {
     pub async fn with_data(
         db_root_path: Arc<PathBuf>,
     ) -> Result<Self> {
-        let file_maps = FileMaps::new(&db_root_path).await?;
+        Self::with_durability(db_root_path, false).await
+    }
+
+    /// Creates new instance. If `sync_dirs` is set, containing directory is fsynced after
+    /// creation of each package file, so the file survives a crash. It costs an extra
+    /// synchronous metadata write per new package
+    pub async fn with_durability(
+        db_root_path: Arc<PathBuf>,
+        sync_dirs: bool,
+    ) -> Result<Self> {
+        Self::with_compression(db_root_path, sync_dirs, PackageCompression::Stored).await
+    }
+
+    /// Creates new instance, which appends entries to packages with given compression.
+    /// Packages holding compressed entries can't be sent to peers by get_archive_slice()
+    pub async fn with_compression(
+        db_root_path: Arc<PathBuf>,
+        sync_dirs: bool,
+        compression: PackageCompression,
+    ) -> Result<Self> {
+        let file_maps = FileMaps::new(&db_root_path, sync_dirs, compression).await?;
         let unapplied_dir = Arc::new(db_root_path.join("archive").join("unapplied"));
         tokio::fs::create_dir_all(&*unapplied_dir).await?;
 
@@ -40,6 +92,9 @@ impl ArchiveManager {
             db_root_path,
             unapplied_dir,
             file_maps,
+            sync_dirs,
+            compression,
+            create_lock: Mutex::new(()),
         })
     }
 
@@ -51,6 +106,10 @@ impl ArchiveManager {
         &self.unapplied_dir
     }
 
+    pub const fn compression(&self) -> PackageCompression {
+        self.compression
+    }
+
     pub async fn add_file<B, U256, PK>(&self, entry_id: &PackageEntryId<B, U256, PK>, data: Vec<u8>) -> Result<()>
     where
         B: Borrow<BlockIdExt> + Hash,
@@ -81,21 +140,86 @@ impl ArchiveManager {
         U256: Borrow<UInt256> + Hash,
         PK: Borrow<PublicKey> + Hash
     {
-        handle.temp_lock().read().await;
+        let _lock = handle.temp_lock().read().await;
 
-        if handle.moved_to_archive() {
-            let package_id = self.get_package_id(get_mc_seq_no(handle)).await?;
-            if let Some(ref fd) = self.get_file_desc(package_id, false).await? {
-                return Ok(fd.archive_slice()
-                    .get_file(Some(handle), entry_id).await?
-                    .take_data());
+        self.get_file_unlocked(handle, entry_id).await
+    }
+
+    /// Gets block data and its proof (or proof link) at once, so both are read from the same
+    /// location even if the block is being moved to archive concurrently
+    pub async fn get_block_with_proof(&self, handle: &BlockHandle) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        let _lock = handle.temp_lock().read().await;
+
+        let data = if handle.data_inited() {
+            Some(self.get_file_unlocked(handle, &PackageEntryId::<_, &UInt256, &PublicKey>::Block(handle.id())).await?)
+        } else {
+            None
+        };
+        let proof = if handle.proof_inited() {
+            Some(self.get_file_unlocked(handle, &PackageEntryId::<_, &UInt256, &PublicKey>::Proof(handle.id())).await?)
+        } else if handle.proof_link_inited() {
+            Some(self.get_file_unlocked(handle, &PackageEntryId::<_, &UInt256, &PublicKey>::ProofLink(handle.id())).await?)
+        } else {
+            None
+        };
+
+        Ok((data, proof))
+    }
+
+    /// Gets offset of archived entry and metadata of its package from snapshots of archive index
+    /// databases (see ArchiveSlice::get_entry_meta). Returns None if the entry is not archived
+    pub async fn get_entry_meta<B, U256, PK>(
+        &self,
+        handle: &BlockHandle,
+        entry_id: &PackageEntryId<B, U256, PK>
+    ) -> Result<Option<(u64, PackageEntryMeta)>>
+    where
+        B: Borrow<BlockIdExt> + Hash,
+        U256: Borrow<UInt256> + Hash,
+        PK: Borrow<PublicKey> + Hash
+    {
+        if !handle.moved_to_archive() {
+            return Ok(None);
+        }
+
+        let package_id = self.get_package_id(get_mc_seq_no(handle)).await?;
+        if let Some(ref fd) = self.get_file_desc(package_id, false).await? {
+            return fd.archive_slice().get_entry_meta(Some(handle), entry_id);
+        }
+
+        Ok(None)
+    }
+
+    /// Finds package and location there of archived entry of given kind for given block.
+    /// Masterchain seq_no of shardchain block can't be known from its id, so offsets indexes of
+    /// archive slices are looked up from the newest to the oldest; package files are read only
+    /// in the slice indexing the entry. Returns None if the entry is not archived
+    pub async fn locate_entry(&self, block_id: &BlockIdExt, entry_kind: PackageEntryKind) -> Result<Option<EntryLocation>> {
+        let entry_id = entry_kind.entry_id(block_id);
+        let file_map = self.file_maps.files();
+        let candidates = if block_id.shard().is_masterchain() {
+            file_map.get_closest(block_id.seq_no()).await.into_iter().collect()
+        } else {
+            let mut all = file_map.all().await;
+            all.reverse();
+            all
+        };
+
+        for fd in candidates {
+            if fd.deleted() {
+                continue;
+            }
+            if let Some((package_id, offset, size)) = fd.archive_slice().locate_entry(&entry_id).await? {
+                return Ok(Some(EntryLocation { package_id, offset, size }));
             }
         }
 
-        self.read_temp_file(entry_id).await
-            .map(|(_filename, data)| data)
+        Ok(None)
     }
 
+    /// Appends block entries to archive, then runs `on_success` (it is expected to mark the block
+    /// as moved to archive) and removes temporary files under the handle's write lock. So
+    /// `on_success` must not read files of the block
     pub async fn move_to_archive(
         &self,
         handle: &BlockHandle,
@@ -133,19 +257,62 @@ impl ArchiveManager {
             None
         };
 
+        // Readers holding the lock see either temporary files or the archived entries
+        let _lock = handle.temp_lock().write().await;
         on_success()?;
+        if let Some(filename) = proof_filename {
+            tokio::fs::remove_file(filename).await?;
+        }
+        if let Some(filename) = block_filename {
+            tokio::fs::remove_file(filename).await?;
+        }
 
-        {
-            handle.temp_lock().write().await;
-            if let Some(filename) = proof_filename {
-                tokio::fs::remove_file(filename).await?;
+        Ok(())
+    }
+
+    /// Removes packages, which contain only blocks with masterchain seq_no below given one.
+    /// Packages are marked as deleted before their files are removed, so removal interrupted
+    /// by a crash is finished on next start. A package being read concurrently is removed
+    /// on next start as well. Returns count of packages removed now, the postponed ones excluded
+    pub async fn gc_below(&self, mc_seq_no: u32) -> Result<u32> {
+        let file_map = self.file_maps.files();
+        let taken = file_map.take_below(mc_seq_no).await?;
+        let mut count = 0;
+        for fd in taken {
+            let id = fd.id().clone();
+            if Arc::strong_count(&fd) > 1 || Arc::strong_count(fd.archive_slice()) > 1 {
+                log::warn!(target: "storage", "Package {:?} is in use, it will be removed on next start", id);
+                continue;
             }
-            if let Some(filename) = block_filename {
-                tokio::fs::remove_file(filename).await?;
+            drop(fd);
+
+            log::debug!(target: "storage", "Removing package {:?} below mc_seq_no {}", id, mc_seq_no);
+            ArchiveSlice::remove_files(&self.db_root_path, id.id(), id.package_type()).await?;
+            file_map.forget(id.id())?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Gets ids of packages, which contain masterchain blocks with seq_no in given inclusive range.
+    /// Partially overlapping packages are included; empty range gives no packages
+    pub async fn packages_for_range(&self, from_mc_seq_no: u32, to_mc_seq_no: u32) -> Result<Vec<PackageId>> {
+        Ok(self.file_maps.files().packages_for_range(from_mc_seq_no, to_mc_seq_no).await)
+    }
+
+    /// Cross-checks all packages with their index databases and returns found inconsistencies.
+    /// Read-only, so it is safe to run on a live database
+    pub async fn verify(&self) -> Result<Vec<ArchiveProblem>> {
+        let mut problems = Vec::new();
+        for fd in self.file_maps.files().all().await {
+            if fd.deleted() {
+                continue;
             }
+            problems.extend(fd.archive_slice().verify().await?);
         }
 
-        Ok(())
+        Ok(problems)
     }
 
     pub async fn get_archive_id(&self, mc_seq_no: u32) -> Option<u64> {
@@ -163,6 +330,37 @@ impl ArchiveManager {
         fd.archive_slice().get_slice(archive_id, offset, limit).await
     }
 
+    /// Opens stream of entries of the package with given archive id, reading one entry at a time
+    pub async fn get_entry_stream(&self, archive_id: u64) -> Result<PackageEntryStream> {
+        let fd = self.get_file_desc(PackageId::for_block(archive_id as u32), false).await?
+            .ok_or_else(|| error!("Archive not found"))?;
+
+        fd.archive_slice().entry_stream(archive_id).await
+    }
+
+    async fn get_file_unlocked<B, U256, PK>(
+        &self,
+        handle: &BlockHandle,
+        entry_id: &PackageEntryId<B, U256, PK>
+    ) -> Result<Vec<u8>>
+    where
+        B: Borrow<BlockIdExt> + Hash,
+        U256: Borrow<UInt256> + Hash,
+        PK: Borrow<PublicKey> + Hash
+    {
+        if handle.moved_to_archive() {
+            let package_id = self.get_package_id(get_mc_seq_no(handle)).await?;
+            if let Some(ref fd) = self.get_file_desc(package_id, false).await? {
+                return Ok(fd.archive_slice()
+                    .get_file(Some(handle), entry_id).await?
+                    .take_data());
+            }
+        }
+
+        self.read_temp_file(entry_id).await
+            .map(|(_filename, data)| data)
+    }
+
     async fn move_file_to_archive<B, U256, PK>(&self, handle: &BlockHandle, entry_id: &PackageEntryId<B, U256, PK>) -> Result<PathBuf>
     where
         B: Borrow<BlockIdExt> + Hash,
@@ -171,12 +369,30 @@ impl ArchiveManager {
     {
         log::debug!(target: "storage", "Moving entry to archive: {}", entry_id.filename_short());
         let (filename, data) = {
-            handle.temp_lock().read().await;
+            let _lock = handle.temp_lock().read().await;
             self.read_temp_file(entry_id).await?
         };
 
         // TODO: Copy proofs and prooflinks into a corresponding keyblocks archive?
 
+        self.append_file(handle, entry_id, data).await?;
+
+        Ok(filename)
+    }
+
+    /// Appends entry to the package chosen for the block and updates package index.
+    /// Returns description of the package
+    pub(crate) async fn append_file<B, U256, PK>(
+        &self,
+        handle: &BlockHandle,
+        entry_id: &PackageEntryId<B, U256, PK>,
+        data: Vec<u8>
+    ) -> Result<Arc<FileDescription>>
+    where
+        B: Borrow<BlockIdExt> + Hash,
+        U256: Borrow<UInt256> + Hash,
+        PK: Borrow<PublicKey> + Hash
+    {
         let mc_seq_no = get_mc_seq_no(handle);
 
         let is_key = handle.is_key_block()?;
@@ -196,7 +412,7 @@ impl ArchiveManager {
 
         fd.archive_slice().add_file(Some(handle), entry_id, data).await?;
 
-        Ok(filename)
+        Ok(fd)
     }
 
     async fn read_temp_file<B, U256, PK>(&self, entry_id: &PackageEntryId<B, U256, PK>) -> Result<(PathBuf, Vec<u8>)>
@@ -238,9 +454,13 @@ impl ArchiveManager {
     }
 
     async fn add_file_desc(&self, id: PackageId) -> Result<Arc<FileDescription>> {
-        // TODO: Rewrite logics in order to handle multithreaded adding of packages
+        // Packages are created one at a time; the package could be created by a concurrent
+        // writer while waiting for the lock, so it is checked again
+        let _create_guard = self.create_lock.lock().await;
         let file_map = self.file_maps.get(id.package_type());
-        assert!(file_map.get(id.id()).await.is_none());
+        if let Some(fd) = file_map.get(id.id()).await {
+            return Ok(fd);
+        }
 
         let dir = self.db_root_path.join(id.path());
         tokio::fs::create_dir_all(&dir).await?;
@@ -251,6 +471,8 @@ impl ArchiveManager {
                 id.id(),
                 id.package_type(),
                 false,
+                self.sync_dirs,
+                self.compression,
             ).await?
         );
 
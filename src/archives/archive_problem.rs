@@ -0,0 +1,65 @@
+use std::fmt::{Display, Formatter};
+
+use crate::archives::package_id::PackageId;
+
+/// Kind of inconsistency between archive package files and their index databases
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveProblemKind {
+    /// Package status database has no value for the key
+    MissingStatus(&'static str),
+    /// Stored count of slices differs from count of opened packages
+    SliceCountMismatch { stored: u32, actual: usize },
+    /// Stored slice size is zero
+    ZeroSliceSize,
+    /// Entry metadata database has no record for the slice
+    MissingEntryMeta(u32),
+    /// Size in entry metadata differs from actual size of package data
+    SizeMismatch { idx: u32, stored: u64, actual: u64 },
+    /// Offset does not point to a valid entry header in any package of the slice
+    BadOffset { offset: u64, reason: String },
+    /// Package file cannot be read
+    Unreadable(String),
+}
+
+impl Display for ArchiveProblemKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveProblemKind::MissingStatus(key) => write!(f, "status {} is missing", key),
+            ArchiveProblemKind::SliceCountMismatch { stored, actual } =>
+                write!(f, "{} slices stored, but {} packages opened", stored, actual),
+            ArchiveProblemKind::ZeroSliceSize => write!(f, "slice size is zero"),
+            ArchiveProblemKind::MissingEntryMeta(idx) => write!(f, "entry metadata of slice #{} is missing", idx),
+            ArchiveProblemKind::SizeMismatch { idx, stored, actual } =>
+                write!(f, "slice #{} size is {} bytes, but {} bytes stored in metadata", idx, actual, stored),
+            ArchiveProblemKind::BadOffset { offset, reason } => write!(f, "bad entry offset {}: {}", offset, reason),
+            ArchiveProblemKind::Unreadable(reason) => write!(f, "package is unreadable: {}", reason),
+        }
+    }
+}
+
+/// Inconsistency found in archive package
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveProblem {
+    package_id: PackageId,
+    kind: ArchiveProblemKind,
+}
+
+impl ArchiveProblem {
+    pub const fn with_data(package_id: PackageId, kind: ArchiveProblemKind) -> Self {
+        Self { package_id, kind }
+    }
+
+    pub const fn package_id(&self) -> &PackageId {
+        &self.package_id
+    }
+
+    pub const fn kind(&self) -> &ArchiveProblemKind {
+        &self.kind
+    }
+}
+
+impl Display for ArchiveProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "package {:?}: {}", self.package_id, self.kind)
+    }
+}
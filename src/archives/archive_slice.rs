@@ -1,7 +1,7 @@
 use std::borrow::Borrow;
 use std::hash::Hash;
-use std::io::SeekFrom;
-use std::path::PathBuf;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use tokio::fs::File;
@@ -12,15 +12,19 @@ use ton_block::BlockIdExt;
 use ton_types::{error, fail, Result, UInt256};
 
 use crate::archives::archive_manager::SLICE_SIZE;
+use crate::archives::archive_problem::{ArchiveProblem, ArchiveProblemKind};
 use crate::archives::get_mc_seq_no_opt;
-use crate::archives::package::Package;
-use crate::archives::package_entry::PackageEntry;
+use crate::archives::package::{
+    probe_entry_header_at, read_entry_header_at, read_package_from_file, Package, PackageEntryStream,
+    PKG_HEADER_SIZE
+};
+use crate::archives::package_entry::{PackageCompression, PackageEntry};
 use crate::archives::package_entry_id::{GetFileName, PackageEntryId};
 use crate::archives::package_entry_meta::PackageEntryMeta;
 use crate::archives::package_entry_meta_db::PackageEntryMetaDb;
 use crate::archives::package_id::{PackageId, PackageType};
 use crate::archives::package_info::PackageInfo;
-use crate::archives::package_offsets_db::PackageOffsetsDb;
+use crate::archives::package_offsets_db::{PackageOffset, PackageOffsetsDb};
 use crate::archives::package_status_db::PackageStatusDb;
 use crate::archives::package_status_key::PackageStatusKey;
 use crate::traits::Serializable;
@@ -39,6 +43,9 @@ pub struct ArchiveSlice {
     slice_size: u32,
     package_type: PackageType,
     finalized: bool,
+    sync_dirs: bool,
+    compression: PackageCompression,
+    compressed: bool,
     index_db: Arc<PackageEntryMetaDb>,
     offsets_db: Arc<PackageOffsetsDb>,
     package_status_db: Arc<PackageStatusDb>,
@@ -50,6 +57,8 @@ impl ArchiveSlice {
         archive_id: u32,
         package_type: PackageType,
         finalized: bool,
+        sync_dirs: bool,
+        compression: PackageCompression,
     ) -> Result<Self> {
         let package_id = PackageId::with_values(archive_id, package_type);
         let index_path = package_id.full_path(db_root_path.as_ref(), "index");
@@ -58,6 +67,13 @@ impl ArchiveSlice {
         let offsets_db = Arc::new(PackageOffsetsDb::with_path(index_path.join("offsets_db")));
         let package_status_db = Arc::new(PackageStatusDb::with_path(index_path.join("status_db")));
 
+        // Marker is set before any compressed entry is appended, so it can't be missed after a crash
+        if compression != PackageCompression::Stored && !finalized {
+            package_status_db.put_value(&PackageStatusKey::Compressed, true)?;
+        }
+        let compressed = package_status_db.try_get_value::<bool>(&PackageStatusKey::Compressed)?
+            .unwrap_or(false);
+
         let mut archive_slice = Self {
             archive_id,
             packages: RwLock::new(Vec::new()),
@@ -67,6 +83,9 @@ impl ArchiveSlice {
             slice_size: SLICE_SIZE,
             package_type,
             finalized,
+            sync_dirs,
+            compression,
+            compressed,
             index_db: Arc::clone(&index_db),
             offsets_db,
             package_status_db: Arc::clone(&package_status_db),
@@ -104,7 +123,7 @@ impl ArchiveSlice {
                     transaction.put(&PackageStatusKey::TotalSlices, 1u32.to_vec()?.as_slice());
                     transaction.put(&PackageStatusKey::SliceSize, archive_slice.slice_size.to_vec()?.as_slice());
 
-                    let meta = PackageEntryMeta::with_data(0, DEFAULT_PKG_VERSION);
+                    let meta = PackageEntryMeta::with_data(0, package_type, DEFAULT_PKG_VERSION);
                     index_db.put_value(&0.into(), &meta)?;
                     transaction.commit()?;
                 }
@@ -129,6 +148,49 @@ impl ArchiveSlice {
         Ok(archive_slice)
     }
 
+    pub const fn finalized(&self) -> bool {
+        self.finalized
+    }
+
+    /// Compression of entries appended to the slice
+    pub const fn compression(&self) -> PackageCompression {
+        self.compression
+    }
+
+    /// Whether packages of the slice may hold compressed entries
+    pub const fn compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Removes package files and index databases of the slice, which must not be opened.
+    /// Missing files are skipped, so interrupted removal can be repeated
+    pub async fn remove_files(db_root_path: &Path, archive_id: u32, package_type: PackageType) -> Result<()> {
+        let index_path = PackageId::with_values(archive_id, package_type).full_path(db_root_path, "index");
+
+        let mut seq_nos = vec![archive_id];
+        if tokio::fs::metadata(&index_path).await.is_ok() {
+            let package_status_db = PackageStatusDb::with_path(index_path.join("status_db"));
+            if package_status_db.try_get_value::<bool>(&PackageStatusKey::SlicedMode)? == Some(true) {
+                let total_slices = package_status_db.get_value::<u32>(&PackageStatusKey::TotalSlices)?;
+                let slice_size = package_status_db.get_value::<u32>(&PackageStatusKey::SliceSize)?;
+                seq_nos = (0..total_slices).map(|i| archive_id + slice_size * i).collect();
+            }
+        }
+
+        for seq_no in seq_nos {
+            let path = PackageId::with_values(seq_no, package_type).full_path(db_root_path, "pack");
+            log::debug!(target: "storage", "Removing package file {:?}", path);
+            match tokio::fs::remove_file(&path).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+        match tokio::fs::remove_dir_all(&index_path).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
     #[allow(dead_code)]
     pub async fn destroy(mut self) -> Result<()> {
         for pi in self.packages.write().await.drain(..) {
@@ -193,14 +255,24 @@ impl ArchiveSlice {
 
         package_info.package().append_entry(&entry,
             |offset, size| {
-                let meta = PackageEntryMeta::with_data(size, package_info.version());
+                let meta = PackageEntryMeta::with_data(size, self.package_type, package_info.version());
                 log::debug!(target: "storage", "Writing package entry metadata for slice #{}: {:?}, offset: {}", idx, meta, offset);
                 self.index_db.put_value(&idx.into(), meta)?;
-                self.offsets_db.put_value(&offset_key, offset)
+                self.offsets_db.put_value(&offset_key, PackageOffset::with_package(offset, package_info.idx()))
             }
         ).await
     }
 
+    /// Flushes all package files of the slice to disk. Index databases rely on their write-ahead log
+    pub async fn sync(&self) -> Result<()> {
+        let packages = self.packages.read().await.clone();
+        for package_info in packages {
+            package_info.package().sync().await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn get_file<B, U256, PK>(
         &self, 
         block_handle: Option<&BlockHandle>, 
@@ -213,7 +285,8 @@ impl ArchiveSlice {
     {
         let offset_key = entry_id.into();
         let offset = self.offsets_db.try_get_value(&offset_key)?
-            .ok_or_else(|| error!("File is not in archive: {}", entry_id))?;
+            .ok_or_else(|| error!("File is not in archive: {}", entry_id))?
+            .offset();
 
         let package_info = self.choose_package(get_mc_seq_no_opt(block_handle), false).await?;
 
@@ -226,10 +299,240 @@ impl ArchiveSlice {
         package_info.package().read_entry(offset).await
     }
 
+    /// Gets offset of the entry and metadata of its package, reading index databases from their
+    /// snapshots. Snapshots of the two databases are taken one after another, not atomically:
+    /// offsets are taken first, since entry meta is written before the entry offset, so the meta
+    /// read later covers the entry. That is checked after reading the meta
+    pub fn get_entry_meta<B, U256, PK>(
+        &self,
+        block_handle: Option<&BlockHandle>,
+        entry_id: &PackageEntryId<B, U256, PK>
+    ) -> Result<Option<(u64, PackageEntryMeta)>>
+    where
+        B: Borrow<BlockIdExt> + Hash,
+        U256: Borrow<UInt256> + Hash,
+        PK: Borrow<PublicKey> + Hash
+    {
+        let offsets_snapshot = self.offsets_db.snapshot()?;
+        let index_snapshot = self.index_db.snapshot()?;
+
+        let offset = match PackageOffsetsDb::try_get_value_from(&*offsets_snapshot, &entry_id.into())? {
+            Some(offset) => offset.offset(),
+            None => return Ok(None),
+        };
+
+        let idx = if self.sliced_mode {
+            let mc_seq_no = get_mc_seq_no_opt(block_handle);
+            if mc_seq_no < self.archive_id {
+                fail!("mc_seq_no is too small");
+            }
+            (mc_seq_no - self.archive_id) / self.slice_size
+        } else {
+            u32::max_value()
+        };
+        let meta = PackageEntryMetaDb::get_value_from(&*index_snapshot, &idx.into())?;
+        if offset >= meta.entry_size() {
+            fail!(
+                "Entry {} with offset {} is past the end of slice #{} ({} bytes), index is inconsistent",
+                entry_id, offset, idx, meta.entry_size()
+            );
+        }
+
+        Ok(Some((offset, meta)))
+    }
+
+    /// Finds package holding given entry. Returns id of the package, offset of the entry relative
+    /// to package data and size of the entry, its header and filename included. The package is
+    /// taken from the offsets index. Offsets written by older versions lack it, and offsets of
+    /// sliced archive are shared by its packages, so for them the package is found by entry
+    /// filename at the offset
+    pub async fn locate_entry<B, U256, PK>(
+        &self,
+        entry_id: &PackageEntryId<B, U256, PK>
+    ) -> Result<Option<(PackageId, u64, u64)>>
+    where
+        B: Borrow<BlockIdExt> + Hash,
+        U256: Borrow<UInt256> + Hash,
+        PK: Borrow<PublicKey> + Hash
+    {
+        let offsets_snapshot = self.offsets_db.snapshot()?;
+        let package_offset = match PackageOffsetsDb::try_get_value_from(&*offsets_snapshot, &entry_id.into())? {
+            Some(package_offset) => package_offset,
+            None => return Ok(None),
+        };
+        let offset = package_offset.offset();
+
+        let filename = entry_id.filename();
+        let packages = self.packages.read().await.clone();
+        if let Some(idx) = package_offset.idx() {
+            let package_info = packages.iter().find(|package_info| package_info.idx() == idx)
+                .ok_or_else(|| error!(
+                    "Entry {} is indexed in package #{} missing in archive slice {}", entry_id, idx, self.archive_id
+                ))?;
+            let mut file = File::open(&**package_info.package().path()).await?;
+            let file_size = file.metadata().await?.len();
+            let entry_header = read_entry_header_at(&mut file, file_size, offset).await?;
+            // Other entry with the same offset key means the entry itself was never stored
+            if !Self::entry_filename_matches(&mut file, entry_header.filename_size(), &filename).await? {
+                return Ok(None);
+            }
+            return Ok(Some((package_info.package_id().clone(), offset, entry_header.calc_entry_size())));
+        }
+
+        for package_info in packages {
+            let mut file = File::open(&**package_info.package().path()).await?;
+            let file_size = file.metadata().await?.len();
+            let entry_header = match probe_entry_header_at(&mut file, file_size, offset).await? {
+                Some(entry_header) => entry_header,
+                None => continue,
+            };
+            if Self::entry_filename_matches(&mut file, entry_header.filename_size(), &filename).await? {
+                return Ok(Some((package_info.package_id().clone(), offset, entry_header.calc_entry_size())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads filename of the entry, which header has just been read from the file, and compares it
+    async fn entry_filename_matches(file: &mut File, filename_size: u16, filename: &str) -> Result<bool> {
+        let mut entry_filename = vec![0; filename_size as usize];
+        file.read_exact(&mut entry_filename).await?;
+
+        Ok(entry_filename == filename.as_bytes())
+    }
+
+    /// Reads entries of all packages of the slice one by one, passing them into given callback
+    /// until it returns false
+    pub async fn for_each_entry(
+        &self,
+        f: &mut dyn FnMut(&PackageEntryId<BlockIdExt, UInt256, PublicKey>, &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        let packages = self.packages.read().await.clone();
+        for package_info in packages {
+            let mut reader = read_package_from_file(&**package_info.package().path()).await?;
+            while let Some(entry) = reader.next().await? {
+                let entry_id = PackageEntryId::from_filename(entry.filename())?;
+                if !f(&entry_id, entry.data().as_slice())? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Cross-checks package files with index databases without modifying anything.
+    /// Entry metadata and offsets are read from snapshots
+    pub async fn verify(&self) -> Result<Vec<ArchiveProblem>> {
+        let package_id = PackageId::with_values(self.archive_id, self.package_type);
+        let mut problems = Vec::new();
+        let mut report = |kind| problems.push(ArchiveProblem::with_data(package_id.clone(), kind));
+
+        let index_snapshot = self.index_db.snapshot()?;
+        let offsets_snapshot = self.offsets_db.snapshot()?;
+        let packages = self.packages.read().await.clone();
+
+        if self.package_status_db.try_get_value::<bool>(&PackageStatusKey::SlicedMode)?.is_none() {
+            report(ArchiveProblemKind::MissingStatus("SlicedMode"));
+        }
+        if self.sliced_mode {
+            match self.package_status_db.try_get_value::<u32>(&PackageStatusKey::TotalSlices)? {
+                None => report(ArchiveProblemKind::MissingStatus("TotalSlices")),
+                Some(stored) if stored as usize != packages.len() => {
+                    report(ArchiveProblemKind::SliceCountMismatch { stored, actual: packages.len() })
+                }
+                Some(_) => (),
+            }
+            match self.package_status_db.try_get_value::<u32>(&PackageStatusKey::SliceSize)? {
+                None => report(ArchiveProblemKind::MissingStatus("SliceSize")),
+                Some(0) => report(ArchiveProblemKind::ZeroSliceSize),
+                Some(_) => (),
+            }
+        } else if self.package_status_db.try_get_value::<u64>(&PackageStatusKey::NonSlicedSize)?.is_none() {
+            report(ArchiveProblemKind::MissingStatus("NonSlicedSize"));
+        }
+
+        let mut files = Vec::new();
+        for package_info in packages.iter() {
+            let path = package_info.package().path();
+            let (file, file_size) = match File::open(&**path).await {
+                Ok(file) => match file.metadata().await {
+                    Ok(metadata) => (file, metadata.len()),
+                    Err(err) => {
+                        report(ArchiveProblemKind::Unreadable(err.to_string()));
+                        continue;
+                    }
+                },
+                Err(err) => {
+                    report(ArchiveProblemKind::Unreadable(err.to_string()));
+                    continue;
+                }
+            };
+
+            if self.sliced_mode {
+                let idx = package_info.idx();
+                match PackageEntryMetaDb::try_get_value_from(&*index_snapshot, &idx.into())? {
+                    None => report(ArchiveProblemKind::MissingEntryMeta(idx)),
+                    Some(meta) => {
+                        let actual = file_size.saturating_sub(PKG_HEADER_SIZE as u64);
+                        if meta.entry_size() != actual {
+                            report(ArchiveProblemKind::SizeMismatch { idx, stored: meta.entry_size(), actual });
+                        }
+                    }
+                }
+            }
+            files.push((file, file_size));
+        }
+
+        // Offsets of sliced archive are shared by its packages, so an offset is valid
+        // if it points to an entry in any of them
+        for offset in PackageOffsetsDb::offsets_in(&*offsets_snapshot)? {
+            let mut reason = None;
+            for (file, file_size) in files.iter_mut() {
+                match read_entry_header_at(file, *file_size, offset).await {
+                    Ok(_) => {
+                        reason = None;
+                        break;
+                    }
+                    Err(err) => reason = Some(err.to_string()),
+                }
+            }
+            if let Some(reason) = reason {
+                report(ArchiveProblemKind::BadOffset { offset, reason });
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Opens stream of entries of the package with given archive id (as in get_slice()).
+    /// Offsets database of sliced archive is shared by all its packages, so entries of
+    /// a package in sliced mode are found by their headers
+    pub async fn entry_stream(&self, archive_id: u64) -> Result<PackageEntryStream> {
+        if archive_id as u32 != self.archive_id {
+            fail!("Bad archive ID (archive_id = {}, expected {})!", archive_id as u32, self.archive_id);
+        }
+
+        let package_id = (archive_id >> 32) as u32;
+        let package_info = self.choose_package(package_id, false).await?;
+        let file = File::open(&**package_info.package().path()).await?;
+        if self.sliced_mode {
+            PackageEntryStream::with_all_entries(file).await
+        } else {
+            PackageEntryStream::with_offsets(file, self.offsets_db.offsets()?).await
+        }
+    }
+
+    /// Reads raw bytes of the package file to be sent to peers. Peers understand stored entries
+    /// only, so the slice, which may hold compressed entries, is refused
     pub async fn get_slice(&self, archive_id: u64, offset: u64, limit: u32) -> Result<Vec<u8>> {
         if archive_id as u32 != self.archive_id {
             fail!("Bad archive ID (archive_id = {}, expected {})!", archive_id as u32, self.archive_id);
         }
+        if self.compressed {
+            fail!("Archive {} holds compressed entries, it can't be sent as is", self.archive_id);
+        }
 
         let package_id = (archive_id >> 32) as u32;
         let package_info = self.choose_package(package_id, false).await?;
@@ -256,8 +559,14 @@ impl ArchiveSlice {
         let package_id = PackageId::with_values(seq_no, self.package_type);
         let path = Arc::new(package_id.full_path(self.db_root_path.as_ref(), "pack"));
 
-        let package = Package::open(Arc::clone(&path), false, true).await
+        let created = tokio::fs::metadata(&*path).await.is_err();
+        let package = Package::open_with_compression(Arc::clone(&path), false, true, self.compression).await
             .map_err(|err| error!("Failed to open or create archive \"{}\": {}", path.to_string_lossy(), err))?;
+        if created && self.sync_dirs {
+            if let Some(dir) = path.parent() {
+                File::open(dir).await?.sync_all().await?;
+            }
+        }
 
         if !self.finalized && version >= DEFAULT_PKG_VERSION {
             package.truncate(size).await?;
@@ -283,6 +592,11 @@ impl ArchiveSlice {
         }
 
         let idx = (mc_seq_no - self.archive_id) / self.slice_size;
+        // Existing packages are shared under read lock, so appends to them run concurrently;
+        // appends to the same package are serialized by its own write mutex
+        if let Some(package_info) = self.packages.read().await.get(idx as usize) {
+            return Ok(Arc::clone(package_info));
+        }
         {
             let mut write_guard = self.packages.write().await;
             let package_count = write_guard.len();
@@ -312,7 +626,7 @@ impl ArchiveSlice {
 
                 let pi = self.new_package(idx, mc_seq_no, 0, DEFAULT_PKG_VERSION).await?;
 
-                let index_entry = PackageEntryMeta::with_data(0, DEFAULT_PKG_VERSION);
+                let index_entry = PackageEntryMeta::with_data(0, self.package_type, DEFAULT_PKG_VERSION);
                 self.index_db.put_value(&idx.into(), &index_entry)?;
                 self.package_status_db.put_value(&PackageStatusKey::TotalSlices, idx + 1)?;
                 write_guard.push(Arc::clone(&pi));
@@ -322,3 +636,167 @@ impl ArchiveSlice {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ton_block::ShardIdent;
+
+    use super::*;
+    use crate::block_handle_db::{BlockHandleDb, BlockHandleStorage};
+    use crate::status_db::StatusDb;
+
+    async fn open_slice(root: &Arc<PathBuf>, compression: PackageCompression) -> Result<ArchiveSlice> {
+        tokio::fs::create_dir_all(root.join(PackageId::for_block(0).path())).await?;
+        ArchiveSlice::with_data(Arc::clone(root), 0, PackageType::Blocks, false, false, compression).await
+    }
+
+    #[tokio::test]
+    async fn test_entries_round_trip_with_and_without_compression() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = Arc::new(dir.path().to_path_buf());
+        let stored_id = PackageEntryId::<_, UInt256, PublicKey>::Block(BlockIdExt::default());
+        let compressed_id = PackageEntryId::<_, UInt256, PublicKey>::Proof(BlockIdExt::default());
+        let data: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+
+        {
+            let slice = open_slice(&root, PackageCompression::Stored).await?;
+            slice.add_file(None, &stored_id, data.clone()).await?;
+            assert!(!slice.compressed());
+            assert!(!slice.get_slice(0, 0, 1 << 20).await?.is_empty());
+        }
+        {
+            let slice = open_slice(&root, PackageCompression::Zstd(3)).await?;
+            slice.add_file(None, &compressed_id, data.clone()).await?;
+            assert!(slice.compressed());
+
+            assert_eq!(slice.get_file(None, &stored_id).await?.data(), &data);
+            assert_eq!(slice.get_file(None, &compressed_id).await?.data(), &data);
+            assert!(slice.get_slice(0, 0, 1 << 20).await.is_err());
+        }
+
+        // Marker stays, when the slice is reopened without compression
+        let slice = open_slice(&root, PackageCompression::Stored).await?;
+        assert!(slice.compressed());
+        let mut entries = Vec::new();
+        slice.for_each_entry(&mut |entry_id, entry_data| {
+            entries.push((entry_id.filename(), entry_data.to_vec()));
+            Ok(true)
+        }).await?;
+        assert_eq!(entries, vec![(stored_id.filename(), data.clone()), (compressed_id.filename(), data)]);
+
+        Ok(())
+    }
+
+    fn entry_data(seq_no: u32) -> Vec<u8> {
+        format!("block {};", seq_no).repeat(1 + seq_no as usize % 50).into_bytes()
+    }
+
+    fn add_block(
+        slice: &ArchiveSlice,
+        runtime: &mut tokio::runtime::Runtime,
+        handles: &[Arc<BlockHandle>],
+        seq_no: u32
+    ) -> Result<()> {
+        let handle = &handles[seq_no as usize];
+        let entry_id = PackageEntryId::<_, UInt256, PublicKey>::Block(handle.id().clone());
+        runtime.block_on(slice.add_file(Some(handle), &entry_id, entry_data(seq_no)))
+    }
+
+    #[test]
+    fn test_concurrent_writers_across_packages() -> Result<()> {
+        const PACKAGES: u32 = 8;
+        const NEW_PACKAGES: u32 = 4;
+        const WRITERS: u32 = 4;
+
+        let dir = tempfile::tempdir()?;
+        let root = Arc::new(dir.path().to_path_buf());
+        let mut runtime = tokio::runtime::Builder::new().basic_scheduler().enable_all().build()?;
+        let slice = Arc::new(runtime.block_on(open_slice(&root, PackageCompression::Stored))?);
+
+        let storage = BlockHandleStorage::new(Arc::new(BlockHandleDb::in_memory()), &StatusDb::in_memory())?;
+        let handles = Arc::new(
+            (0..(PACKAGES + NEW_PACKAGES) * SLICE_SIZE)
+                .map(|seq_no| storage.load_block_handle(&BlockIdExt::with_params(
+                    ShardIdent::masterchain(), seq_no, UInt256::from([seq_no as u8; 32]), UInt256::default()
+                )))
+                .collect::<Result<Vec<_>>>()?
+        );
+
+        // Packages can't be skipped, so they are started in order before writers run
+        for idx in 0..PACKAGES {
+            add_block(&slice, &mut runtime, &handles, idx * SLICE_SIZE)?;
+        }
+
+        let mut workers = Vec::new();
+        for writer in 0..WRITERS {
+            let slice = Arc::clone(&slice);
+            let handles = Arc::clone(&handles);
+            workers.push(std::thread::spawn(move || -> Result<()> {
+                let mut runtime = tokio::runtime::Builder::new().basic_scheduler().enable_all().build()?;
+                for seq_no in (0..PACKAGES * SLICE_SIZE).filter(|seq_no| seq_no % SLICE_SIZE != 0) {
+                    if seq_no % WRITERS == writer {
+                        add_block(&slice, &mut runtime, &handles, seq_no)?;
+                    }
+                }
+                Ok(())
+            }));
+        }
+        // Meanwhile new packages are created and filled
+        for seq_no in PACKAGES * SLICE_SIZE..(PACKAGES + NEW_PACKAGES) * SLICE_SIZE {
+            add_block(&slice, &mut runtime, &handles, seq_no)?;
+        }
+        for worker in workers {
+            worker.join().expect("Writer thread panicked")?;
+        }
+
+        for handle in handles.iter() {
+            let seq_no = handle.id().seq_no();
+            let entry_id = PackageEntryId::<_, UInt256, PublicKey>::Block(handle.id().clone());
+            let entry = runtime.block_on(slice.get_file(Some(handle), &entry_id))?;
+            assert_eq!(entry.data(), &entry_data(seq_no), "entry of block {} is damaged", seq_no);
+        }
+        assert!(runtime.block_on(slice.verify())?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_locate_entry_takes_package_from_index() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = Arc::new(dir.path().to_path_buf());
+        let mut runtime = tokio::runtime::Builder::new().basic_scheduler().enable_all().build()?;
+        let slice = runtime.block_on(open_slice(&root, PackageCompression::Stored))?;
+
+        let storage = BlockHandleStorage::new(Arc::new(BlockHandleDb::in_memory()), &StatusDb::in_memory())?;
+        let handles = [0, SLICE_SIZE].iter()
+            .map(|seq_no| storage.load_block_handle(&BlockIdExt::with_params(
+                ShardIdent::masterchain(), *seq_no, UInt256::from([*seq_no as u8 + 1; 32]), UInt256::default()
+            )))
+            .collect::<Result<Vec<_>>>()?;
+        // Both entries are first in their packages, so they have the same offset
+        for (idx, handle) in handles.iter().enumerate() {
+            let entry_id = PackageEntryId::<_, UInt256, PublicKey>::Block(handle.id().clone());
+            runtime.block_on(slice.add_file(Some(handle), &entry_id, entry_data(idx as u32)))?;
+        }
+
+        let entry_id = PackageEntryId::<_, UInt256, PublicKey>::Block(handles[1].id().clone());
+        let (package_id, offset, _) = runtime.block_on(slice.locate_entry(&entry_id))?
+            .expect("entry must be located");
+        assert_eq!(package_id, PackageId::for_block(SLICE_SIZE));
+        assert_eq!(offset, 0);
+
+        // Offset written by older versions is resolved by probing packages
+        slice.offsets_db.put_value(&(&entry_id).into(), PackageOffset::Legacy(offset))?;
+        let (package_id, _, _) = runtime.block_on(slice.locate_entry(&entry_id))?
+            .expect("legacy entry must be located");
+        assert_eq!(package_id, PackageId::for_block(SLICE_SIZE));
+
+        // Failure to read the package is not taken for absence of the entry
+        let entry_id = PackageEntryId::<_, UInt256, PublicKey>::Block(handles[0].id().clone());
+        let path = Arc::clone(runtime.block_on(slice.packages.read())[0].package().path());
+        std::fs::remove_file(&*path)?;
+        assert!(runtime.block_on(slice.locate_entry(&entry_id)).is_err());
+
+        Ok(())
+    }
+}
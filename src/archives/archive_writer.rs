@@ -0,0 +1,148 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use ton_api::ton::PublicKey;
+use ton_block::BlockIdExt;
+use ton_types::{error, fail, Result, UInt256};
+
+use crate::archives::archive_manager::ArchiveManager;
+use crate::archives::package_entry_id::PackageEntryId;
+use crate::error::StorageError;
+use crate::types::BlockHandle;
+
+
+/// Behaviour of enqueueing into the full queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Caller waits until the writer takes an entry from the queue
+    Block,
+    /// Enqueueing fails with StorageError::WouldBlock
+    WouldBlock,
+}
+
+struct WriterEntry {
+    handle: Arc<BlockHandle>,
+    entry_id: PackageEntryId<BlockIdExt, UInt256, PublicKey>,
+    data: Vec<u8>,
+}
+
+/// Appends entries to archive packages on a dedicated thread, so callers don't wait for disk.
+/// Queue of pending entries is bounded
+pub struct ArchiveWriter {
+    sender: Mutex<Option<SyncSender<WriterEntry>>>,
+    thread: Mutex<Option<JoinHandle<Result<()>>>>,
+    backpressure: Backpressure,
+}
+
+impl ArchiveWriter {
+    /// Starts writer thread with queue holding up to `capacity` entries
+    pub fn start(archive_manager: Arc<ArchiveManager>, capacity: usize, backpressure: Backpressure) -> Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let thread = std::thread::Builder::new()
+            .name("archive-writer".to_string())
+            .spawn(move || Self::run(archive_manager, receiver))?;
+
+        Ok(Self {
+            sender: Mutex::new(Some(sender)),
+            thread: Mutex::new(Some(thread)),
+            backpressure,
+        })
+    }
+
+    pub const fn backpressure(&self) -> Backpressure {
+        self.backpressure
+    }
+
+    /// Puts entry into the queue and returns without waiting for it to be written.
+    /// Entry is appended to the package chosen for the block, as in ArchiveManager::move_to_archive
+    pub fn enqueue(
+        &self,
+        handle: Arc<BlockHandle>,
+        entry_id: PackageEntryId<BlockIdExt, UInt256, PublicKey>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let sender = self.sender.lock().unwrap().clone()
+            .ok_or_else(|| error!("Archive writer is stopped"))?;
+        let entry = WriterEntry { handle, entry_id, data };
+        match self.backpressure {
+            Backpressure::Block => {
+                if sender.send(entry).is_err() {
+                    fail!("Archive writer thread is terminated")
+                }
+            },
+            Backpressure::WouldBlock => match sender.try_send(entry) {
+                Ok(()) => {},
+                Err(TrySendError::Full(_)) => Err(StorageError::WouldBlock)?,
+                Err(TrySendError::Disconnected(_)) => fail!("Archive writer thread is terminated"),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Stops accepting entries, waits until all queued entries are written and written packages
+    /// are fsynced. Then blocks, which queued entries are all written, are marked as moved to
+    /// archive; storing their handles is up to the caller. Returns the first error occurred while
+    /// writing, blocks with failed entries are not marked
+    pub fn flush_and_stop(&self) -> Result<()> {
+        self.sender.lock().unwrap().take();
+        let thread = self.thread.lock().unwrap().take()
+            .ok_or_else(|| error!("Archive writer is already stopped"))?;
+
+        thread.join()
+            .map_err(|_| error!("Archive writer thread panicked"))?
+    }
+
+    fn run(archive_manager: Arc<ArchiveManager>, receiver: Receiver<WriterEntry>) -> Result<()> {
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()?;
+
+        let mut written = FnvHashMap::default();
+        let mut archived = FnvHashMap::default();
+        let mut failed = FnvHashSet::default();
+        let mut result = Ok(());
+        for entry in receiver.iter() {
+            log::debug!(target: "storage", "Archive writer: appending entry {}", entry.entry_id);
+            match runtime.block_on(archive_manager.append_file(&entry.handle, &entry.entry_id, entry.data)) {
+                Ok(fd) => {
+                    written.insert(fd.id().clone(), fd);
+                    if !failed.contains(entry.handle.id()) {
+                        archived.insert(entry.handle.id().clone(), entry.handle);
+                    }
+                },
+                Err(err) => {
+                    log::error!(target: "storage", "Archive writer: can't append entry {}: {}", entry.entry_id, err);
+                    archived.remove(entry.handle.id());
+                    failed.insert(entry.handle.id().clone());
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                },
+            }
+        }
+
+        for fd in written.values() {
+            runtime.block_on(fd.archive_slice().sync())?;
+        }
+        // Entries are durable now, so they can be read from archive instead of temporary files
+        for handle in archived.values() {
+            handle.set_moved_to_archive();
+        }
+
+        result
+    }
+}
+
+impl Drop for ArchiveWriter {
+    fn drop(&mut self) {
+        if self.thread.lock().unwrap().is_some() {
+            if let Err(err) = self.flush_and_stop() {
+                log::error!(target: "storage", "Archive writer stopped with error: {}", err);
+            }
+        }
+    }
+}
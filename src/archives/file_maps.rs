@@ -6,6 +6,7 @@ use tokio::sync::RwLock;
 use ton_types::Result;
 
 use crate::archives::archive_slice::ArchiveSlice;
+use crate::archives::package_entry::PackageCompression;
 use crate::archives::package_id::{PackageId, PackageType};
 use crate::archives::package_index_db::{PackageIndexDb, PackageIndexEntry};
 
@@ -47,7 +48,13 @@ pub struct FileMap {
 }
 
 impl FileMap {
-    pub async fn new(db_root_path: &Arc<PathBuf>, path: impl AsRef<Path>, package_type: PackageType) -> Result<Self> {
+    pub async fn new(
+        db_root_path: &Arc<PathBuf>,
+        path: impl AsRef<Path>,
+        package_type: PackageType,
+        sync_dirs: bool,
+        compression: PackageCompression,
+    ) -> Result<Self> {
         let storage = PackageIndexDb::with_path(path);
         let mut index_pairs = Vec::new();
 
@@ -61,11 +68,19 @@ impl FileMap {
 
         let mut elements = Vec::new();
         for (key, value) in index_pairs {
+            if value.deleted() {
+                // Package removal was interrupted, finish it
+                ArchiveSlice::remove_files(db_root_path, key, package_type).await?;
+                storage.delete(&key.into())?;
+                continue;
+            }
             let archive_slice = Arc::new(ArchiveSlice::with_data(
                 Arc::clone(db_root_path),
                 key,
                 package_type,
-                value.finalized()
+                value.finalized(),
+                sync_dirs,
+                compression,
             ).await?);
             let value = Arc::new(FileDescription::with_data(
                 PackageId::with_values(key, package_type),
@@ -93,6 +108,64 @@ impl FileMap {
         Ok(())
     }
 
+    /// Marks packages, which contain only masterchain blocks below given seq_no, as deleted
+    /// in a single transaction and takes them out of the map. The last package is never taken, as it may still grow.
+    /// Marked packages are removed on next start, unless they are forgotten earlier
+    pub async fn take_below(&self, mc_seq_no: u32) -> Result<Vec<Arc<FileDescription>>> {
+        let mut guard = self.elements.write().await;
+        let mut count = 0;
+        while count + 1 < guard.len() && guard[count + 1].key <= mc_seq_no {
+            count += 1;
+        }
+        if count > 0 {
+            let transaction = self.storage.begin_transaction()?;
+            for entry in guard[..count].iter() {
+                let finalized = entry.value.archive_slice().finalized();
+                let value = serde_cbor::to_vec(&PackageIndexEntry::with_data(true, finalized))?;
+                transaction.put(&entry.key.into(), &value);
+            }
+            transaction.commit()?;
+        }
+
+        Ok(guard.drain(..count).map(|entry| entry.value).collect())
+    }
+
+    /// Removes index entry of the package, whose files are already removed
+    pub fn forget(&self, package_id: u32) -> Result<()> {
+        self.storage.delete(&package_id.into())
+    }
+
+    /// Gets ids of packages, which may contain masterchain blocks with seq_no in given inclusive
+    /// range, in ascending order. Package covers seq_nos from its id up to the next package id
+    pub async fn packages_for_range(&self, from_mc_seq_no: u32, to_mc_seq_no: u32) -> Vec<PackageId> {
+        if from_mc_seq_no > to_mc_seq_no {
+            return Vec::new();
+        }
+
+        let guard = self.elements.read().await;
+        let start = match guard.binary_search_by(|entry| entry.key.cmp(&from_mc_seq_no)) {
+            Ok(index) => index,
+            Err(index) => index.saturating_sub(1),
+        };
+        let end = match guard.binary_search_by(|entry| entry.key.cmp(&to_mc_seq_no)) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        };
+
+        guard[start..end.max(start)].iter()
+            .filter(|entry| !entry.value.deleted())
+            .map(|entry| entry.value.id().clone())
+            .collect()
+    }
+
+    /// Gets all file descriptions in ascending order of package ids
+    pub async fn all(&self) -> Vec<Arc<FileDescription>> {
+        self.elements.read().await
+            .iter()
+            .map(|entry| Arc::clone(&entry.value))
+            .collect()
+    }
+
     pub async fn get(&self, package_id: u32) -> Option<Arc<FileDescription>> {
         let guard = self.elements.read().await;
         guard.binary_search_by(|entry| entry.key.cmp(&package_id))
@@ -122,12 +195,12 @@ pub struct FileMaps {
 }
 
 impl FileMaps {
-    pub async fn new(db_root_path: &Arc<PathBuf>) -> Result<Self> {
+    pub async fn new(db_root_path: &Arc<PathBuf>, sync_dirs: bool, compression: PackageCompression) -> Result<Self> {
         let path = db_root_path.join("file_maps");
         Ok(Self {
-            files: FileMap::new(db_root_path, path.join("files"), PackageType::Blocks).await?,
-            // key_files: FileMap::new(db_root_path, path.join("key_files"), PackageType::KeyBlocks).await?,
-            // temp_files: FileMap::new(db_root_path, path.join("temp_files"), PackageType::Temp).await?,
+            files: FileMap::new(db_root_path, path.join("files"), PackageType::Blocks, sync_dirs, compression).await?,
+            // key_files: FileMap::new(db_root_path, path.join("key_files"), PackageType::KeyBlocks, sync_dirs, compression).await?,
+            // temp_files: FileMap::new(db_root_path, path.join("temp_files"), PackageType::Temp, sync_dirs, compression).await?,
         })
     }
 
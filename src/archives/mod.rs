@@ -3,9 +3,14 @@ use crate::types::BlockHandle;
 mod package_index_db;
 
 pub mod archive_manager;
+pub mod archive_problem;
+pub mod archive_writer;
 pub mod package;
 pub mod package_entry_id;
 pub mod package_entry;
+pub mod package_entry_meta;
+pub mod package_entry_meta_db;
+pub mod package_id;
 
 mod package_status_db;
 mod package_status_key;
@@ -13,9 +18,6 @@ mod file_maps;
 mod package_offsets_db;
 mod package_info;
 mod archive_slice;
-mod package_entry_meta_db;
-mod package_entry_meta;
-mod package_id;
 
 fn get_mc_seq_no_opt(block_handle: Option<&BlockHandle>) -> u32 {
     if let Some(handle) = block_handle {
@@ -6,9 +6,13 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::sync::Mutex;
-use ton_types::{error, fail, Result};
+use ton_api::ton::PublicKey;
+use ton_block::BlockIdExt;
+use ton_types::{error, fail, Result, UInt256};
 
-use crate::archives::package_entry::{PackageEntry, PKG_ENTRY_HEADER_SIZE};
+use crate::archives::package_entry::{PackageCompression, PackageEntry, PackageEntryHeader, PKG_ENTRY_HEADER_SIZE};
+use crate::archives::package_entry_id::PackageEntryId;
+use crate::traits::Serializable;
 
 
 #[derive(Debug)]
@@ -16,6 +20,7 @@ pub struct Package {
     path: Arc<PathBuf>,
     read_only: bool,
     size: AtomicU64,
+    compression: PackageCompression,
     write_mutex: Mutex<()>
 }
 
@@ -36,6 +41,17 @@ async fn read_header<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<()> {
 
 impl Package {
     pub async fn open(path: Arc<PathBuf>, read_only: bool, create: bool) -> Result<Self> {
+        Self::open_with_compression(path, read_only, create, PackageCompression::Stored).await
+    }
+
+    /// Opens package, which appends entries with given compression.
+    /// Entries of any compression are read regardless of it
+    pub async fn open_with_compression(
+        path: Arc<PathBuf>,
+        read_only: bool,
+        create: bool,
+        compression: PackageCompression
+    ) -> Result<Self> {
         let mut file = Self::open_file_ext(read_only, create, &*path).await?;
         let mut size = file.metadata().await?.len();
 
@@ -55,6 +71,7 @@ impl Package {
                 path,
                 read_only, size:
                 AtomicU64::new(size),
+                compression,
                 write_mutex: Mutex::new(()),
             }
         )
@@ -68,6 +85,10 @@ impl Package {
         &self.path
     }
 
+    pub const fn compression(&self) -> PackageCompression {
+        self.compression
+    }
+
     pub async fn truncate(&self, size: u64) -> Result<()> {
         let new_size = PKG_HEADER_SIZE as u64 + size;
         log::debug!(target: "storage", "Truncating package, new size: {} bytes", new_size);
@@ -98,22 +119,43 @@ impl Package {
         &self,
         entry: &PackageEntry,
         after_append: impl FnOnce(u64, u64) -> Result<()>
+    ) -> Result<()> {
+        self.append_entry_with_compression(entry, self.compression, after_append).await
+    }
+
+    /// Appends entry with given compression instead of the package one
+    pub async fn append_entry_with_compression(
+        &self,
+        entry: &PackageEntry,
+        compression: PackageCompression,
+        after_append: impl FnOnce(u64, u64) -> Result<()>
     ) -> Result<()> {
         assert!(entry.filename().as_bytes().len() <= u16::max_value() as usize);
-        assert!(entry.data().len() <= u32::max_value() as usize);
+        if compression == PackageCompression::Stored {
+            assert!(entry.data().len() <= u32::max_value() as usize);
+        }
 
         let mut file = self.open_file().await?;
         {
             let _write_guard = self.write_mutex.lock().await;
             file.seek(SeekFrom::End(0)).await?;
             let entry_offset = self.size();
-            let entry_size = entry.write_to(&mut file).await?;
+            let entry_size = entry.write_to(&mut file, compression).await?;
             self.size.fetch_add(entry_size, Ordering::SeqCst);
 
             after_append(entry_offset, entry_offset + entry_size)
         }
     }
 
+    /// Flushes appended entries of the package file to disk
+    pub async fn sync(&self) -> Result<()> {
+        let file = self.open_file().await?;
+        let _write_guard = self.write_mutex.lock().await;
+        file.sync_all().await?;
+
+        Ok(())
+    }
+
     async fn open_file_ext(read_only: bool, create: bool, path: impl AsRef<Path>) -> Result<File> {
         Ok(OpenOptions::new()
             .read(true)
@@ -137,6 +179,116 @@ impl<R: AsyncReadExt + Unpin> PackageReader<R> {
     }
 }
 
+/// Reads header of entry with given offset from opened package file of given size,
+/// checking that the whole entry fits into the file
+pub(crate) async fn read_entry_header_at(file: &mut File, file_size: u64, offset: u64) -> Result<PackageEntryHeader> {
+    let position = PKG_HEADER_SIZE as u64 + offset;
+    if position + PKG_ENTRY_HEADER_SIZE as u64 > file_size {
+        fail!("Entry offset {} points past the end of package ({} bytes), index is corrupted", offset, file_size)
+    }
+    file.seek(SeekFrom::Start(position)).await?;
+    let mut buf = [0; PKG_ENTRY_HEADER_SIZE];
+    file.read_exact(&mut buf).await?;
+    let entry_header = PackageEntryHeader::from_slice(&buf)?;
+    if position + entry_header.calc_entry_size() > file_size {
+        fail!("Entry with offset {} exceeds the end of package ({} bytes), index is corrupted", offset, file_size)
+    }
+
+    Ok(entry_header)
+}
+
+/// Reads header of entry with given offset like read_entry_header_at(), but returns None
+/// if there is no entry at the offset: it is past the end of the file, bytes there are not
+/// an entry header or the entry doesn't fit into the file. I/O errors are returned
+pub(crate) async fn probe_entry_header_at(file: &mut File, file_size: u64, offset: u64) -> Result<Option<PackageEntryHeader>> {
+    let position = PKG_HEADER_SIZE as u64 + offset;
+    if position + PKG_ENTRY_HEADER_SIZE as u64 > file_size {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(position)).await?;
+    let mut buf = [0; PKG_ENTRY_HEADER_SIZE];
+    file.read_exact(&mut buf).await?;
+    if !PackageEntryHeader::starts_header(&buf) {
+        return Ok(None);
+    }
+    let entry_header = PackageEntryHeader::from_slice(&buf)?;
+    if position + entry_header.calc_entry_size() > file_size {
+        return Ok(None);
+    }
+
+    Ok(Some(entry_header))
+}
+
+/// Reads package entries at given offsets one by one, keeping only the current entry in memory
+pub struct PackageEntryStream {
+    file: File,
+    file_size: u64,
+    offsets: std::vec::IntoIter<u64>,
+    data: Vec<u8>,
+}
+
+impl PackageEntryStream {
+    /// Constructs stream over opened package file. Offsets are relative to the package data,
+    /// as stored in package offsets database; they are read in ascending order
+    pub async fn with_offsets(mut file: File, mut offsets: Vec<u64>) -> Result<Self> {
+        let file_size = file.metadata().await?.len();
+        file.seek(SeekFrom::Start(0)).await?;
+        read_header(&mut file).await?;
+
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        Ok(Self {
+            file,
+            file_size,
+            offsets: offsets.into_iter(),
+            data: Vec::new(),
+        })
+    }
+
+    /// Constructs stream over all entries of opened package file, finding them by entry headers
+    pub async fn with_all_entries(mut file: File) -> Result<Self> {
+        let file_size = file.metadata().await?.len();
+        file.seek(SeekFrom::Start(0)).await?;
+        read_header(&mut file).await?;
+
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        while PKG_HEADER_SIZE as u64 + offset + PKG_ENTRY_HEADER_SIZE as u64 <= file_size {
+            let mut buf = [0; PKG_ENTRY_HEADER_SIZE];
+            file.read_exact(&mut buf).await?;
+            let entry_size = PackageEntryHeader::from_slice(&buf)?.calc_entry_size();
+            offsets.push(offset);
+            offset += entry_size;
+            file.seek(SeekFrom::Start(PKG_HEADER_SIZE as u64 + offset)).await?;
+        }
+
+        Self::with_offsets(file, offsets).await
+    }
+
+    /// Reads next entry. Returned data is valid until the next call
+    pub async fn next(&mut self) -> Result<Option<(PackageEntryId<BlockIdExt, UInt256, PublicKey>, &[u8])>> {
+        let offset = match self.offsets.next() {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+
+        let entry_header = read_entry_header_at(&mut self.file, self.file_size, offset).await?;
+
+        let mut filename = vec![0; entry_header.filename_size() as usize];
+        self.file.read_exact(&mut filename).await?;
+        let entry_id = PackageEntryId::from_filename(&String::from_utf8(filename)?)?;
+
+        self.data.resize(entry_header.data_size() as usize, 0);
+        self.file.read_exact(&mut self.data).await?;
+        if entry_header.compressed() {
+            self.data = zstd::decode_all(self.data.as_slice())?;
+        }
+
+        Ok(Some((entry_id, &self.data)))
+    }
+}
+
 pub async fn read_package_from_file(path: impl AsRef<Path>) -> Result<PackageReader<File>> {
     read_package_from(
         OpenOptions::new()
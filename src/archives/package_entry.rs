@@ -7,16 +7,62 @@ use crate::traits::Serializable;
 
 pub(crate) const PKG_ENTRY_HEADER_SIZE: usize = 8;
 const PKG_ENTRY_HEADER_MAGIC: u16 = 0x1E8B;
+/// Magic of entries with zstd-compressed data
+const PKG_ENTRY_HEADER_MAGIC_ZSTD: u16 = 0x1E8C;
+
+/// Compression of package entry data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageCompression {
+    /// Data is stored as is
+    Stored,
+    /// Data is compressed by zstd with given level
+    Zstd(i32),
+}
+
+impl Default for PackageCompression {
+    fn default() -> Self {
+        PackageCompression::Stored
+    }
+}
 
 #[derive(Debug)]
 pub struct PackageEntryHeader {
     filename_size: u16,
     data_size: u32,
+    compressed: bool,
 }
 
 impl PackageEntryHeader {
     pub const fn with_data(filename_size: u16, data_size: u32) -> Self {
-        Self { filename_size, data_size }
+        Self::with_compression(filename_size, data_size, false)
+    }
+
+    /// Constructs header of entry, which data of given size is compressed, if `compressed` is set
+    pub const fn with_compression(filename_size: u16, data_size: u32, compressed: bool) -> Self {
+        Self { filename_size, data_size, compressed }
+    }
+
+    pub const fn filename_size(&self) -> u16 {
+        self.filename_size
+    }
+
+    pub const fn data_size(&self) -> u32 {
+        self.data_size
+    }
+
+    pub const fn compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Checks whether given bytes start with magic of entry header
+    pub fn starts_header(data: &[u8]) -> bool {
+        match data {
+            [b0, b1, ..] => {
+                let magic = u16::from_le_bytes([*b0, *b1]);
+                magic == PKG_ENTRY_HEADER_MAGIC || magic == PKG_ENTRY_HEADER_MAGIC_ZSTD
+            }
+            _ => false,
+        }
     }
 
     pub const fn calc_entry_size(&self) -> u64 {
@@ -28,7 +74,8 @@ impl PackageEntryHeader {
 
 impl Serializable for PackageEntryHeader {
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
-        writer.write_all(&PKG_ENTRY_HEADER_MAGIC.to_le_bytes())?;
+        let magic = if self.compressed { PKG_ENTRY_HEADER_MAGIC_ZSTD } else { PKG_ENTRY_HEADER_MAGIC };
+        writer.write_all(&magic.to_le_bytes())?;
         writer.write_all(&self.filename_size.to_le_bytes())?;
         writer.write_all(&self.data_size.to_le_bytes())?;
 
@@ -36,16 +83,16 @@ impl Serializable for PackageEntryHeader {
     }
 
     fn deserialize<R: Read>(reader: &mut R) -> Result<Self> where Self: Sized {
-        let magic = reader.read_le_u16()?;
-
-        if magic != PKG_ENTRY_HEADER_MAGIC {
-            fail!("Bad entry magic: 0x{:X}", magic)
-        }
+        let compressed = match reader.read_le_u16()? {
+            PKG_ENTRY_HEADER_MAGIC => false,
+            PKG_ENTRY_HEADER_MAGIC_ZSTD => true,
+            magic => fail!("Bad entry magic: 0x{:X}", magic),
+        };
 
         let filename_size = reader.read_le_u16()?;
         let data_size = reader.read_le_u32()?;
 
-        Ok(Self::with_data(filename_size, data_size))
+        Ok(Self::with_compression(filename_size, data_size, compressed))
     }
 }
 
@@ -79,19 +126,34 @@ impl PackageEntry {
 
         let mut data = vec![0; entry_header.data_size as usize];
         reader.read_exact(&mut data).await?;
+        if entry_header.compressed() {
+            data = zstd::decode_all(data.as_slice())?;
+        }
 
         Ok(Some(Self::with_data(filename, data)))
     }
 
-    pub(super) async fn write_to<W: AsyncWriteExt + Unpin>(&self, writer: &mut W) -> Result<u64> {
-        let entry_header = PackageEntryHeader::with_data(
+    pub(super) async fn write_to<W: AsyncWriteExt + Unpin>(
+        &self,
+        writer: &mut W,
+        compression: PackageCompression
+    ) -> Result<u64> {
+        let compressed_data = match compression {
+            PackageCompression::Stored => None,
+            PackageCompression::Zstd(level) => Some(zstd::encode_all(self.data.as_slice(), level)?),
+        };
+        let data = compressed_data.as_ref().unwrap_or(&self.data);
+        assert!(data.len() <= u32::max_value() as usize);
+
+        let entry_header = PackageEntryHeader::with_compression(
             self.filename.as_bytes().len() as u16,
-            self.data.len() as u32
+            data.len() as u32,
+            compressed_data.is_some()
         );
 
         writer.write_all(&entry_header.to_vec()?).await?;
         writer.write_all(self.filename.as_bytes()).await?;
-        writer.write_all(&self.data).await?;
+        writer.write_all(data).await?;
         writer.flush().await?;
 
         Ok(entry_header.calc_entry_size())
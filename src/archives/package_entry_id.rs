@@ -152,6 +152,25 @@ where
     }
 }
 
+/// Kind of block entry, matching data, proof and prooflink flags of block handle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageEntryKind {
+    Block,
+    Proof,
+    ProofLink,
+}
+
+impl PackageEntryKind {
+    /// Gets id of the entry of given kind for given block
+    pub fn entry_id(self, block_id: &BlockIdExt) -> PackageEntryId<&BlockIdExt, &UInt256, &PublicKey> {
+        match self {
+            PackageEntryKind::Block => PackageEntryId::Block(block_id),
+            PackageEntryKind::Proof => PackageEntryId::Proof(block_id),
+            PackageEntryKind::ProofLink => PackageEntryId::ProofLink(block_id),
+        }
+    }
+}
+
 pub trait GetFileName {
     fn filename(&self) -> String;
 }
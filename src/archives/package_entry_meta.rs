@@ -1,20 +1,36 @@
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::archives::package_id::PackageType;
+
+/// Metadata of archive package slice, stored in the slice index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PackageEntryMeta {
+    /// Size of package data in bytes
     entry_size: u64,
+    /// Type of the package; absent in records written by older versions, which are blocks only
+    #[serde(default = "default_entry_kind")]
+    entry_kind: PackageType,
+    /// Package format version
     version: u32,
 }
 
+const fn default_entry_kind() -> PackageType {
+    PackageType::Blocks
+}
+
 impl PackageEntryMeta {
-    pub const fn with_data(entry_size: u64, version: u32) -> Self {
-        Self { entry_size, version }
+    pub const fn with_data(entry_size: u64, entry_kind: PackageType, version: u32) -> Self {
+        Self { entry_size, entry_kind, version }
     }
 
     pub const fn entry_size(&self) -> u64 {
         self.entry_size
     }
 
+    pub const fn entry_kind(&self) -> PackageType {
+        self.entry_kind
+    }
+
     pub const fn version(&self) -> u32 {
         self.version
     }
@@ -1,5 +1,5 @@
 use crate::archives::package_entry_meta::PackageEntryMeta;
-use crate::db::traits::{KvcWriteable, U32Key};
+use crate::db::traits::{KvcSnapshotable, U32Key};
 use crate::db_impl_cbor;
 
-db_impl_cbor!(PackageEntryMetaDb, KvcWriteable, U32Key, PackageEntryMeta);
+db_impl_cbor!(PackageEntryMetaDb, KvcSnapshotable, U32Key, PackageEntryMeta);
@@ -4,7 +4,7 @@ use serde_derive::{Deserialize, Serialize};
 
 use ton_types::Result;
 
-use crate::db::traits::{KvcWriteable, U32Key};
+use crate::db::traits::{KvcReadable, KvcTransactional, U32Key};
 use crate::db_impl_cbor;
 
 #[derive(Serialize, Deserialize)]
@@ -31,11 +31,22 @@ impl PackageIndexEntry {
     }
 }
 
-db_impl_cbor!(PackageIndexDb, KvcWriteable, U32Key, PackageIndexEntry);
+db_impl_cbor!(PackageIndexDb, KvcTransactional, U32Key, PackageIndexEntry);
 
 impl PackageIndexDb {
-    pub fn for_each_deserialized(&self, mut predicate: impl FnMut(u32, PackageIndexEntry) -> Result<bool>) -> Result<bool> {
-        self.for_each(&mut |key_data, data| {
+    pub fn for_each_deserialized(&self, predicate: impl FnMut(u32, PackageIndexEntry) -> Result<bool>) -> Result<bool> {
+        Self::for_each_deserialized_in(&*self.db, predicate)
+    }
+
+    /// Iterates over deserialized entries of given collection or its snapshot
+    pub fn for_each_deserialized_in<T>(
+        db: &T,
+        mut predicate: impl FnMut(u32, PackageIndexEntry) -> Result<bool>
+    ) -> Result<bool>
+    where
+        T: KvcReadable<U32Key> + ?Sized
+    {
+        db.for_each(&mut |key_data, data| {
             let key = u32::from_le_bytes(key_data.try_into()?);
             let value = serde_cbor::from_slice(data)?;
             predicate(key, value)
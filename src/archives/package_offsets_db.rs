@@ -2,12 +2,13 @@ use std::borrow::Borrow;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
+use serde_derive::{Deserialize, Serialize};
 use ton_api::ton::PublicKey;
 use ton_block::BlockIdExt;
-use ton_types::UInt256;
+use ton_types::{Result, UInt256};
 
 use crate::archives::package_entry_id::PackageEntryId;
-use crate::db::traits::{DbKey, KvcWriteable};
+use crate::db::traits::{DbKey, KvcReadable, KvcSnapshotable};
 use crate::db_impl_cbor;
 
 pub struct PackageOffsetKey {
@@ -49,4 +50,54 @@ impl DbKey for PackageOffsetKey {
     }
 }
 
-db_impl_cbor!(PackageOffsetsDb, KvcWriteable, PackageOffsetKey, u64);
+/// Offset of entry relative to package data, stored in the offsets index
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PackageOffset {
+    /// Offset in the package with given index in the slice
+    Located { offset: u64, idx: u32 },
+    /// Bare offset written by older versions; its package is unknown
+    Legacy(u64),
+}
+
+impl PackageOffset {
+    pub const fn with_package(offset: u64, idx: u32) -> Self {
+        Self::Located { offset, idx }
+    }
+
+    pub const fn offset(&self) -> u64 {
+        match self {
+            Self::Located { offset, .. } => *offset,
+            Self::Legacy(offset) => *offset,
+        }
+    }
+
+    /// Index of the package holding the entry, if it is known
+    pub const fn idx(&self) -> Option<u32> {
+        match self {
+            Self::Located { idx, .. } => Some(*idx),
+            Self::Legacy(_) => None,
+        }
+    }
+}
+
+db_impl_cbor!(PackageOffsetsDb, KvcSnapshotable, PackageOffsetKey, PackageOffset);
+
+impl PackageOffsetsDb {
+    /// Gets all stored offsets in ascending order
+    pub fn offsets(&self) -> Result<Vec<u64>> {
+        Self::offsets_in(&*self.db)
+    }
+
+    /// Gets all offsets stored in given collection or its snapshot, in ascending order
+    pub fn offsets_in<T: KvcReadable<PackageOffsetKey> + ?Sized>(db: &T) -> Result<Vec<u64>> {
+        let mut offsets = Vec::new();
+        db.for_each(&mut |_key, value| {
+            offsets.push(serde_cbor::from_slice::<PackageOffset>(value)?.offset());
+            Ok(true)
+        })?;
+        offsets.sort_unstable();
+
+        Ok(offsets)
+    }
+}
@@ -8,6 +8,8 @@ pub enum PackageStatusKey {
     SliceSize,
     NonSlicedSize,
     TotalSlices,
+    /// Set once the slice is opened for appending compressed entries
+    Compressed,
 }
 
 impl DbKey for PackageStatusKey {
@@ -1,35 +1,140 @@
+use std::path::Path;
 use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicU32, Ordering};
 
+use rocksdb::Options;
+use rocksdb::compaction_filter::Decision;
 use ton_block::BlockIdExt;
-use ton_types::{error, Result};
+use ton_types::{error, fail, Result};
 
+use crate::db::rocksdb::RocksDb;
 use crate::db::traits::KvcWriteable;
 use crate::db_impl_serializable;
+use crate::status_db::StatusDb;
 use crate::traits::Serializable;
-use crate::types::{BlockHandle, BlockId, BlockMeta};
+use crate::types::{BlockHandle, BlockId, BlockIdHashing, BlockMeta, StatusKey};
 
 
 db_impl_serializable!(BlockHandleDb, KvcWriteable, BlockId, BlockMeta);
 
+/// Name of compaction filter expiring archived block meta
+pub const ARCHIVED_META_FILTER_NAME: &str = "archived_block_meta";
+
+impl BlockHandleDb {
+    /// Constructs new instance using RocksDB with given path, which drops meta of blocks
+    /// moved to archive below given GC horizon (masterchain seqno) during compaction
+    pub fn with_path_and_gc_horizon<P: AsRef<Path>>(path: P, gc_horizon: Arc<AtomicU32>) -> Self {
+        Self {
+            db: Box::new(RocksDb::with_options(path, |options| {
+                configure_archived_meta_filter(options, Arc::clone(&gc_horizon))
+            }))
+        }
+    }
+
+    /// Gets scheme of block id keys the database is built with, by its marker kept in given
+    /// status database apart from block meta. Database without the marker is built with SHA-256 keys
+    pub fn hashing(&self, status_db: &StatusDb) -> Result<BlockIdHashing> {
+        match status_db.try_get(&StatusKey::BlockIdHashing)? {
+            Some(marker) => match marker.as_ref().first() {
+                Some(&marker) => BlockIdHashing::from_marker(marker),
+                None => fail!("Empty block id hashing marker"),
+            },
+            None => Ok(BlockIdHashing::Sha256),
+        }
+    }
+
+    /// Ensures the database is built with given scheme of block id keys, marking new database
+    /// with it in given status database. Fails, if the database is built with another scheme
+    pub fn ensure_hashing(&self, status_db: &StatusDb, hashing: BlockIdHashing) -> Result<()> {
+        if status_db.contains(&StatusKey::BlockIdHashing)? {
+            let stored = self.hashing(status_db)?;
+            if stored != hashing {
+                fail!("Block handle database is built with {:?} keys, not {:?}", stored, hashing);
+            }
+            return Ok(());
+        }
+        let mut is_empty = true;
+        self.for_each(&mut |_, _| {
+            is_empty = false;
+            Ok(false)
+        })?;
+        if !is_empty && hashing != BlockIdHashing::Sha256 {
+            fail!("Block handle database is built with {:?} keys, not {:?}", BlockIdHashing::Sha256, hashing);
+        }
+        status_db.put(&StatusKey::BlockIdHashing, &[hashing.marker()])
+    }
+}
+
+/// Sets compaction filter removing meta of blocks moved to archive with masterchain seqno
+/// below GC horizon. The horizon is read on every check, so it may be advanced at runtime.
+/// Options may belong to the whole database or to column family of block meta
+pub fn configure_archived_meta_filter(options: &mut Options, gc_horizon: Arc<AtomicU32>) {
+    options.set_compaction_filter(ARCHIVED_META_FILTER_NAME, move |_level: u32, key: &[u8], value: &[u8]| {
+        if is_expired_meta(key, value, gc_horizon.load(Ordering::Relaxed)) {
+            Decision::Remove
+        } else {
+            Decision::Keep
+        }
+    });
+}
+
+/// Meta without stored block id can't be checked, so it is never expired
+fn is_expired_meta(key: &[u8], value: &[u8], gc_horizon: u32) -> bool {
+    let (block_id, meta) = match (BlockId::from_key_and_value(key, value), BlockMeta::from_slice(value)) {
+        (Ok(block_id), Ok(meta)) => (block_id, meta),
+        _ => return false,
+    };
+    if !meta.is_moved_to_archive() {
+        return false;
+    }
+    let id = block_id.block_id_ext();
+    let mc_seq_no = if id.shard().is_masterchain() {
+        id.seq_no()
+    } else {
+        meta.masterchain_ref_seq_no().load(Ordering::Relaxed)
+    };
+
+    mc_seq_no < gc_horizon
+}
+
 pub(crate) type BlockHandleCache = Arc<lockfree::map::Map<BlockIdExt, Weak<BlockHandle>>>;
 
 pub struct BlockHandleStorage {
     block_handle_db: Arc<BlockHandleDb>,
     block_handle_cache: BlockHandleCache,
+    hashing: BlockIdHashing,
 }
 
 impl BlockHandleStorage {
-    pub fn new(block_handle_db: Arc<BlockHandleDb>) -> Self {
-        Self {
+    /// Creates storage with SHA-256 keys, which must match the scheme marked in given status database
+    pub fn new(block_handle_db: Arc<BlockHandleDb>, status_db: &StatusDb) -> Result<Self> {
+        Self::with_hashing(block_handle_db, status_db, BlockIdHashing::default())
+    }
+
+    /// Creates storage with given scheme of block id keys, which must match the scheme marked
+    /// in given status database
+    pub fn with_hashing(
+        block_handle_db: Arc<BlockHandleDb>,
+        status_db: &StatusDb,
+        hashing: BlockIdHashing,
+    ) -> Result<Self> {
+        block_handle_db.ensure_hashing(status_db, hashing)?;
+        Ok(Self {
             block_handle_db,
             block_handle_cache: BlockHandleCache::default(),
-        }
+            hashing,
+        })
     }
 
     pub const fn block_handle_db(&self) -> &Arc<BlockHandleDb> {
         &self.block_handle_db
     }
 
+    /// Gets scheme of block id keys of the storage
+    pub const fn hashing(&self) -> BlockIdHashing {
+        self.hashing
+    }
+
     pub fn load_block_handle(&self, id: &BlockIdExt) -> Result<Arc<BlockHandle>> {
         log::trace!("load_block_handle {}", id);
 
@@ -48,19 +153,33 @@ impl BlockHandleStorage {
         Ok(handle.ok_or_else(|| error!("unexpected None value in load_block_handle_impl"))?)
     }
 
+    /// Stores block meta followed by block id, so the id can be recovered while iterating
     pub fn store_block_handle(&self, handle: &BlockHandle) -> Result<()> {
-        self.block_handle_db.put_value(&handle.id().into(), handle.meta())?;
+        let mut value = handle.meta().to_vec()?;
+        handle.id().serialize(&mut value)?;
+        self.block_handle_db.put(&BlockId::with_hashing(handle.id().clone(), self.hashing), &value)?;
         Ok(())
     }
 
+    /// Iterates over stored block handles. Handles stored without block id are skipped
+    pub fn for_each_block(&self, mut predicate: impl FnMut(BlockId, BlockMeta) -> Result<bool>) -> Result<bool> {
+        self.block_handle_db.for_each(&mut |key, value| {
+            let block_id = match BlockId::from_key_and_value(key, value) {
+                Ok(block_id) => block_id,
+                Err(_) => return Ok(true),
+            };
+            predicate(block_id, BlockMeta::from_slice(value)?)
+        })
+    }
+
     #[inline]
     pub(super) fn create_handle(&self, id: BlockIdExt, meta: BlockMeta) -> Arc<BlockHandle> {
         Arc::new(BlockHandle::with_values(id, meta, Arc::clone(&self.block_handle_cache)))
     }
 
     fn load_or_create_handle(&self, id: BlockIdExt) -> Result<Arc<BlockHandle>> {
-        Ok(match self.block_handle_db.try_get_value(&(&id).into())? {
-            None => self.create_handle(id, BlockMeta::default()),
+        Ok(match self.block_handle_db.try_get_value(&BlockId::with_hashing(id.clone(), self.hashing))? {
+            None => self.create_handle(id, BlockMeta::created_now()),
             Some(block_meta) => self.create_handle(id, block_meta),
         })
     }
@@ -0,0 +1,128 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+
+use ton_types::{CellData, CellImpl, UInt256};
+
+use crate::types::{CellId, StorageCell};
+
+/// Usage statistics of cell cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+}
+
+impl CacheStats {
+    pub const fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub const fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns share of lookups served from the cache, or 0 if there were no lookups
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+
+        self.hits as f64 / total as f64
+    }
+}
+
+/// Decoded cell with hashes of its references. It holds neither loaded references
+/// nor the storage, so cached cells don't keep subtrees and the storage alive
+#[derive(Debug)]
+pub(crate) struct CachedCell {
+    cell_data: CellData,
+    references: Vec<UInt256>,
+}
+
+impl CachedCell {
+    pub fn new(cell: &StorageCell) -> Self {
+        Self {
+            cell_data: cell.cell_data().clone(),
+            references: cell.references().iter().map(|reference| reference.hash()).collect(),
+        }
+    }
+
+    pub const fn cell_data(&self) -> &CellData {
+        &self.cell_data
+    }
+
+    pub fn references(&self) -> &[UInt256] {
+        &self.references
+    }
+}
+
+/// Bounded LRU cache of decoded cells
+pub(crate) struct CellCache {
+    cells: Mutex<LruCache<CellId, Arc<CachedCell>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CellCache {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            cells: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, cell_id: &CellId) -> Option<Arc<CachedCell>> {
+        let cell = self.cells.lock()
+            .expect("Poisoned Mutex")
+            .get(cell_id)
+            .map(Arc::clone);
+        match cell {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        cell
+    }
+
+    pub fn insert(&self, cell_id: CellId, cell: &StorageCell) {
+        let cell = Arc::new(CachedCell::new(cell));
+        self.cells.lock()
+            .expect("Poisoned Mutex")
+            .put(cell_id, cell);
+    }
+
+    pub fn invalidate(&self, cell_id: &CellId) {
+        self.cells.lock()
+            .expect("Poisoned Mutex")
+            .pop(cell_id);
+    }
+
+    pub fn clear(&self) {
+        self.cells.lock()
+            .expect("Poisoned Mutex")
+            .clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Debug for CellCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cells = self.cells.lock().expect("Poisoned Mutex");
+        f.debug_struct("CellCache")
+            .field("len", &cells.len())
+            .field("capacity", &cells.cap())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
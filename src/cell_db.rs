@@ -1,62 +1,844 @@
+use std::collections::hash_map::Entry;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
 use std::io::{Cursor, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
 use std::sync::Arc;
 
-use ton_types::{ByteOrderRead, Cell, CellData, Result, MAX_REFERENCES_COUNT};
+use fnv::{FnvHashMap, FnvHashSet};
+use rocksdb::MergeOperands;
+use sha2::{Digest, Sha256};
+
+use ton_types::{error, fail, ByteOrderRead, Cell, CellData, CellType, Result, MAX_REFERENCES_COUNT};
 use ton_types::UInt256;
 
-use crate::db_impl_base;
-use crate::db::traits::{KvcTransaction, KvcTransactional};
+use crate::db::memorydb::MemoryDb;
+use crate::db::rocksdb::RocksDb;
+use crate::db::rocksdb_options::RocksDbOptions;
+use crate::db::traits::{DbKey, KvcReadableTransaction, KvcTransactional};
 use crate::dynamic_boc_db::DynamicBocDb;
+use crate::error::StorageError;
 use crate::types::{CellId, Reference, StorageCell};
 
-db_impl_base!(CellDb, KvcTransactional, CellId);
+#[derive(Debug)]
+pub struct CellDb {
+    db: Box<dyn KvcTransactional<CellId> + Send + Sync>,
+}
+
+/// Reference count of new cells and of cells stored before reference counting was introduced
+const DEFAULT_REFCOUNT: u32 = 1;
+
+/// Current format version of stored cell records
+pub const CELL_FORMAT_VERSION: u8 = VERSION_CHECKSUMMED;
+
+/// Records of legacy format have no version marker and start with serialized cell data
+const VERSION_LEGACY: u8 = 0;
+/// Records of this version end with a checksum
+const VERSION_CHECKSUMMED: u8 = 1;
+/// Versioned record starts with this value plus version. It is out of range of cell type
+/// tags, which are the first byte of legacy records
+const VERSION_MARKER_BASE: u8 = 0xC0;
+/// Count of records rewritten in one transaction during migration
+const MIGRATION_BATCH_SIZE: usize = 10000;
+/// Length of truncated SHA-256 checksum
+const CHECKSUM_LEN: usize = 4;
+
+/// Name of merge operator applying reference count deltas to cell records, see `CellDb::merge_ref()`
+pub const REFCOUNT_MERGE_OPERATOR: &str = "cell_refcount";
+/// Length of reference count delta, which is merge operand. Value of this length is deltas
+/// merged into absent record: cell records are always longer
+const DELTA_LEN: usize = 4;
+
+/// Stored cell record
+struct CellRecord {
+    cell_data: CellData,
+    references: Vec<Reference>,
+    refcount: u32,
+    /// Whether the reference count is stored in the record, not defaulted
+    counted: bool,
+}
+
+/// Human-readable description of stored cell record
+#[derive(Debug, Clone)]
+pub struct CellDescription {
+    cell_id: CellId,
+    raw: Vec<u8>,
+    cell_type: Option<CellType>,
+    bit_length: Option<usize>,
+    data: Option<Vec<u8>>,
+    references: Vec<UInt256>,
+    refcount: Option<u32>,
+    format_version: Option<u8>,
+    malformed: Option<String>,
+}
+
+impl CellDescription {
+    pub const fn cell_id(&self) -> &CellId {
+        &self.cell_id
+    }
+
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn cell_type(&self) -> Option<CellType> {
+        self.cell_type.clone()
+    }
+
+    pub const fn bit_length(&self) -> Option<usize> {
+        self.bit_length
+    }
+
+    pub fn data(&self) -> Option<&[u8]> {
+        self.data.as_deref()
+    }
+
+    pub fn references(&self) -> &[UInt256] {
+        &self.references
+    }
+
+    pub const fn refcount(&self) -> Option<u32> {
+        self.refcount
+    }
+
+    pub const fn format_version(&self) -> Option<u8> {
+        self.format_version
+    }
+
+    /// Returns description of a problem in the record, if it is malformed
+    pub fn malformed(&self) -> Option<&str> {
+        self.malformed.as_deref()
+    }
+}
+
+impl Display for CellDescription {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "cell {}", self.cell_id)?;
+        writeln!(f, "  raw: {} bytes", self.raw.len())?;
+        if let Some(ref cell_type) = self.cell_type {
+            writeln!(f, "  type: {}", cell_type)?;
+        }
+        if let Some(bit_length) = self.bit_length {
+            writeln!(f, "  bits: {}", bit_length)?;
+        }
+        if let Some(ref data) = self.data {
+            writeln!(f, "  data: {}", hex::encode(data))?;
+        }
+        for (i, hash) in self.references.iter().enumerate() {
+            writeln!(f, "  ref #{}: {}", i, hash.to_hex_string())?;
+        }
+        if let Some(refcount) = self.refcount {
+            writeln!(f, "  refcount: {}", refcount)?;
+        }
+        if let Some(format_version) = self.format_version {
+            writeln!(f, "  format version: {}", format_version)?;
+        }
+        if let Some(ref malformed) = self.malformed {
+            writeln!(f, "  malformed: {}", malformed)?;
+        }
+
+        Ok(())
+    }
+}
 
 impl CellDb {
+    /// Constructs new instance using in-memory key-value collection.
+    /// It has no merge operator, so merge_ref() is not supported
+    pub fn in_memory() -> Self {
+        Self {
+            db: Box::new(MemoryDb::new())
+        }
+    }
+
+    /// Constructs new instance using RocksDB with given path and reference count merge operator
+    pub fn with_path<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_db_options(path, RocksDbOptions::new())
+    }
+
+    /// Constructs new instance using RocksDB with given path and options,
+    /// adding reference count merge operator to them
+    pub fn with_db_options<P: AsRef<Path>>(path: P, db_options: RocksDbOptions) -> Self {
+        let db_options = db_options.set_merge_operator(
+            REFCOUNT_MERGE_OPERATOR,
+            Self::refcount_full_merge,
+            Self::refcount_partial_merge,
+        );
+        Self {
+            db: Box::new(RocksDb::with_db_options(path, &db_options))
+        }
+    }
+
     /// Gets cell from key-value storage by cell id
     pub fn get_cell(&self, cell_id: &CellId, boc_db: Arc<DynamicBocDb>) -> Result<StorageCell> {
-        let (cell_data, references) = Self::deserialize_cell(self.db.get(&cell_id)?.as_ref())?;
+        let (cell_data, references) = Self::deserialize_cell(cell_id, self.db.get(&cell_id)?.as_ref())?;
         Ok(StorageCell::with_params(cell_data, references, boc_db))
     }
 
-    /// Puts cell into transaction
-    pub fn put_cell<T: KvcTransaction<CellId> + ?Sized>(transaction: &T, cell_id: &CellId, cell: Cell) -> Result<()> {
-        transaction.put(cell_id, &Self::serialize_cell(cell)?);
+    /// Gets cells by their ids in one batch, in input order
+    pub fn get_cells(&self, cell_ids: &[CellId], boc_db: Arc<DynamicBocDb>) -> Result<Vec<StorageCell>> {
+        let mut cells = Vec::with_capacity(cell_ids.len());
+        for (cell_id, data) in cell_ids.iter().zip(self.db.get_multi(cell_ids)?) {
+            let data = data.ok_or_else(|| StorageError::KeyNotFound(cell_id.key_name(), cell_id.as_string()))?;
+            let (cell_data, references) = Self::deserialize_cell(cell_id, data.as_ref())?;
+            cells.push(StorageCell::with_params(cell_data, references, Arc::clone(&boc_db)));
+        }
+        Ok(cells)
+    }
+
+    /// Determines presence of each of given cells in one batch, in input order.
+    /// Orphan delta is not a stored cell, so it is reported as absent, as put_cell_referenced() treats it
+    pub fn contains_batch(&self, cell_ids: &[CellId]) -> Result<Vec<bool>> {
+        Ok(self.db.get_multi(cell_ids)?.iter()
+            .map(|data| data.as_ref().map_or(false, |data| !Self::is_orphan_delta(data.as_ref())))
+            .collect())
+    }
+
+    /// Describes stored cell record without loading its references.
+    /// Malformed record is reported in the description instead of failing
+    pub fn describe_cell(&self, cell_id: &CellId) -> Result<CellDescription> {
+        let raw = self.db.get(cell_id)?.into_vec();
+        let mut description = CellDescription {
+            cell_id: cell_id.clone(),
+            raw,
+            cell_type: None,
+            bit_length: None,
+            data: None,
+            references: Vec::new(),
+            refcount: None,
+            format_version: None,
+            malformed: None,
+        };
+        if let Err(err) = Self::describe_record(&mut description) {
+            description.malformed = Some(err.to_string());
+        }
+
+        Ok(description)
+    }
+
+    fn describe_record(description: &mut CellDescription) -> Result<()> {
+        let (version, payload) = Self::record_payload(&description.cell_id, &description.raw)?;
+        description.format_version = Some(version);
+
+        let mut reader = Cursor::new(payload);
+        let cell_data = CellData::deserialize(&mut reader)?;
+        description.cell_type = Some(cell_data.cell_type());
+        description.bit_length = Some(cell_data.bit_length());
+        description.data = Some(cell_data.data().to_vec());
+
+        let references_count = reader.read_byte()?;
+        if references_count as usize > MAX_REFERENCES_COUNT {
+            Err(StorageError::TooManyReferences(references_count as usize))?;
+        }
+        for _ in 0..references_count {
+            description.references.push(UInt256::from(reader.read_u256()?));
+        }
+        description.refcount = Self::read_refcount(&description.cell_id, &mut reader, payload.len())?;
+        if reader.position() != payload.len() as u64 {
+            fail!("{} trailing bytes", payload.len() as u64 - reader.position());
+        }
+
         Ok(())
     }
 
-    /// Binary serialization of cell data
-    fn serialize_cell(cell: Cell) -> Result<Vec<u8>> {
-        let references_count = cell.references_count() as u8;
+    /// Increments reference count of stored cell, putting updated record into transaction.
+    /// The record is read through the transaction, so pending changes of the cell are kept.
+    /// Returns new reference count
+    pub fn inc_ref<T: KvcReadableTransaction<CellId> + ?Sized>(&self, transaction: &T, cell_id: &CellId) -> Result<u32> {
+        Self::update_refcount(transaction, cell_id, |refcount| {
+            refcount.checked_add(1)
+                .ok_or_else(|| error!("Reference count of cell {} overflowed", cell_id))
+        })
+    }
+
+    /// Decrements reference count of stored cell, putting updated record into transaction.
+    /// Returns new reference count; cells with zero count are deleted by gc()
+    pub fn dec_ref<T: KvcReadableTransaction<CellId> + ?Sized>(&self, transaction: &T, cell_id: &CellId) -> Result<u32> {
+        Self::update_refcount(transaction, cell_id, |refcount| {
+            refcount.checked_sub(1)
+                .ok_or_else(|| error!("Reference count of cell {} is already zero", cell_id))
+        })
+    }
 
-        assert!(references_count as usize <= MAX_REFERENCES_COUNT);
+    /// Deletes stored cell, only if its reference count, read through the transaction, equals
+    /// `expected`, and decrements reference counts of its children. Returns whether the cell
+    /// is deleted. With optimistic transaction a concurrent change of the count makes commit fail,
+    /// so the cell is never deleted after being referenced again
+    pub fn delete_if_refcount<T: KvcReadableTransaction<CellId> + ?Sized>(
+        &self,
+        transaction: &T,
+        cell_id: &CellId,
+        expected: u32,
+    ) -> Result<bool> {
+        let record = match transaction.try_get(cell_id)? {
+            Some(data) if !Self::is_orphan_delta(data.as_ref()) => Self::deserialize_record(cell_id, data.as_ref())?,
+            _ => return Ok(false),
+        };
+        if record.refcount != expected {
+            return Ok(false);
+        }
 
-        let mut data = Vec::new();
+        transaction.delete(cell_id);
+        for reference in record.references {
+            let child_id = CellId::from(reference.hash());
+            if let Some(data) = transaction.try_get(&child_id)?.filter(|data| !Self::is_orphan_delta(data.as_ref())) {
+                Self::write_refcount(transaction, &child_id, data.as_ref(), |refcount| Ok(refcount.saturating_sub(1)))?;
+            }
+        }
 
-        cell.cell_data().serialize(&mut data)?;
-        data.write(&[references_count])?;
+        Ok(true)
+    }
 
+    /// Adds delta to reference count of stored cell by merge, without reading the record.
+    /// Requires database opened with merge operator `REFCOUNT_MERGE_OPERATOR` made of
+    /// `refcount_full_merge()` and `refcount_partial_merge()`, as `with_path()` does
+    pub fn merge_ref(&self, cell_id: &CellId, delta: i32) -> Result<()> {
+        self.db.merge(cell_id, &delta.to_le_bytes())
+    }
+
+    /// Full merge of reference count deltas: applies them to stored record, keeping its format
+    /// version. It never fails, since RocksDB treats failed merge as corruption: count out of
+    /// range is clamped, invalid operands and malformed record are left as is, with error logged.
+    /// Deltas merged into absent record are kept summed up as the value, which is treated
+    /// as absent cell and removed by gc()
+    pub fn refcount_full_merge(key: &[u8], existing: Option<&[u8]>, operands: &mut MergeOperands) -> Option<Vec<u8>> {
+        let delta = Self::sum_deltas(key, operands);
+        let existing = match existing {
+            Some(existing) if !Self::is_orphan_delta(existing) => existing,
+            orphan => {
+                log::error!(target: "storage", "Reference count of absent cell {} is merged", hex::encode(key));
+                let delta = delta + orphan.map_or(0, |orphan| Self::sum_deltas(key, Some(orphan)));
+                return Some(Self::clamp_delta(key, delta).to_le_bytes().to_vec());
+            }
+        };
+
+        let result = CellId::try_from(key).and_then(|cell_id| {
+            let version = Self::record_payload(&cell_id, existing)?.0;
+            let mut record = Self::deserialize_record(&cell_id, existing)?;
+            let refcount = record.refcount as i64 + delta;
+            record.refcount = u32::try_from(refcount).unwrap_or_else(|_| {
+                log::error!(target: "storage", "Reference count of cell {} is out of range: {}", cell_id, refcount);
+                if refcount < 0 { 0 } else { u32::MAX }
+            });
+            Self::serialize_record_in(version, &record.cell_data, &record.references, record.refcount)
+        });
+        match result {
+            Ok(record) => Some(record),
+            Err(err) => {
+                log::error!(target: "storage", "Cannot merge reference count of {}, record is kept: {}", hex::encode(key), err);
+                Some(existing.to_vec())
+            }
+        }
+    }
+
+    /// Partial merge of reference count deltas: sums them up into single delta
+    pub fn refcount_partial_merge(key: &[u8], _existing: Option<&[u8]>, operands: &mut MergeOperands) -> Option<Vec<u8>> {
+        let delta = Self::sum_deltas(key, operands);
+        Some(Self::clamp_delta(key, delta).to_le_bytes().to_vec())
+    }
+
+    /// Sums up reference count deltas, skipping invalid ones
+    fn sum_deltas<'a>(key: &[u8], operands: impl IntoIterator<Item = &'a [u8]>) -> i64 {
+        let mut sum = 0i64;
+        for operand in operands {
+            match <[u8; DELTA_LEN]>::try_from(operand) {
+                Ok(delta) => sum += i32::from_le_bytes(delta) as i64,
+                Err(_) => log::error!(
+                    target: "storage",
+                    "Invalid reference count delta of {} bytes for {} is skipped", operand.len(), hex::encode(key)
+                ),
+            }
+        }
+        sum
+    }
+
+    fn clamp_delta(key: &[u8], delta: i64) -> i32 {
+        i32::try_from(delta).unwrap_or_else(|_| {
+            log::error!(target: "storage", "Reference count delta for {} is out of range: {}", hex::encode(key), delta);
+            if delta < 0 { i32::MIN } else { i32::MAX }
+        })
+    }
+
+    /// Determines if stored value is reference count deltas merged into absent record
+    pub(crate) fn is_orphan_delta(data: &[u8]) -> bool {
+        data.len() == DELTA_LEN
+    }
+
+    /// Deletes cells with zero reference count. Deleting a cell decrements reference counts of
+    /// its children, so they are deleted as well, if not referenced anymore.
+    /// Must not run concurrently with other writers of reference counts.
+    /// Returns count of deleted cells
+    pub fn gc(&self) -> Result<usize> {
+        let mut to_delete = Vec::new();
+        let mut orphans = Vec::new();
+        self.db.for_each(&mut |key, value| {
+            let cell_id = CellId::try_from(key)?;
+            if Self::is_orphan_delta(value) {
+                orphans.push(cell_id);
+            } else if Self::deserialize_record(&cell_id, value)?.refcount == 0 {
+                to_delete.push(cell_id);
+            }
+            Ok(true)
+        })?;
+
+        let transaction = self.db.begin_transaction()?;
+        for cell_id in orphans.iter() {
+            transaction.delete(cell_id);
+        }
+        let mut deleted: FnvHashSet<_> = orphans.into_iter().collect();
+        // Cells with decremented reference count
+        let mut updated: FnvHashMap<CellId, CellRecord> = FnvHashMap::default();
+        while let Some(cell_id) = to_delete.pop() {
+            if deleted.contains(&cell_id) {
+                continue;
+            }
+            let references = match self.db.try_get(&cell_id)? {
+                Some(data) if !Self::is_orphan_delta(data.as_ref()) => {
+                    Self::deserialize_cell(&cell_id, data.as_ref())?.1
+                }
+                _ => continue,
+            };
+            transaction.delete(&cell_id);
+            updated.remove(&cell_id);
+            deleted.insert(cell_id);
+
+            for reference in references {
+                let child_id = CellId::from(reference.hash());
+                if deleted.contains(&child_id) {
+                    continue;
+                }
+                let child = match updated.entry(child_id.clone()) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let data = match self.db.try_get(&child_id)? {
+                            Some(data) if !Self::is_orphan_delta(data.as_ref()) => data,
+                            _ => continue,
+                        };
+                        entry.insert(Self::deserialize_record(&child_id, data.as_ref())?)
+                    }
+                };
+                child.refcount = child.refcount.saturating_sub(1);
+                if child.refcount == 0 {
+                    to_delete.push(child_id);
+                }
+            }
+        }
+        for (cell_id, record) in updated {
+            transaction.put(&cell_id, &Self::serialize_record(&record.cell_data, &record.references, record.refcount)?);
+        }
+        transaction.commit()?;
+
+        Ok(deleted.len())
+    }
+
+    fn update_refcount<T: KvcReadableTransaction<CellId> + ?Sized>(
+        transaction: &T,
+        cell_id: &CellId,
+        update: impl FnOnce(u32) -> Result<u32>,
+    ) -> Result<u32> {
+        let data = transaction.get(cell_id)?;
+        Self::write_refcount(transaction, cell_id, data.as_ref(), update)
+    }
+
+    /// Puts given record with updated reference count into transaction
+    fn write_refcount<T: KvcReadableTransaction<CellId> + ?Sized>(
+        transaction: &T,
+        cell_id: &CellId,
+        data: &[u8],
+        update: impl FnOnce(u32) -> Result<u32>,
+    ) -> Result<u32> {
+        let mut record = Self::deserialize_record(cell_id, data)?;
+        record.refcount = update(record.refcount)?;
+        transaction.put(cell_id, &Self::serialize_record(&record.cell_data, &record.references, record.refcount)?);
+
+        Ok(record.refcount)
+    }
+
+    /// Rewrites every stored record, which is not in given format version yet.
+    /// Records are rewritten in batches, each under its own transaction, so interrupted
+    /// migration can be safely restarted. Returns count of rewritten records
+    pub fn migrate_to(&self, version: u8) -> Result<usize> {
+        if version > CELL_FORMAT_VERSION {
+            fail!("Unsupported cell format version {}", version);
+        }
+
+        let mut to_migrate = Vec::new();
+        self.db.for_each(&mut |key, value| {
+            let cell_id = CellId::try_from(key)?;
+            if !Self::is_orphan_delta(value) && Self::record_payload(&cell_id, value)?.0 != version {
+                to_migrate.push(cell_id);
+            }
+            Ok(true)
+        })?;
+
+        for batch in to_migrate.chunks(MIGRATION_BATCH_SIZE) {
+            let transaction = self.db.begin_transaction()?;
+            for cell_id in batch {
+                let record = Self::deserialize_record(cell_id, self.db.get(cell_id)?.as_ref())?;
+                transaction.put(
+                    cell_id,
+                    &Self::serialize_record_in(version, &record.cell_data, &record.references, record.refcount)?
+                );
+            }
+            transaction.commit()?;
+        }
+        log::debug!(target: "storage", "Migrated {} cell records to format version {}", to_migrate.len(), version);
+
+        Ok(to_migrate.len())
+    }
+
+    /// Sets reference counts of records stored before reference counting was introduced:
+    /// each gets count of stored cells referencing it, plus one if it is among given roots
+    /// (or if nothing references it). Keeps the count of parents of every cell in memory.
+    /// Records are rewritten in batches, so interrupted migration can be safely restarted.
+    /// Returns count of rewritten records
+    pub fn migrate_refcounts(&self, roots: &[CellId]) -> Result<usize> {
+        let mut parents: FnvHashMap<CellId, u32> = FnvHashMap::default();
+        let mut to_migrate = Vec::new();
+        self.db.for_each(&mut |key, value| {
+            if Self::is_orphan_delta(value) {
+                return Ok(true);
+            }
+            let cell_id = CellId::try_from(key)?;
+            let record = Self::deserialize_record(&cell_id, value)?;
+            for reference in record.references {
+                *parents.entry(CellId::from(reference.hash())).or_insert(0) += 1;
+            }
+            if !record.counted {
+                to_migrate.push(cell_id);
+            }
+            Ok(true)
+        })?;
+        let roots: FnvHashSet<_> = roots.iter().collect();
+
+        for batch in to_migrate.chunks(MIGRATION_BATCH_SIZE) {
+            let transaction = self.db.begin_transaction()?;
+            for cell_id in batch {
+                let data = self.db.get(cell_id)?;
+                let version = Self::record_payload(cell_id, data.as_ref())?.0;
+                let record = Self::deserialize_record(cell_id, data.as_ref())?;
+                let refcount = match parents.get(cell_id) {
+                    Some(&count) if roots.contains(&cell_id) => count.saturating_add(1),
+                    Some(&count) => count,
+                    None => DEFAULT_REFCOUNT,
+                };
+                transaction.put(
+                    cell_id,
+                    &Self::serialize_record_in(version, &record.cell_data, &record.references, refcount)?
+                );
+            }
+            transaction.commit()?;
+        }
+        log::debug!(target: "storage", "Set reference counts of {} cell records", to_migrate.len());
+
+        Ok(to_migrate.len())
+    }
+
+    /// Puts cell into transaction as referenced once, see put_cell_referenced()
+    pub fn put_cell<T: KvcReadableTransaction<CellId> + ?Sized>(
+        transaction: &T,
+        cell_id: &CellId,
+        cell: Cell,
+    ) -> Result<bool> {
+        Self::put_cell_referenced(transaction, cell_id, cell, 1)
+    }
+
+    /// Puts cell into transaction as referenced `refs` more times. Cell stored already
+    /// (as seen through the transaction) gets its reference count increased, new cell is
+    /// written with count `refs`. References of a new cell to its children are not counted
+    /// here, so the children must be put as well. Returns true, if the cell is new
+    pub fn put_cell_referenced<T: KvcReadableTransaction<CellId> + ?Sized>(
+        transaction: &T,
+        cell_id: &CellId,
+        cell: Cell,
+        refs: u32,
+    ) -> Result<bool> {
+        if let Some(data) = transaction.try_get(cell_id)?.filter(|data| !Self::is_orphan_delta(data.as_ref())) {
+            Self::write_refcount(transaction, cell_id, data.as_ref(), |refcount| {
+                refcount.checked_add(refs)
+                    .ok_or_else(|| error!("Reference count of cell {} overflowed", cell_id))
+            })?;
+            return Ok(false);
+        }
+        transaction.put(cell_id, &Self::serialize_cell(cell, refs)?);
+
+        Ok(true)
+    }
+
+    /// Puts all given cells into transaction, each item being one reference to its cell
+    /// (see put_cell()). Each unique cell is serialized once. Returns count of new cells
+    pub fn put_cells<T, I>(transaction: &T, cells: I) -> Result<usize>
+    where
+        T: KvcReadableTransaction<CellId> + ?Sized,
+        I: IntoIterator<Item = (CellId, Cell)>,
+    {
+        let mut refs: FnvHashMap<CellId, (Cell, u32)> = FnvHashMap::default();
+        for (cell_id, cell) in cells {
+            refs.entry(cell_id).or_insert((cell, 0)).1 += 1;
+        }
+
+        let mut written_count = 0;
+        for (cell_id, (cell, refs)) in refs {
+            if Self::put_cell_referenced(transaction, &cell_id, cell, refs)? {
+                written_count += 1;
+            }
+        }
+
+        Ok(written_count)
+    }
+
+    /// Binary serialization of cell data with given reference count
+    pub(crate) fn serialize_cell(cell: Cell, refcount: u32) -> Result<Vec<u8>> {
+        let references_count = cell.references_count();
+        Self::check_cell_kind(cell.cell_type(), references_count)?;
+
+        let mut references = Vec::with_capacity(references_count);
         for i in 0..references_count {
-            data.write(cell.reference(i as usize)?.repr_hash().as_slice())?;
+            references.push(Reference::NeedToLoad(cell.reference(i)?.repr_hash()));
+        }
+
+        Self::serialize_record(cell.cell_data(), &references, refcount)
+    }
+
+    /// Serializes record in current format version
+    fn serialize_record(cell_data: &CellData, references: &[Reference], refcount: u32) -> Result<Vec<u8>> {
+        Self::serialize_record_in(CELL_FORMAT_VERSION, cell_data, references, refcount)
+    }
+
+    /// Serializes record in given format version. Version 0 is cell data, references and
+    /// reference count; version 1 is prefixed with marker and followed by checksum
+    fn serialize_record_in(
+        version: u8,
+        cell_data: &CellData,
+        references: &[Reference],
+        refcount: u32,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if version != VERSION_LEGACY {
+            data.push(VERSION_MARKER_BASE + version);
+        }
+
+        cell_data.serialize(&mut data)?;
+        data.write(&[references.len() as u8])?;
+        for reference in references {
+            data.write(reference.hash().as_slice())?;
         }
+        data.write(&refcount.to_le_bytes())?;
 
-        assert!(!data.is_empty());
+        match version {
+            VERSION_LEGACY => (),
+            VERSION_CHECKSUMMED => {
+                let checksum = Self::checksum(&data);
+                data.write(&checksum)?;
+            }
+            _ => fail!("Unsupported cell format version {}", version),
+        }
 
         Ok(data)
     }
 
+    /// Determines format version of record and returns it with the payload:
+    /// record without version marker and verified checksum
+    fn record_payload<'a>(cell_id: &CellId, data: &'a [u8]) -> Result<(u8, &'a [u8])> {
+        let version = match data.first() {
+            None => Err(StorageError::EmptyCellData { id: cell_id.clone() })?,
+            Some(&marker) if marker > VERSION_MARKER_BASE && marker <= VERSION_MARKER_BASE + CELL_FORMAT_VERSION => {
+                marker - VERSION_MARKER_BASE
+            }
+            Some(_) => VERSION_LEGACY,
+        };
+        match version {
+            VERSION_LEGACY => Ok((version, data)),
+            _ => {
+                Self::verify_checksum(cell_id, data)?;
+                Ok((version, &data[1..data.len() - CHECKSUM_LEN]))
+            }
+        }
+    }
+
+    fn checksum(data: &[u8]) -> [u8; CHECKSUM_LEN] {
+        let mut hasher = Sha256::new();
+        hasher.input(data);
+        let mut checksum = [0; CHECKSUM_LEN];
+        checksum.copy_from_slice(&hasher.result()[..CHECKSUM_LEN]);
+
+        checksum
+    }
+
+    /// Checks trailing checksum of versioned record
+    fn verify_checksum(cell_id: &CellId, data: &[u8]) -> Result<()> {
+        if data.len() <= CHECKSUM_LEN
+            || data[data.len() - CHECKSUM_LEN..] != Self::checksum(&data[..data.len() - CHECKSUM_LEN])
+        {
+            Err(StorageError::CellChecksumMismatch { id: cell_id.clone() })?;
+        }
+
+        Ok(())
+    }
+
+    /// Deserializes stored record of any supported format version
+    fn deserialize_record(cell_id: &CellId, data: &[u8]) -> Result<CellRecord> {
+        let payload = Self::record_payload(cell_id, data)?.1;
+        let mut reader = Cursor::new(payload);
+        let (cell_data, references) = Self::read_cell(cell_id, &mut reader)?;
+        let refcount = Self::read_refcount(cell_id, &mut reader, payload.len())?;
+
+        Ok(CellRecord {
+            cell_data,
+            references,
+            refcount: refcount.unwrap_or(DEFAULT_REFCOUNT),
+            counted: refcount.is_some(),
+        })
+    }
+
+    /// Reads reference count, if stored. Records without it are treated as referenced once
+    /// until migrate_refcounts() sets their counts
+    fn read_refcount(cell_id: &CellId, reader: &mut Cursor<&[u8]>, len: usize) -> Result<Option<u32>> {
+        if reader.position() < len as u64 {
+            Ok(Some(reader.read_le_u32().map_err(|err| Self::corrupted(cell_id, err))?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads reference count from stored record
+    pub(crate) fn record_refcount(cell_id: &CellId, data: &[u8]) -> Result<u32> {
+        Ok(Self::deserialize_record(cell_id, data)?.refcount)
+    }
+
     /// Binary deserialization of cell data
-    pub(crate) fn deserialize_cell(data: &[u8]) -> Result<(CellData, Vec<Reference>)> {
-        assert!(!data.is_empty());
+    pub(crate) fn deserialize_cell(cell_id: &CellId, data: &[u8]) -> Result<(CellData, Vec<Reference>)> {
+        let record = Self::deserialize_record(cell_id, data)?;
 
-        let mut reader = Cursor::new(data);
-        let cell_data = CellData::deserialize(&mut reader)?;
-        let references_count = reader.read_byte()?;
+        Ok((record.cell_data, record.references))
+    }
+
+    /// Checks reference count against cell type. Cell type and level mask are stored
+    /// within serialized cell data, so special cells keep their kind after loading
+    fn check_cell_kind(cell_type: CellType, references_count: usize) -> Result<()> {
+        if references_count > MAX_REFERENCES_COUNT {
+            Err(StorageError::TooManyReferences(references_count))?;
+        }
+        let valid = match cell_type {
+            CellType::Ordinary => true,
+            CellType::PrunedBranch | CellType::LibraryReference => references_count == 0,
+            CellType::MerkleProof => references_count == 1,
+            CellType::MerkleUpdate => references_count == 2,
+            _ => false,
+        };
+        if !valid {
+            Err(StorageError::InvalidCellReferences(format!("{:?}", cell_type), references_count))?;
+        }
+
+        Ok(())
+    }
+
+    fn read_cell(cell_id: &CellId, reader: &mut Cursor<&[u8]>) -> Result<(CellData, Vec<Reference>)> {
+        let cell_data = CellData::deserialize(reader)
+            .map_err(|err| Self::corrupted(cell_id, err))?;
+        let references_count = reader.read_byte()
+            .map_err(|err| Self::corrupted(cell_id, err))?;
+        Self::check_cell_kind(cell_data.cell_type(), references_count as usize)?;
         let mut references = Vec::with_capacity(references_count as usize);
         for _ in 0..references_count {
-            let hash = UInt256::from(reader.read_u256()?);
+            let hash = UInt256::from(reader.read_u256().map_err(|err| Self::corrupted(cell_id, err))?);
             references.push(Reference::NeedToLoad(hash));
         }
 
         Ok((cell_data, references))
     }
+
+    /// Wraps failure of reading stored record into corruption error
+    fn corrupted(cell_id: &CellId, err: impl Display) -> failure::Error {
+        StorageError::CorruptedCell { id: cell_id.clone(), reason: err.to_string() }.into()
+    }
+}
+
+impl Deref for CellDb {
+    type Target = dyn KvcTransactional<CellId> + Send + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        self.db.deref()
+    }
+}
+
+impl DerefMut for CellDb {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.db.deref_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ton_types::BuilderData;
+    use crate::db::traits::KvcWriteable;
+
+    fn library_cell() -> Cell {
+        let mut builder = BuilderData::new();
+        builder.set_type(CellType::LibraryReference);
+        builder.append_u8(u8::from(CellType::LibraryReference)).unwrap();
+        builder.append_raw(&[0x11; 32], 256).unwrap();
+        builder.into_cell().unwrap()
+    }
+
+    #[test]
+    fn test_special_cell_with_references_is_rejected_on_loading() -> Result<()> {
+        let cell = library_cell();
+        let cell_id = CellId::new(cell.repr_hash());
+        let (cell_data, references) = CellDb::deserialize_cell(&cell_id, &CellDb::serialize_cell(cell.clone(), 1)?)?;
+        assert_eq!(cell_data.cell_type(), CellType::LibraryReference);
+        assert!(references.is_empty());
+
+        let references = vec![Reference::NeedToLoad(UInt256::from([0x22; 32]))];
+        for &version in &[VERSION_LEGACY, VERSION_CHECKSUMMED] {
+            let data = CellDb::serialize_record_in(version, cell.cell_data(), &references, 1)?;
+            let err = CellDb::deserialize_cell(&cell_id, &data).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<StorageError>(),
+                Some(&StorageError::InvalidCellReferences(format!("{:?}", CellType::LibraryReference), 1))
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_tree_detects_reference_cycle() -> Result<()> {
+        // Records reference each other by ids unrelated to their contents, which can't happen
+        // for hashes of real cells, so they are written as is
+        let first = CellId::new(UInt256::from([0x01; 32]));
+        let second = CellId::new(UInt256::from([0x02; 32]));
+        let mut builder = BuilderData::new();
+        builder.append_u32(1)?;
+        let cell = builder.into_cell()?;
+        let db = DynamicBocDb::with_db(CellDb::in_memory());
+        for (cell_id, reference) in &[(&first, &second), (&second, &first)] {
+            let references = vec![Reference::NeedToLoad(reference.as_hash().clone())];
+            db.cell_db().put(cell_id, &CellDb::serialize_record(cell.cell_data(), &references, 1)?)?;
+        }
+
+        let report = db.check_tree(&first)?;
+        assert_eq!(report.cells(), 2);
+        assert_eq!(report.cycles(), &[first]);
+        assert!(report.dangling().is_empty());
+        assert!(!report.is_complete());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_too_many_references_are_rejected_on_loading() -> Result<()> {
+        let cell = library_cell();
+        let cell_id = CellId::new(cell.repr_hash());
+        let references = vec![Reference::NeedToLoad(UInt256::from([0x22; 32])); MAX_REFERENCES_COUNT + 1];
+        let data = CellDb::serialize_record_in(VERSION_CHECKSUMMED, cell.cell_data(), &references, 1)?;
+        let err = CellDb::deserialize_cell(&cell_id, &data).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StorageError>(),
+            Some(&StorageError::TooManyReferences(MAX_REFERENCES_COUNT + 1))
+        );
+
+        Ok(())
+    }
 }
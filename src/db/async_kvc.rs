@@ -0,0 +1,137 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use ton_types::{error, Result};
+
+use crate::db::traits::{DbKey, KvcTransactional};
+
+/// Async facade over blocking key-value collection: every operation runs on the blocking
+/// thread pool of Tokio runtime, so awaiting it doesn't stall the runtime worker threads.
+/// Spawned tasks own a clone of the collection, keys and values
+#[derive(Debug)]
+pub struct AsyncKvc<K: DbKey + Send + Sync + 'static, T: KvcTransactional<K> + 'static> {
+    kvc: Arc<T>,
+    phantom: std::marker::PhantomData<fn(K)>,
+}
+
+impl<K: DbKey + Send + Sync + 'static, T: KvcTransactional<K> + 'static> Clone for AsyncKvc<K, T> {
+    fn clone(&self) -> Self {
+        Self::with_arc(Arc::clone(&self.kvc))
+    }
+}
+
+impl<K: DbKey + Send + Sync + 'static, T: KvcTransactional<K> + 'static> AsyncKvc<K, T> {
+    pub fn new(kvc: T) -> Self {
+        Self::with_arc(Arc::new(kvc))
+    }
+
+    pub fn with_arc(kvc: Arc<T>) -> Self {
+        Self { kvc, phantom: std::marker::PhantomData }
+    }
+
+    pub fn kvc(&self) -> &Arc<T> {
+        &self.kvc
+    }
+
+    /// Tries to get value from collection by the key; returns Ok(None) if the key not found
+    pub async fn try_get(&self, key: K) -> Result<Option<Vec<u8>>> {
+        self.run(move |kvc| Ok(kvc.try_get(&key)?.map(|value| value.into_vec()))).await
+    }
+
+    /// Gets value from collection by the key
+    pub async fn get(&self, key: K) -> Result<Vec<u8>> {
+        self.run(move |kvc| Ok(kvc.get(&key)?.into_vec())).await
+    }
+
+    /// Determines, is key exists in key-value collection
+    pub async fn contains(&self, key: K) -> Result<bool> {
+        self.run(move |kvc| kvc.contains(&key)).await
+    }
+
+    /// Puts value into collection by the key
+    pub async fn put(&self, key: K, value: Vec<u8>) -> Result<()> {
+        self.run(move |kvc| kvc.put(&key, &value)).await
+    }
+
+    /// Deletes value from collection by the key
+    pub async fn delete(&self, key: K) -> Result<()> {
+        self.run(move |kvc| kvc.delete(&key)).await
+    }
+
+    /// Creates new transaction. Operations are collected in memory and passed
+    /// to the collection's transaction on commit
+    pub fn begin_transaction(&self) -> AsyncKvcTransaction<K, T> {
+        AsyncKvcTransaction {
+            kvc: self.clone(),
+            pending: Vec::new(),
+        }
+    }
+
+    async fn run<R: Send + 'static>(&self, f: impl FnOnce(&T) -> Result<R> + Send + 'static) -> Result<R> {
+        let kvc = Arc::clone(&self.kvc);
+        tokio::task::spawn_blocking(move || f(&kvc)).await
+            .map_err(|err| error!("Blocking storage task failed: {}", err))?
+    }
+}
+
+/// Transaction of `AsyncKvc`; dropping it without commit discards pending operations
+#[derive(Debug)]
+pub struct AsyncKvcTransaction<K: DbKey + Send + Sync + 'static, T: KvcTransactional<K> + 'static> {
+    kvc: AsyncKvc<K, T>,
+    pending: Vec<(K, Option<Vec<u8>>)>,
+}
+
+impl<K: DbKey + Send + Sync + 'static, T: KvcTransactional<K> + 'static> AsyncKvcTransaction<K, T> {
+    /// Adds put operation into transaction
+    pub fn put(&mut self, key: K, value: Vec<u8>) {
+        self.pending.push((key, Some(value)));
+    }
+
+    /// Adds delete operation into transaction
+    pub fn delete(&mut self, key: K) {
+        self.pending.push((key, None));
+    }
+
+    /// Removes all pending operations from transaction
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Gets pending operations count
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns true if pending operation count is zero; otherwise false
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Commits the transaction
+    pub async fn commit(self) -> Result<()> {
+        self.commit_with_sync(false).await
+    }
+
+    /// Commits the transaction, waiting for its data being synced to disk
+    pub async fn commit_sync(self) -> Result<()> {
+        self.commit_with_sync(true).await
+    }
+
+    async fn commit_with_sync(self, sync: bool) -> Result<()> {
+        let pending = self.pending;
+        self.kvc.run(move |kvc| {
+            let transaction = kvc.begin_transaction()?;
+            for (key, value) in &pending {
+                match value {
+                    Some(value) => transaction.put(key, value),
+                    None => transaction.delete(key),
+                }
+            }
+            if sync {
+                transaction.commit_sync()
+            } else {
+                transaction.commit()
+            }
+        }).await
+    }
+}
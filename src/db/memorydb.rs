@@ -1,34 +1,60 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-use fnv::FnvHashMap;
-
 use ton_types::Result;
 
-use crate::db::traits::{DbKey, Kvc, KvcReadable, KvcSnapshotable, KvcTransaction, KvcTransactional, KvcWriteable};
+use crate::db::traits::{
+    DbKey, Kvc, KvcOptimisticTransactional, KvcReadable, KvcReadableTransaction, KvcSnapshotable,
+    KvcTransaction, KvcTransactional, KvcWriteable
+};
 use crate::error::StorageError;
 use crate::types::DbSlice;
 
-/// In-memory key-value collection
+type Map = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// In-memory key-value collection; iterates over items in sorted key order, like RocksDB
 #[derive(Debug, Clone)]
 pub struct MemoryDb {
-    map: Arc<Option<Mutex<FnvHashMap<Vec<u8>, Vec<u8>>>>>
+    map: Arc<Option<Mutex<Map>>>
 }
 
 /// Implementation of in-memory key-value collection
 impl MemoryDb {
     /// Constructs empty collection
     pub fn new() -> Self {
-        Self::with_map(FnvHashMap::default())
+        Self::with_map(Map::new())
     }
 
-    fn with_map(map: FnvHashMap<Vec<u8>, Vec<u8>>) -> Self {
+    fn with_map(map: Map) -> Self {
         Self {
             map: Arc::new(Some(Mutex::new(map)))
         }
     }
 
-    fn map(&self) -> Result<&Mutex<FnvHashMap<Vec<u8>, Vec<u8>>>> {
+    /// Runs predicate for copies of items in given range, while keys satisfy the condition
+    fn for_each_in(
+        &self,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        condition: &mut dyn FnMut(&[u8]) -> bool,
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        let pairs: Vec<_> = self.map()?.lock().unwrap()
+            .range(range)
+            .take_while(|(key, _value)| condition(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        for (key, value) in pairs {
+            if !predicate(&key[..], &value[..])? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn map(&self) -> Result<&Mutex<Map>> {
         if let Some(ref map) = *self.map {
             Ok(map)
         } else {
@@ -79,17 +105,43 @@ impl<K: DbKey + Send + Sync> KvcReadable<K> for MemoryDb {
     }
 
     fn for_each(&self, predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<bool> {
-        let mut pairs = Vec::with_capacity(self.map()?.lock().unwrap().len());
-        for (key, value) in self.map()?.lock().unwrap().iter() {
-            pairs.push((key.clone(), value.clone()));
-        }
+        self.for_each_in((Bound::Unbounded, Bound::Unbounded), &mut |_| true, predicate)
+    }
 
-        for (key, value) in pairs {
-            if !predicate(&key[..], &value[..])? {
-                return Ok(false);
-            }
+    fn for_each_from(
+        &self,
+        start: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        self.for_each_in((Bound::Included(start.to_vec()), Bound::Unbounded), &mut |_| true, predicate)
+    }
+
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        self.for_each_in(
+            (Bound::Included(prefix.to_vec()), Bound::Unbounded),
+            &mut |key| key.starts_with(prefix),
+            predicate
+        )
+    }
+
+    fn for_each_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        if start >= end {
+            return Ok(true);
         }
-        Ok(true)
+        self.for_each_in(
+            (Bound::Included(start.to_vec()), Bound::Excluded(end.to_vec())),
+            &mut |_| true,
+            predicate
+        )
     }
 }
 
@@ -108,6 +160,17 @@ impl<K: DbKey + Send + Sync> KvcWriteable<K> for MemoryDb {
             .remove(key.key());
         Ok(())
     }
+
+    fn delete_range(&self, from: &[u8], to: &[u8]) -> Result<()> {
+        if from >= to {
+            return Ok(());
+        }
+        let mut map = self.map()?.lock().unwrap();
+        let mut tail = map.split_off(from);
+        let mut rest = tail.split_off(to);
+        map.append(&mut rest);
+        Ok(())
+    }
 }
 
 /// Implementation of support for take snapshots for MemoryDb.
@@ -138,13 +201,13 @@ enum PendingOperation {
 
 #[derive(Debug)]
 pub struct MemoryDbTransaction {
-    db_map: Arc<Option<Mutex<FnvHashMap<Vec<u8>, Vec<u8>>>>>,
+    db_map: Arc<Option<Mutex<Map>>>,
     pending: Mutex<Vec<PendingOperation>>,
 }
 
 /// Implementation of transaction for MemoryDb.
 impl MemoryDbTransaction {
-    fn new(db_map: Arc<Option<Mutex<FnvHashMap<Vec<u8>, Vec<u8>>>>>) -> Self {
+    fn new(db_map: Arc<Option<Mutex<Map>>>) -> Self {
         Self {
             db_map,
             pending: Mutex::new(Vec::new()),
@@ -196,3 +259,92 @@ impl<K: DbKey + Send + Sync> KvcTransaction<K> for MemoryDbTransaction {
         self.pending.lock().unwrap().is_empty()
     }
 }
+
+/// Implementation of optimistic transaction support for MemoryDb. Validation of read values
+/// and applying of pending operations are done under the single lock of the collection.
+impl<K: DbKey + Send + Sync> KvcOptimisticTransactional<K> for MemoryDb {
+    fn begin_optimistic_transaction(&self) -> Result<Box<dyn KvcReadableTransaction<K>>> {
+        Ok(Box::new(MemoryDbOptimisticTransaction::new(Arc::clone(&self.map))))
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryDbOptimisticTransaction {
+    inner: MemoryDbTransaction,
+    reads: Mutex<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+/// Implementation of optimistic transaction for MemoryDb.
+impl MemoryDbOptimisticTransaction {
+    fn new(db_map: Arc<Option<Mutex<Map>>>) -> Self {
+        Self {
+            inner: MemoryDbTransaction::new(db_map),
+            reads: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn pending_value(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.inner.pending.lock().unwrap().iter().rev()
+            .find_map(|operation| match operation {
+                PendingOperation::Put(pair) if pair.key == key => Some(Some(pair.value.clone())),
+                PendingOperation::Delete(deleted) if deleted == key => Some(None),
+                _ => None,
+            })
+    }
+}
+
+impl<K: DbKey + Send + Sync> KvcTransaction<K> for MemoryDbOptimisticTransaction {
+    fn put(&self, key: &K, value: &[u8]) {
+        KvcTransaction::<K>::put(&self.inner, key, value)
+    }
+
+    fn delete(&self, key: &K) {
+        KvcTransaction::<K>::delete(&self.inner, key)
+    }
+
+    fn clear(&self) {
+        KvcTransaction::<K>::clear(&self.inner)
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        let mut guard = self.inner.db_map.as_ref().as_ref()
+            .ok_or(StorageError::DbIsDropped)?
+            .lock().unwrap();
+        for (key, value) in self.reads.lock().unwrap().iter() {
+            if guard.get(key) != value.as_ref() {
+                Err(StorageError::TransactionConflict(hex::encode(key)))?
+            }
+        }
+        for operation in self.inner.pending.lock().unwrap().drain(..) {
+            match operation {
+                PendingOperation::Put(pair) => guard.insert(pair.key, pair.value),
+                PendingOperation::Delete(key) => guard.remove(&key),
+            };
+        }
+
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        KvcTransaction::<K>::len(&self.inner)
+    }
+}
+
+impl<K: DbKey + Send + Sync> KvcReadableTransaction<K> for MemoryDbOptimisticTransaction {
+    fn try_get(&self, key: &K) -> Result<Option<DbSlice>> {
+        if let Some(value) = self.pending_value(key.key()) {
+            return Ok(value.map(|value| value.into()));
+        }
+        let mut reads = self.reads.lock().unwrap();
+        if let Some(value) = reads.get(key.key()) {
+            return Ok(value.clone().map(|value| value.into()));
+        }
+        let value = self.inner.db_map.as_ref().as_ref()
+            .ok_or(StorageError::DbIsDropped)?
+            .lock().unwrap()
+            .get(key.key())
+            .cloned();
+        reads.insert(key.key().to_vec(), value.clone());
+        Ok(value.map(|value| value.into()))
+    }
+}
@@ -1,6 +1,10 @@
 pub mod traits;
 pub mod async_adapter;
+pub mod async_kvc;
 pub mod rocksdb;
+pub mod rocksdb_options;
+pub mod rocksdb_backup;
 pub mod memorydb;
 pub mod filedb;
+pub mod snapshot_transaction;
 
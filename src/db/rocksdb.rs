@@ -1,120 +1,1319 @@
+use std::convert::TryFrom;
 use std::fmt::{Debug, Formatter};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-use rocksdb::{DB, IteratorMode, Options, Snapshot, WriteBatch};
+use fnv::FnvHashMap;
+use lazy_static::lazy_static;
 
-use ton_types::{fail, Result};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{ColumnFamily, ColumnFamilyDescriptor, DB, DBRawIterator, Direction, ErrorKind, IteratorMode, Options, ReadOptions, Snapshot, WriteBatch, WriteOptions};
 
-use crate::db::traits::{DbKey, Kvc, KvcReadable, KvcSnapshotable, KvcTransaction, KvcTransactional, KvcWriteable};
+use ton_types::{error, fail, Result};
+
+use crate::db::rocksdb_options::{set_rate_limiter, ChecksumVerification, RocksDbOptions};
+use crate::db::traits::{
+    DbKey, Kvc, KvcOptimisticTransactional, KvcReadable, KvcReadableTransaction, KvcSnapshotable,
+    KvcTransaction, KvcTransactional, KvcWriteable
+};
 use crate::error::StorageError;
 use crate::types::DbSlice;
 
+const CLONE_BATCH_SIZE: usize = 10_000;
+const DEFAULT_SNAPSHOT_WARNING_AGE: Duration = Duration::from_secs(600);
+const DESTROY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const DEFAULT_CF_NAME: &str = "default";
+
+lazy_static! {
+    /// Databases opened by this process, to keep `RocksDb::repair()` off them
+    static ref OPEN_DBS: Mutex<Vec<(PathBuf, Weak<Option<DB>>)>> = Mutex::new(Vec::new());
+}
+
+#[derive(Debug)]
+pub struct RocksDb {
+    db: Arc<Option<DB>>,
+    path: PathBuf,
+    options: DbOptions,
+    snapshots: Arc<SnapshotTracker>,
+    commit_lock: Arc<Mutex<()>>,
+    read_verification: ChecksumVerification,
+    read_only: bool,
+    /// Column families are opened with their own options, which are not kept
+    own_cf_options: bool,
+}
+
+/// Options the database was opened with; they hold statistics, if enabled
+struct DbOptions(Options);
+
+impl Debug for DbOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[options]")
+    }
+}
+
+impl RocksDb {
+    /// Creates new instance with given path
+    pub fn with_path(path: impl AsRef<Path>) -> Self {
+        Self::open(path).expect("Cannot open DB")
+    }
+
+    /// Creates new instance with given path and ability to additionally configure options
+    pub fn with_options(path: impl AsRef<Path>, configure_options: impl Fn(&mut Options)) -> Self {
+        Self::open_with_options(path, configure_options).expect("Cannot open DB")
+    }
+
+    /// Opens database with given path, returning error instead of panicking on failure
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(path, |_| {})
+    }
+
+    /// Opens database with given path and ability to additionally configure options
+    pub fn open_with_options(path: impl AsRef<Path>, configure_options: impl Fn(&mut Options)) -> Result<Self> {
+        let pathbuf = path.as_ref().to_path_buf();
+        let options = Self::build_options(configure_options);
+        let db = DB::open(&options, &pathbuf)
+            .map_err(|err| error!("Cannot open DB {}: {}", pathbuf.display(), err))?;
+
+        Ok(Self::with_db(db, pathbuf, options, false))
+    }
+
+    /// Creates new instance with given path and options built by options builder
+    pub fn with_db_options(path: impl AsRef<Path>, db_options: &RocksDbOptions) -> Self {
+        Self::with_options(path, |options| db_options.apply(options))
+            .with_read_verification(db_options.read_verification())
+    }
+
+    /// Lists names of column families of existing database with given path
+    pub fn list_column_families(path: impl AsRef<Path>) -> Result<Vec<String>> {
+        Ok(DB::list_cf(&Options::default(), path)?)
+    }
+
+    /// Opens database with given path, discovering and opening all its existing column families
+    pub fn with_all_column_families(path: impl AsRef<Path>, db_options: &RocksDbOptions) -> Result<Self> {
+        let pathbuf = path.as_ref().to_path_buf();
+        let options = Self::build_options(|options| db_options.apply(options));
+        let cf_names = if pathbuf.join("CURRENT").exists() {
+            Self::list_column_families(&pathbuf)?
+        } else {
+            Vec::new()
+        };
+
+        let db = DB::open_cf(&options, &pathbuf, cf_names)?;
+
+        Ok(Self::with_db(db, pathbuf, options, false).with_read_verification(db_options.read_verification()))
+    }
+
+    /// Opens database with given path and column families. Column families missing in existing
+    /// database are created only if it is allowed by options; new database gets all of them
+    pub fn open_with_cfs(path: impl AsRef<Path>, cf_names: &[&str]) -> Result<Self> {
+        Self::open_with_cfs_and_options(path, cf_names, &RocksDbOptions::default())
+    }
+
+    /// Opens database with given path, column families and options built by options builder
+    pub fn open_with_cfs_and_options(
+        path: impl AsRef<Path>,
+        cf_names: &[&str],
+        db_options: &RocksDbOptions,
+    ) -> Result<Self> {
+        let pathbuf = path.as_ref().to_path_buf();
+        let is_new = !pathbuf.join("CURRENT").exists();
+        let options = Self::build_options(|options| {
+            db_options.apply(options);
+            if is_new {
+                options.create_missing_column_families(true);
+            }
+        });
+        let db = DB::open_cf(&options, &pathbuf, cf_names)
+            .map_err(|err| error!("Cannot open DB {}: {}", pathbuf.display(), err))?;
+
+        Ok(Self::with_db(db, pathbuf, options, false).with_read_verification(db_options.read_verification()))
+    }
+
+    /// Opens database with given path and column families, each with its own options
+    /// (e.g. compaction filter or merge operator specific to the family)
+    pub fn open_with_cf_options(
+        path: impl AsRef<Path>,
+        db_options: &RocksDbOptions,
+        cfs: Vec<(&str, Options)>,
+    ) -> Result<Self> {
+        let pathbuf = path.as_ref().to_path_buf();
+        let is_new = !pathbuf.join("CURRENT").exists();
+        let options = Self::build_options(|options| {
+            db_options.apply(options);
+            if is_new {
+                options.create_missing_column_families(true);
+            }
+        });
+        let descriptors = cfs.into_iter()
+            .map(|(name, cf_options)| ColumnFamilyDescriptor::new(name, cf_options));
+        let db = DB::open_cf_descriptors(&options, &pathbuf, descriptors)
+            .map_err(|err| error!("Cannot open DB {}: {}", pathbuf.display(), err))?;
+
+        let mut db = Self::with_db(db, pathbuf, options, false).with_read_verification(db_options.read_verification());
+        db.own_cf_options = true;
+        Ok(db)
+    }
+
+    /// Returns collection over column family with given name
+    pub fn cf(&self, name: &str) -> Result<RocksDbCf> {
+        let cf = RocksDbCf {
+            db: Arc::clone(&self.db),
+            name: name.to_string(),
+            snapshots: Arc::clone(&self.snapshots),
+            read_verification: self.read_verification,
+            read_only: self.read_only,
+        };
+        cf.cf_handle()?;
+
+        Ok(cf)
+    }
+
+    /// Creates new transaction, which operations may target different column families of the
+    /// database; all of them are committed atomically in one batch
+    pub fn begin_multi_cf_transaction(&self) -> Result<RocksDbMultiCfTransaction> {
+        if self.read_only {
+            Err(StorageError::ReadOnly)?
+        }
+        Ok(RocksDbMultiCfTransaction {
+            inner: RocksDbTransaction::new(Arc::clone(&self.db)),
+        })
+    }
+
+    /// Counts items by full iteration. Unlike len(), which is estimated, the result is exact
+    pub fn exact_len(&self) -> Result<usize> {
+        Ok(self.db()?.iterator(IteratorMode::Start).count())
+    }
+
+    /// Opens database with given path in read-only mode, so it can be used along with another
+    /// instance opened normally. Any modification returns `StorageError::ReadOnly`
+    pub fn open_read_only(path: impl AsRef<Path>, error_if_log_file_exist: bool) -> Result<Self> {
+        let pathbuf = path.as_ref().to_path_buf();
+        let options = Options::default();
+        let db = DB::open_for_read_only(&options, &pathbuf, error_if_log_file_exist)
+            .map_err(|err| error!("Cannot open DB {} for read only: {}", pathbuf.display(), err))?;
+
+        Ok(Self::with_db(db, pathbuf, options, true))
+    }
+
+    /// Opens secondary instance of database with given primary path, keeping own info logs in
+    /// secondary path. Secondary instance follows primary by `catch_up_with_primary()`;
+    /// writes are disallowed and return `StorageError::ReadOnly`
+    pub fn open_as_secondary(primary_path: impl AsRef<Path>, secondary_path: impl AsRef<Path>) -> Result<Self> {
+        let pathbuf = primary_path.as_ref().to_path_buf();
+        let mut options = Options::default();
+        // Secondary instance requires all files to be kept open
+        options.set_max_open_files(-1);
+        let db = DB::open_as_secondary(&options, &pathbuf, secondary_path.as_ref())
+            .map_err(|err| error!("Cannot open DB {} as secondary: {}", pathbuf.display(), err))?;
+
+        Ok(Self::with_db(db, pathbuf, options, true))
+    }
+
+    /// Replays new changes of primary instance, so reads see everything written up to this call.
+    /// Has effect for secondary instances only
+    pub fn catch_up_with_primary(&self) -> Result<()> {
+        Ok(self.db()?.try_catch_up_with_primary()?)
+    }
+
+    /// Creates new chunked, non-atomic transaction: whenever the staged batch reaches `max_bytes`,
+    /// it is written into the database and a fresh one is started, so memory stays bounded.
+    /// Chunks written before a failure or an abort are not rolled back; `len()` counts operations
+    /// of the current chunk only. An error of writing a chunk is reported by `commit()`
+    pub fn begin_transaction_chunked<K: DbKey + Send + Sync>(&self, max_bytes: usize) -> Result<Box<dyn KvcTransaction<K>>> {
+        if self.read_only {
+            Err(StorageError::ReadOnly)?
+        }
+        Ok(Box::new(RocksDbTransaction::with_max_bytes(Arc::clone(&self.db), Some(max_bytes))))
+    }
+
+    /// Repairs damaged database with given path, salvaging as much data as possible. Records
+    /// which can't be recovered (e.g. from damaged SST files) are dropped. The database must be
+    /// closed: fails with `StorageError::DbIsOpen`, if this process has it opened, while other
+    /// processes are kept off by the lock file of database
+    pub fn repair<P: AsRef<Path>>(path: P, options: Options) -> Result<()> {
+        let path = path.as_ref();
+        let canonical = canonical_path(path);
+        let is_open = OPEN_DBS.lock().unwrap().iter()
+            .any(|(open_path, db)| *open_path == canonical && db.upgrade().map_or(false, |db| db.is_some()));
+        if is_open {
+            Err(StorageError::DbIsOpen(path.display().to_string()))?
+        }
+        DB::repair(&options, path)
+            .map_err(|err| error!("Cannot repair DB {}: {}", path.display(), err))
+    }
+
+    /// Gets count of outstanding handles sharing the database: uncommitted transactions,
+    /// owned snapshots and column families. Destroying the database requires it to be zero
+    pub fn active_transactions(&self) -> usize {
+        Arc::strong_count(&self.db) - 1
+    }
+
+    /// Waits for all transactions and column family handles to be dropped (polling
+    /// their count), then destroys the database. Fails with `StorageError::Timeout`,
+    /// if they are still alive after given timeout; the database is left intact in that case
+    pub fn destroy_blocking(&mut self, timeout: Duration) -> Result<()> {
+        let started = Instant::now();
+        while self.active_transactions() > 0 {
+            if started.elapsed() >= timeout {
+                Err(StorageError::Timeout(
+                    format!("{} active transactions on {}", self.active_transactions(), self.path.display())
+                ))?
+            }
+            std::thread::sleep(DESTROY_POLL_INTERVAL);
+        }
+        self.destroy()
+    }
+
+    /// Closes the database before its files are removed. Dropping the only instance
+    /// of `DB` releases its lock file; the instance is left with no database, so any further
+    /// operation fails with `StorageError::DbIsDropped`
+    fn close(&mut self) -> Result<()> {
+        let db = Arc::get_mut(&mut self.db)
+            .ok_or(StorageError::HasActiveTransactions)?
+            .take();
+        drop(db);
+        Ok(())
+    }
+
+    /// Returns true if database is opened in read-only mode
+    pub const fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn with_db(db: DB, path: PathBuf, options: Options, read_only: bool) -> Self {
+        Self {
+            db: Self::register(db, &path),
+            path,
+            options: DbOptions(options),
+            snapshots: Arc::new(SnapshotTracker::new()),
+            commit_lock: Arc::new(Mutex::new(())),
+            read_verification: ChecksumVerification::default(),
+            read_only,
+            own_cf_options: false,
+        }
+    }
+
+    /// Adds opened database to the registry of open databases
+    fn register(db: DB, path: &Path) -> Arc<Option<DB>> {
+        let db = Arc::new(Some(db));
+        let mut open_dbs = OPEN_DBS.lock().unwrap();
+        open_dbs.retain(|(_, db)| db.strong_count() > 0);
+        open_dbs.push((canonical_path(path), Arc::downgrade(&db)));
+        db
+    }
+
+    fn with_read_verification(mut self, read_verification: ChecksumVerification) -> Self {
+        self.read_verification = read_verification;
+        self
+    }
+
+    fn writable_db(&self) -> Result<&DB> {
+        if self.read_only {
+            Err(StorageError::ReadOnly)?
+        }
+        self.db()
+    }
+
+    /// Returns description of background errors (e.g. failed flush because of full disk) occurred
+    /// since the database was opened or resumed, if any. The description is built from
+    /// `rocksdb.background-errors` property: RocksDB bindings don't expose the error itself
+    pub fn background_error(&self) -> Result<Option<String>> {
+        let count = self.db()?.property_int_value("rocksdb.background-errors")?
+            .unwrap_or(0);
+        if count == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(format!("{} background error(s) occurred in database {}", count, self.path.display())))
+    }
+
+    /// Clears background error, e.g. after disk space is freed, so the database accepts writes again.
+    /// RocksDB bindings have no DB::Resume(), so the database is closed and reopened with the same
+    /// options, which recovers it the same way. Fails with `StorageError::HasActiveTransactions`, if
+    /// the database is shared (see active_transactions()), leaving it intact. If reopening fails,
+    /// the instance is left closed. Database opened with own options of column families
+    /// can't be resumed
+    pub fn resume(&mut self) -> Result<()> {
+        self.reopen(|_| {})?;
+        log::debug!(target: "storage", "Database {} is resumed", self.path.display());
+
+        Ok(())
+    }
+
+    /// Changes limit of flush and compaction writes set by `RocksDbOptions::set_write_rate_limit()`,
+    /// e.g. raises it once initial sync is completed. RocksDB bindings give no handle of the limiter,
+    /// so the database is reopened in place with the new limit, like in resume(), and fails the same way
+    pub fn set_write_rate_limit(&mut self, bytes_per_sec: i64) -> Result<()> {
+        if bytes_per_sec <= 0 {
+            fail!("Write rate limit must be positive, got {}", bytes_per_sec);
+        }
+        self.reopen(|options| set_rate_limiter(options, bytes_per_sec))?;
+        log::debug!(target: "storage", "Write rate limit of database {} is set to {} bytes/s", self.path.display(), bytes_per_sec);
+
+        Ok(())
+    }
+
+    /// Closes the database and opens it again with all its column families and kept options,
+    /// changed by given function once the database is closed
+    fn reopen(&mut self, configure_options: impl FnOnce(&mut Options)) -> Result<()> {
+        if self.read_only {
+            Err(StorageError::ReadOnly)?
+        }
+        if self.own_cf_options {
+            fail!("Database {} is opened with options of column families, it can't be reopened", self.path.display());
+        }
+        let cf_names = Self::list_column_families(&self.path)?;
+        self.close()?;
+        configure_options(&mut self.options.0);
+        let db = DB::open_cf(&self.options.0, &self.path, cf_names)
+            .map_err(|err| error!("Cannot reopen DB {}: {}", self.path.display(), err))?;
+        self.db = Self::register(db, &self.path);
+
+        Ok(())
+    }
+
+    /// Copies consistent snapshot of the database (optionally only keys with given prefix)
+    /// into a new database with given path, column families included. Fails with
+    /// `StorageError::PathAlreadyExists`, if the path exists
+    pub fn clone_to(&self, dst: &Path, prefix: Option<&[u8]>) -> Result<()> {
+        if dst.exists() {
+            Err(StorageError::PathAlreadyExists(dst.display().to_string()))?
+        }
+        let db = self.db()?;
+        let cf_names = Self::list_column_families(&self.path)?;
+        let dst_db = DB::open_cf(
+            &Self::build_options(|options| options.create_missing_column_families(true)),
+            dst,
+            &cf_names
+        )?;
+
+        let snapshot = db.snapshot();
+        for name in cf_names.iter() {
+            let mode = match prefix {
+                Some(prefix) => IteratorMode::From(prefix, Direction::Forward),
+                None => IteratorMode::Start,
+            };
+            let (iterator, dst_cf) = if name == DEFAULT_CF_NAME {
+                (snapshot.iterator(mode), None)
+            } else {
+                let cf = db.cf_handle(name)
+                    .ok_or_else(|| error!("Column family {} is not opened", name))?;
+                let dst_cf = dst_db.cf_handle(name)
+                    .ok_or_else(|| error!("Column family {} is not created in {}", name, dst.display()))?;
+                (snapshot.iterator_cf(cf, mode), Some(dst_cf))
+            };
+
+            let mut batch = WriteBatch::default();
+            for (key, value) in iterator {
+                if let Some(prefix) = prefix {
+                    if !key.starts_with(prefix) {
+                        break;
+                    }
+                }
+                match dst_cf {
+                    Some(dst_cf) => batch.put_cf(dst_cf, key, value),
+                    None => batch.put(key, value),
+                }
+                if batch.len() >= CLONE_BATCH_SIZE {
+                    dst_db.write(std::mem::take(&mut batch))?;
+                }
+            }
+            if !batch.is_empty() {
+                dst_db.write(batch)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates checkpoint: consistent copy of the database at given path, which doesn't exist yet.
+    /// SST files are hard-linked, if the path is on the same filesystem, otherwise copied
+    pub fn create_checkpoint<P: AsRef<Path>>(&self, target: P) -> Result<()> {
+        let target = target.as_ref();
+        if target.exists() {
+            Err(StorageError::PathAlreadyExists(target.display().to_string()))?
+        }
+        Checkpoint::new(self.db()?)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(target))
+            .map_err(storage_error)
+    }
+
+    /// Writes every key-value pair of consistent snapshot of the database into writer as records:
+    /// key length (u32 LE), key, value length (u32 LE), value. Returns count of records
+    pub fn export_to_writer(&self, writer: &mut dyn Write) -> Result<u64> {
+        let snapshot = self.db()?.snapshot();
+        let mut count = 0;
+        for (key, value) in snapshot.iterator(IteratorMode::Start) {
+            write_dump_field(writer, &key)?;
+            write_dump_field(writer, &value)?;
+            count += 1;
+        }
+        writer.flush()?;
+
+        Ok(count)
+    }
+
+    /// Loads records written by `export_to_writer()` into the database in batches, each under
+    /// its own write. Returns count of loaded records
+    pub fn import_from_reader(&self, reader: &mut dyn Read) -> Result<u64> {
+        let db = self.writable_db()?;
+        let mut batch = WriteBatch::default();
+        let mut count = 0;
+        while let Some(key) = read_dump_field(reader, true)? {
+            let value = read_dump_field(reader, false)?
+                .ok_or_else(|| error!("Unexpected end of dump after key {}", hex::encode(&key)))?;
+            batch.put(key, value);
+            count += 1;
+            if batch.len() >= CLONE_BATCH_SIZE {
+                db.write(std::mem::take(&mut batch)).map_err(storage_error)?;
+            }
+        }
+        if !batch.is_empty() {
+            db.write(batch).map_err(storage_error)?;
+        }
+
+        Ok(count)
+    }
+
+    /// Returns formatted statistics. Statistics must be enabled by options
+    pub fn statistics(&self) -> Result<String> {
+        self.db()?;
+        self.options.0.get_statistics()
+            .ok_or_else(|| error!("Statistics are not enabled for DB {}", self.path.display()))
+    }
+
+    /// Gets value of database property, e.g. "rocksdb.cur-size-all-mem-tables"
+    pub fn property(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.db()?.property_value(name)?)
+    }
+
+    /// Flushes memtables into SST files
+    pub fn flush(&self) -> Result<()> {
+        Ok(self.db()?.flush()?)
+    }
+
+    /// Runs predicates over all items on `shards` threads (at most 256). The keyspace is split into
+    /// contiguous ranges by the first byte of key, each range is iterated on its own thread by its
+    /// own predicate made by `factory`; items are ordered within a range only. When a predicate
+    /// returns false or fails, iteration of other ranges stops at their next item. Returns false,
+    /// if iteration was stopped by a predicate
+    pub fn for_each_parallel(
+        &self,
+        shards: usize,
+        factory: impl Fn() -> Box<dyn FnMut(&[u8], &[u8]) -> Result<bool>> + Sync,
+    ) -> Result<bool> {
+        let db = self.db()?;
+        let shards = shards.max(1).min(256);
+        let stop = AtomicBool::new(false);
+        let results = std::thread::scope(|scope| {
+            let workers = (0..shards)
+                .map(|shard| {
+                    let (factory, stop) = (&factory, &stop);
+                    scope.spawn(move || for_each_in_shard(db, shard, shards, factory(), stop))
+                })
+                .collect::<Vec<_>>();
+            workers.into_iter()
+                .map(|worker| worker.join().map_err(|_| error!("for_each_parallel() worker panicked"))?)
+                .collect::<Vec<_>>()
+        });
+
+        let mut completed = true;
+        for result in results {
+            completed &= result?;
+        }
+
+        Ok(completed)
+    }
+
+    /// Scrubs the database: reads all records of all opened column families bypassing block
+    /// cache, so checksum of every data block is verified. RocksDB bindings don't expose
+    /// DB::VerifyChecksum(), so it is done by full scan, reading the whole database from disk.
+    /// Fails with `StorageError::Corruption` on the first mismatch
+    pub fn verify_checksum(&self) -> Result<()> {
+        let db = self.db()?;
+        let mut iterators = vec![db.raw_iterator_opt(scrub_read_options())];
+        for name in Self::list_column_families(&self.path)? {
+            if name == DEFAULT_CF_NAME {
+                continue;
+            }
+            if let Some(cf) = db.cf_handle(&name) {
+                iterators.push(db.raw_iterator_cf_opt(cf, scrub_read_options()));
+            }
+        }
+        for mut iterator in iterators {
+            iterator.seek_to_first();
+            while iterator.valid() {
+                iterator.next();
+            }
+            iterator.status()
+                .map_err(|err| StorageError::Corruption(format!("{}: {}", self.path.display(), err)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Compacts keys in inclusive range [start, end]; None bounds mean beginning and end of
+    /// the keyspace. Fails with `StorageError::ReadOnly` on read-only and secondary instances
+    pub fn compact_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<()> {
+        self.writable_db()?.compact_range(start, end);
+        Ok(())
+    }
+
+    /// Compacts whole database and reports its disk size before and after compaction.
+    /// Obsolete files are removed by RocksDB right after compaction, unless they are pinned
+    /// by live snapshots or iterators
+    pub fn compact_and_report(&self) -> Result<CompactReport> {
+        let size_before = Self::dir_size(&self.path)?;
+        self.compact_range(None, None)?;
+        let size_after = Self::dir_size(&self.path)?;
+
+        Ok(CompactReport { size_before, size_after })
+    }
+
+    fn dir_size(path: &Path) -> Result<u64> {
+        let mut size = 0;
+        for entry in std::fs::read_dir(path)? {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                size += metadata.len();
+            }
+        }
+
+        Ok(size)
+    }
+
+    /// Creates tailing iterator over keys with given prefix (or over all keys), which picks up
+    /// keys written after its creation when re-seeked by `TailingIterator::refresh()`
+    pub fn tail(&self, prefix: Option<&[u8]>) -> Result<TailingIterator> {
+        let mut read_options = ReadOptions::default();
+        read_options.set_tailing(true);
+
+        let mut iterator = TailingIterator {
+            iterator: self.db()?.raw_iterator_opt(read_options),
+            prefix: prefix.map(|prefix| prefix.to_vec()),
+            last_key: None,
+        };
+        iterator.refresh();
+
+        Ok(iterator)
+    }
+
+    /// Takes snapshot, which owns a reference to the database instead of borrowing it, so it can
+    /// be moved to another thread or stored for a long time. While the snapshot is held, SST files
+    /// with data it sees are not deleted by compaction, so disk usage grows with the amount of
+    /// overwritten and deleted data; the database can't be destroyed either
+    pub fn owned_snapshot(&self) -> Result<OwnedSnapshot> {
+        let guard = SnapshotGuard::new(Arc::clone(&self.snapshots));
+        let db_holder = Arc::clone(&self.db);
+        let db = (*db_holder).as_ref().ok_or(StorageError::DbIsDropped)?;
+        // SAFETY: `db` points into the heap allocation of the `Arc`, which doesn't move and is kept
+        // alive by `db_holder` for the whole life of `OwnedSnapshot`; its `snapshot` field is declared
+        // before `_db`, so the snapshot is dropped before the reference. The `DB` can't be taken out
+        // of the `Option` meanwhile: the only mutation is in `close()`, which gets the `Option` by
+        // `Arc::get_mut()`, and it fails with `HasActiveTransactions` while `db_holder` adds a strong
+        // reference (`OPEN_DBS` holds weak ones only). The `'static` lifetime doesn't escape: the
+        // field is private and everything read from the snapshot borrows `OwnedSnapshot` itself
+        let db: &'static DB = unsafe { &*(db as *const DB) };
+
+        Ok(OwnedSnapshot {
+            snapshot: RocksDbSnapshot(db.snapshot(), guard, db),
+            _db: db_holder,
+        })
+    }
+
+    /// Returns information about outstanding snapshots, warning about ones held too long
+    pub fn snapshots_info(&self) -> Vec<SnapshotInfo> {
+        let result = self.snapshots.snapshots_info();
+        let warning_age = self.snapshots.warning_age();
+        for info in result.iter() {
+            if info.age() > warning_age {
+                log::warn!(
+                    target: "storage",
+                    "Snapshot #{} of {:?} is held for {:?}",
+                    info.id(), self.path, info.age()
+                );
+            }
+        }
+
+        result
+    }
+
+    /// Sets age of snapshot, after which holding it is reported as a warning
+    pub fn set_snapshot_warning_age(&self, age: Duration) {
+        self.snapshots.set_warning_age(age);
+    }
+
+    fn build_options(configure_options: impl Fn(&mut Options)) -> Options {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.set_max_total_wal_size(1024 * 1024 * 1024);
+
+        configure_options(&mut options);
+
+        options
+    }
+
+    pub(crate) fn db(&self) -> Result<&DB> {
+        if let Some(ref db) = *self.db {
+            Ok(db)
+        } else {
+            Err(StorageError::DbIsDropped)?
+        }
+    }
+}
+
+/// Disk size of database before and after compaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactReport {
+    size_before: u64,
+    size_after: u64,
+}
+
+impl CompactReport {
+    pub const fn size_before(&self) -> u64 {
+        self.size_before
+    }
+
+    pub const fn size_after(&self) -> u64 {
+        self.size_after
+    }
+
+    /// Bytes freed by compaction
+    pub fn reclaimed(&self) -> u64 {
+        self.size_before.saturating_sub(self.size_after)
+    }
+}
+
+/// Iterator following new writes into RocksDB without reopening.
+/// Note: tailing iterator doesn't pin any snapshot, so deletions made behind its position
+/// are not guaranteed to be observed the same way as by a regular iterator
+pub struct TailingIterator<'db> {
+    iterator: DBRawIterator<'db>,
+    prefix: Option<Vec<u8>>,
+    last_key: Option<Vec<u8>>,
+}
+
+impl TailingIterator<'_> {
+    /// Returns next key-value pair, or None if all written data is already read
+    pub fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if !self.iterator.valid() {
+            self.iterator.status()?;
+            return Ok(None);
+        }
+
+        let (key, value) = match (self.iterator.key(), self.iterator.value()) {
+            (Some(key), Some(value)) => (key.to_vec(), value.to_vec()),
+            _ => return Ok(None),
+        };
+        if let Some(ref prefix) = self.prefix {
+            if !key.starts_with(prefix) {
+                return Ok(None);
+            }
+        }
+
+        self.last_key = Some(key.clone());
+        self.iterator.next();
+
+        Ok(Some((key, value)))
+    }
+
+    /// Re-seeks iterator after the last returned key in order to pick up newly written keys
+    pub fn refresh(&mut self) {
+        if let Some(ref last_key) = self.last_key {
+            self.iterator.seek(last_key);
+            if self.iterator.key() == Some(last_key.as_slice()) {
+                self.iterator.next();
+            }
+        } else if let Some(ref prefix) = self.prefix {
+            self.iterator.seek(prefix);
+        } else {
+            self.iterator.seek_to_first();
+        }
+    }
+}
+
+/// Implementation of key-value collection for RocksDB
+impl Kvc for RocksDb {
+    fn len(&self) -> Result<usize> {
+        estimate_num_keys(self.db()?, None)
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        if self.read_only {
+            Err(StorageError::ReadOnly)?
+        }
+        self.close()?;
+
+        Ok(DB::destroy(&Options::default(), &self.path)?)
+    }
+}
+
+/// Implementation of readable key-value collection for RocksDB. Actual implementation is blocking.
+impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDb {
+    fn try_get(&self, key: &K) -> Result<Option<DbSlice>> {
+        let db = self.db()?;
+        let value = match self.read_verification.read_options() {
+            Some(read_options) => db.get_pinned_opt(key.key(), &read_options),
+            None => db.get_pinned(key.key()),
+        };
+        Ok(value.map_err(storage_error)?
+            .map(|value| value.into()))
+    }
+
+    fn may_exist(&self, key: &K) -> bool {
+        // Dropped database is reported as possibly containing the key; the real read will fail
+        self.db().map_or(true, |db| db.key_may_exist(key.key()))
+    }
+
+    fn get_multi(&self, keys: &[K]) -> Result<Vec<Option<DbSlice>>> {
+        self.db()?.multi_get(keys.iter().map(|key| key.key()))
+            .into_iter()
+            .map(|result| Ok(result?.map(|value| value.into())))
+            .collect()
+    }
+
+    fn contains_multi(&self, keys: &[K]) -> Result<Vec<bool>> {
+        self.db()?.multi_get(keys.iter().map(|key| key.key()))
+            .into_iter()
+            .map(|result| Ok(result?.is_some()))
+            .collect()
+    }
+
+    fn for_each(&self, predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<bool> {
+        for (key, value) in self.db()?.iterator(IteratorMode::Start) {
+            if !call_predicate(predicate, key.as_ref(), value.as_ref())? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_>> {
+        Ok(Box::new(self.db()?.iterator(IteratorMode::Start).map(into_item)))
+    }
+
+    fn for_each_from(
+        &self,
+        start: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        iterate_all(self.db()?.iterator(IteratorMode::From(start, Direction::Forward)), predicate)
+    }
+
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        iterate_prefix(self.db()?.iterator(IteratorMode::From(prefix, Direction::Forward)), prefix, predicate)
+    }
+
+    fn for_each_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        if start >= end {
+            return Ok(true);
+        }
+        let iterator = self.db()?.iterator_opt(IteratorMode::From(start, Direction::Forward), range_read_options(start, end));
+        iterate_all(iterator, predicate)
+    }
+}
+
+/// Implementation of writable key-value collection for RocksDB. Actual implementation is blocking.
+impl<K: DbKey + Send + Sync> KvcWriteable<K> for RocksDb {
+    fn put(&self, key: &K, value: &[u8]) -> Result<()> {
+        self.writable_db()?.put(key.key(), value)
+            .map_err(storage_error)
+    }
+
+    fn delete(&self, key: &K) -> Result<()> {
+        self.writable_db()?.delete(key.key())
+            .map_err(storage_error)
+    }
+
+    fn merge(&self, key: &K, operand: &[u8]) -> Result<()> {
+        self.writable_db()?.merge(key.key(), operand)
+            .map_err(storage_error)
+    }
+
+    fn delete_range(&self, from: &[u8], to: &[u8]) -> Result<()> {
+        let db = self.writable_db()?;
+        if from >= to {
+            return Ok(());
+        }
+        let mut batch = WriteBatch::default();
+        batch.delete_range(from, to);
+        db.write(batch)
+            .map_err(storage_error)
+    }
+}
+
+/// Implementation of support for take snapshots for RocksDB.
+impl<K: DbKey + Send + Sync> KvcSnapshotable<K> for RocksDb {
+    fn snapshot<'db>(&'db self) -> Result<Arc<dyn KvcReadable<K> + 'db>> {
+        let guard = SnapshotGuard::new(Arc::clone(&self.snapshots));
+        let db = self.db()?;
+        Ok(Arc::new(RocksDbSnapshot(db.snapshot(), guard, db)))
+    }
+}
+
+/// Information about outstanding snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    id: u64,
+    created: SystemTime,
+}
+
+impl SnapshotInfo {
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub const fn created(&self) -> SystemTime {
+        self.created
+    }
+
+    /// Time elapsed since snapshot creation
+    pub fn age(&self) -> Duration {
+        self.created.elapsed().unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct SnapshotTracker {
+    next_id: AtomicU64,
+    snapshots: Mutex<FnvHashMap<u64, SystemTime>>,
+    warning_age_ms: AtomicU64,
+}
+
+impl SnapshotTracker {
+    fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            snapshots: Mutex::new(FnvHashMap::default()),
+            warning_age_ms: AtomicU64::new(DEFAULT_SNAPSHOT_WARNING_AGE.as_millis() as u64),
+        }
+    }
+
+    fn register(&self) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.snapshots.lock().unwrap()
+            .insert(id, SystemTime::now());
+
+        id
+    }
+
+    fn unregister(&self, id: u64) {
+        let created = self.snapshots.lock().unwrap()
+            .remove(&id);
+        if let Some(created) = created {
+            let age = created.elapsed().unwrap_or_default();
+            if age > self.warning_age() {
+                log::warn!(target: "storage", "Snapshot #{} was held for {:?}", id, age);
+            }
+        }
+    }
+
+    fn snapshots_info(&self) -> Vec<SnapshotInfo> {
+        let mut result: Vec<_> = self.snapshots.lock().unwrap()
+            .iter()
+            .map(|(&id, &created)| SnapshotInfo { id, created })
+            .collect();
+        result.sort_by_key(|info| info.id);
+
+        result
+    }
+
+    fn warning_age(&self) -> Duration {
+        Duration::from_millis(self.warning_age_ms.load(Ordering::Relaxed))
+    }
+
+    fn set_warning_age(&self, age: Duration) {
+        self.warning_age_ms.store(age.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Keeps snapshot registered in tracker while alive
+struct SnapshotGuard {
+    id: u64,
+    tracker: Arc<SnapshotTracker>,
+}
+
+impl SnapshotGuard {
+    fn new(tracker: Arc<SnapshotTracker>) -> Self {
+        Self { id: tracker.register(), tracker }
+    }
+}
+
+impl Drop for SnapshotGuard {
+    fn drop(&mut self) {
+        self.tracker.unregister(self.id);
+    }
+}
+
+struct RocksDbSnapshot<'db>(Snapshot<'db>, SnapshotGuard, &'db DB);
+
+impl Debug for RocksDbSnapshot<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[snapshot]")
+    }
+}
+
+impl Kvc for RocksDbSnapshot<'_> {
+    fn len(&self) -> Result<usize> {
+        // Estimation is taken from the database itself, since RocksDB has no per-snapshot estimation
+        estimate_num_keys(self.2, None)
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        fail!("destroy() is not supported for snapshots")
+    }
+}
+
+impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDbSnapshot<'_> {
+    fn try_get(&self, key: &K) -> Result<Option<DbSlice>> {
+        Ok(self.0.get(key.key()).map_err(storage_error)?
+            .map(|value| value.into()))
+    }
+
+    fn get_multi(&self, keys: &[K]) -> Result<Vec<Option<DbSlice>>> {
+        self.0.multi_get(keys.iter().map(|key| key.key()))
+            .into_iter()
+            .map(|result| Ok(result?.map(|value| value.into())))
+            .collect()
+    }
+
+    fn contains_multi(&self, keys: &[K]) -> Result<Vec<bool>> {
+        self.0.multi_get(keys.iter().map(|key| key.key()))
+            .into_iter()
+            .map(|result| Ok(result?.is_some()))
+            .collect()
+    }
+
+    fn for_each(&self, predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<bool> {
+        for (key, value) in self.0.iterator(IteratorMode::Start) {
+            if !call_predicate(predicate, key.as_ref(), value.as_ref())? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_>> {
+        Ok(Box::new(self.0.iterator(IteratorMode::Start).map(into_item)))
+    }
+
+    fn for_each_from(
+        &self,
+        start: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        iterate_all(self.0.iterator(IteratorMode::From(start, Direction::Forward)), predicate)
+    }
+
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        iterate_prefix(self.0.iterator(IteratorMode::From(prefix, Direction::Forward)), prefix, predicate)
+    }
+
+    fn for_each_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        if start >= end {
+            return Ok(true);
+        }
+        let iterator = self.0.iterator_opt(IteratorMode::From(start, Direction::Forward), range_read_options(start, end));
+        iterate_all(iterator, predicate)
+    }
+}
+
+/// Snapshot of RocksDB, which keeps the database alive instead of borrowing it
 #[derive(Debug)]
-pub struct RocksDb {
-    db: Arc<Option<DB>>,
-    path: PathBuf,
+pub struct OwnedSnapshot {
+    // Declared first, so it is dropped before the database it refers to
+    snapshot: RocksDbSnapshot<'static>,
+    _db: Arc<Option<DB>>,
 }
 
-impl RocksDb {
-    /// Creates new instance with given path
-    pub fn with_path(path: impl AsRef<Path>) -> Self {
-        Self::with_options(path, |_| {})
+impl Kvc for OwnedSnapshot {
+    fn len(&self) -> Result<usize> {
+        self.snapshot.len()
     }
 
-    /// Creates new instance with given path and ability to additionally configure options
-    pub fn with_options(path: impl AsRef<Path>, configure_options: impl Fn(&mut Options)) -> Self {
-        let pathbuf = path.as_ref().to_path_buf();
+    fn destroy(&mut self) -> Result<()> {
+        self.snapshot.destroy()
+    }
+}
 
-        let mut options = Options::default();
-        options.create_if_missing(true);
-        options.set_max_total_wal_size(1024 * 1024 * 1024);
+impl<K: DbKey + Send + Sync> KvcReadable<K> for OwnedSnapshot {
+    fn try_get(&self, key: &K) -> Result<Option<DbSlice>> {
+        self.snapshot.try_get(key)
+    }
 
-        configure_options(&mut options);
+    fn get_multi(&self, keys: &[K]) -> Result<Vec<Option<DbSlice>>> {
+        self.snapshot.get_multi(keys)
+    }
 
-        Self {
-            db: Arc::new(Some(DB::open(&options, path)
-                .expect("Cannot open DB"))),
-            path: pathbuf
-        }
+    fn contains_multi(&self, keys: &[K]) -> Result<Vec<bool>> {
+        self.snapshot.contains_multi(keys)
     }
 
-    pub(crate) fn db(&self) -> Result<&DB> {
+    fn for_each(&self, predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<bool> {
+        KvcReadable::<K>::for_each(&self.snapshot, predicate)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_>> {
+        KvcReadable::<K>::iter(&self.snapshot)
+    }
+
+    fn for_each_from(
+        &self,
+        start: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        KvcReadable::<K>::for_each_from(&self.snapshot, start, predicate)
+    }
+
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        KvcReadable::<K>::for_each_prefix(&self.snapshot, prefix, predicate)
+    }
+
+    fn for_each_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        KvcReadable::<K>::for_each_range(&self.snapshot, start, end, predicate)
+    }
+}
+
+/// Key-value collection over a column family of RocksDB
+#[derive(Debug)]
+pub struct RocksDbCf {
+    db: Arc<Option<DB>>,
+    name: String,
+    snapshots: Arc<SnapshotTracker>,
+    read_verification: ChecksumVerification,
+    read_only: bool,
+}
+
+impl RocksDbCf {
+    /// Column family name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn db(&self) -> Result<&DB> {
         if let Some(ref db) = *self.db {
             Ok(db)
         } else {
             Err(StorageError::DbIsDropped)?
         }
     }
+
+    /// Counts items by full iteration. Unlike len(), which is estimated, the result is exact
+    pub fn exact_len(&self) -> Result<usize> {
+        let (db, cf) = self.cf_handle()?;
+        Ok(db.iterator_cf(cf, IteratorMode::Start).count())
+    }
+
+    /// Flushes memtable of column family into SST files
+    pub fn flush(&self) -> Result<()> {
+        let (db, cf) = self.cf_handle()?;
+        Ok(db.flush_cf(cf)?)
+    }
+
+    /// Compacts keys of column family in inclusive range [start, end]; None bounds mean beginning
+    /// and end of the keyspace. Fails with `StorageError::ReadOnly` on read-only instances
+    pub fn compact_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<()> {
+        let (db, cf) = self.writable_cf_handle()?;
+        db.compact_range_cf(cf, start, end);
+        Ok(())
+    }
+
+    fn writable_cf_handle(&self) -> Result<(&DB, &ColumnFamily)> {
+        if self.read_only {
+            Err(StorageError::ReadOnly)?
+        }
+        self.cf_handle()
+    }
+
+    fn cf_handle(&self) -> Result<(&DB, &ColumnFamily)> {
+        let db = self.db()?;
+        let cf = db.cf_handle(&self.name)
+            .ok_or_else(|| error!("Column family {} is not opened", self.name))?;
+
+        Ok((db, cf))
+    }
 }
 
-/// Implementation of key-value collection for RocksDB
-impl Kvc for RocksDb {
+/// Implementation of key-value collection for column family of RocksDB
+impl Kvc for RocksDbCf {
     fn len(&self) -> Result<usize> {
-        fail!("len() is not supported for RocksDb")
+        let (db, cf) = self.cf_handle()?;
+        estimate_num_keys(db, Some(cf))
     }
 
     fn destroy(&mut self) -> Result<()> {
-        if Arc::get_mut(&mut self.db)
-            .ok_or(StorageError::HasActiveTransactions)?
-            .is_some()
-        {
-            self.db = Arc::new(None);
-        }
-
-        Ok(DB::destroy(&Options::default(), &self.path)?)
+        fail!("destroy() is not supported for column families")
     }
 }
 
-/// Implementation of readable key-value collection for RocksDB. Actual implementation is blocking.
-impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDb {
+/// Implementation of readable key-value collection for column family of RocksDB
+impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDbCf {
     fn try_get(&self, key: &K) -> Result<Option<DbSlice>> {
-        Ok(self.db()?.get_pinned(key.key())?
+        let (db, cf) = self.cf_handle()?;
+        let value = match self.read_verification.read_options() {
+            Some(read_options) => db.get_pinned_cf_opt(cf, key.key(), &read_options),
+            None => db.get_pinned_cf(cf, key.key()),
+        };
+        Ok(value.map_err(storage_error)?
             .map(|value| value.into()))
     }
 
+    fn may_exist(&self, key: &K) -> bool {
+        self.cf_handle().map_or(true, |(db, cf)| db.key_may_exist_cf(cf, key.key()))
+    }
+
+    fn get_multi(&self, keys: &[K]) -> Result<Vec<Option<DbSlice>>> {
+        let (db, cf) = self.cf_handle()?;
+        db.multi_get_cf(keys.iter().map(|key| (cf, key.key())))
+            .into_iter()
+            .map(|result| Ok(result?.map(|value| value.into())))
+            .collect()
+    }
+
+    fn contains_multi(&self, keys: &[K]) -> Result<Vec<bool>> {
+        let (db, cf) = self.cf_handle()?;
+        db.multi_get_cf(keys.iter().map(|key| (cf, key.key())))
+            .into_iter()
+            .map(|result| Ok(result?.is_some()))
+            .collect()
+    }
+
     fn for_each(&self, predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<bool> {
-        for (key, value) in self.db()?.iterator(IteratorMode::Start) {
-            if !predicate(key.as_ref(), value.as_ref())? {
+        let (db, cf) = self.cf_handle()?;
+        for (key, value) in db.iterator_cf(cf, IteratorMode::Start) {
+            if !call_predicate(predicate, key.as_ref(), value.as_ref())? {
                 return Ok(false);
             }
         }
         Ok(true)
     }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_>> {
+        let (db, cf) = self.cf_handle()?;
+        Ok(Box::new(db.iterator_cf(cf, IteratorMode::Start).map(into_item)))
+    }
+
+    fn for_each_from(
+        &self,
+        start: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        let (db, cf) = self.cf_handle()?;
+        iterate_all(db.iterator_cf(cf, IteratorMode::From(start, Direction::Forward)), predicate)
+    }
+
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        let (db, cf) = self.cf_handle()?;
+        iterate_prefix(db.iterator_cf(cf, IteratorMode::From(prefix, Direction::Forward)), prefix, predicate)
+    }
+
+    fn for_each_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        if start >= end {
+            return Ok(true);
+        }
+        let (db, cf) = self.cf_handle()?;
+        let iterator = db.iterator_cf_opt(cf, range_read_options(start, end), IteratorMode::From(start, Direction::Forward));
+        iterate_all(iterator, predicate)
+    }
 }
 
-/// Implementation of writable key-value collection for RocksDB. Actual implementation is blocking.
-impl<K: DbKey + Send + Sync> KvcWriteable<K> for RocksDb {
+/// Implementation of writable key-value collection for column family of RocksDB
+impl<K: DbKey + Send + Sync> KvcWriteable<K> for RocksDbCf {
     fn put(&self, key: &K, value: &[u8]) -> Result<()> {
-        self.db()?.put(key.key(), value)
-            .map_err(|err| err.into())
+        let (db, cf) = self.writable_cf_handle()?;
+        db.put_cf(cf, key.key(), value)
+            .map_err(storage_error)
     }
 
     fn delete(&self, key: &K) -> Result<()> {
-        self.db()?.delete(key.key())
-            .map_err(|err| err.into())
+        let (db, cf) = self.writable_cf_handle()?;
+        db.delete_cf(cf, key.key())
+            .map_err(storage_error)
+    }
+
+    fn merge(&self, key: &K, operand: &[u8]) -> Result<()> {
+        let (db, cf) = self.writable_cf_handle()?;
+        db.merge_cf(cf, key.key(), operand)
+            .map_err(storage_error)
+    }
+
+    fn delete_range(&self, from: &[u8], to: &[u8]) -> Result<()> {
+        let (db, cf) = self.writable_cf_handle()?;
+        if from >= to {
+            return Ok(());
+        }
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(cf, from, to);
+        db.write(batch)
+            .map_err(storage_error)
     }
 }
 
-/// Implementation of support for take snapshots for RocksDB.
-impl<K: DbKey + Send + Sync> KvcSnapshotable<K> for RocksDb {
+/// Implementation of support for take snapshots for column family of RocksDB
+impl<K: DbKey + Send + Sync> KvcSnapshotable<K> for RocksDbCf {
     fn snapshot<'db>(&'db self) -> Result<Arc<dyn KvcReadable<K> + 'db>> {
-        Ok(Arc::new(RocksDbSnapshot(self.db()?.snapshot())))
+        let (db, cf) = self.cf_handle()?;
+        let guard = SnapshotGuard::new(Arc::clone(&self.snapshots));
+        Ok(Arc::new(RocksDbCfSnapshot { snapshot: db.snapshot(), db, cf, _guard: guard }))
     }
 }
 
-struct RocksDbSnapshot<'db>(Snapshot<'db>);
+struct RocksDbCfSnapshot<'db> {
+    snapshot: Snapshot<'db>,
+    db: &'db DB,
+    cf: &'db ColumnFamily,
+    _guard: SnapshotGuard,
+}
 
-impl Debug for RocksDbSnapshot<'_> {
+impl Debug for RocksDbCfSnapshot<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("[snapshot]")
+        f.write_str("[cf snapshot]")
     }
 }
 
-impl Kvc for RocksDbSnapshot<'_> {
+impl Kvc for RocksDbCfSnapshot<'_> {
     fn len(&self) -> Result<usize> {
-        fail!("len() is not supported for RocksDb")
+        estimate_num_keys(self.db, Some(self.cf))
     }
 
     fn destroy(&mut self) -> Result<()> {
@@ -122,25 +1321,223 @@ impl Kvc for RocksDbSnapshot<'_> {
     }
 }
 
-impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDbSnapshot<'_> {
+impl<K: DbKey + Send + Sync> KvcReadable<K> for RocksDbCfSnapshot<'_> {
     fn try_get(&self, key: &K) -> Result<Option<DbSlice>> {
-        Ok(self.0.get(key.key())?
+        Ok(self.snapshot.get_cf(self.cf, key.key()).map_err(storage_error)?
             .map(|value| value.into()))
     }
 
+    fn get_multi(&self, keys: &[K]) -> Result<Vec<Option<DbSlice>>> {
+        self.snapshot.multi_get_cf(keys.iter().map(|key| (self.cf, key.key())))
+            .into_iter()
+            .map(|result| Ok(result?.map(|value| value.into())))
+            .collect()
+    }
+
+    fn contains_multi(&self, keys: &[K]) -> Result<Vec<bool>> {
+        self.snapshot.multi_get_cf(keys.iter().map(|key| (self.cf, key.key())))
+            .into_iter()
+            .map(|result| Ok(result?.is_some()))
+            .collect()
+    }
+
     fn for_each(&self, predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<bool> {
-        for (key, value) in self.0.iterator(IteratorMode::Start) {
-            if !predicate(key.as_ref(), value.as_ref())? {
+        for (key, value) in self.snapshot.iterator_cf(self.cf, IteratorMode::Start) {
+            if !call_predicate(predicate, key.as_ref(), value.as_ref())? {
                 return Ok(false);
             }
         }
         Ok(true)
     }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_>> {
+        Ok(Box::new(self.snapshot.iterator_cf(self.cf, IteratorMode::Start).map(into_item)))
+    }
+
+    fn for_each_from(
+        &self,
+        start: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        iterate_all(self.snapshot.iterator_cf(self.cf, IteratorMode::From(start, Direction::Forward)), predicate)
+    }
+
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        let iterator = self.snapshot.iterator_cf(self.cf, IteratorMode::From(prefix, Direction::Forward));
+        iterate_prefix(iterator, prefix, predicate)
+    }
+
+    fn for_each_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        if start >= end {
+            return Ok(true);
+        }
+        let iterator = self.snapshot.iterator_cf_opt(
+            self.cf,
+            range_read_options(start, end),
+            IteratorMode::From(start, Direction::Forward)
+        );
+        iterate_all(iterator, predicate)
+    }
+}
+
+/// Converts RocksDB error, distinguishing I/O errors and data corruption
+fn storage_error(err: rocksdb::Error) -> failure::Error {
+    match err.kind() {
+        ErrorKind::IOError => StorageError::Io(err.into_string()).into(),
+        ErrorKind::Corruption => StorageError::Corruption(err.into_string()).into(),
+        _ => err.into(),
+    }
+}
+
+fn write_dump_field(writer: &mut dyn Write, data: &[u8]) -> Result<()> {
+    let len = u32::try_from(data.len())
+        .map_err(|_| error!("Too long field to dump: {} bytes", data.len()))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// Reads length-prefixed field of dump; end of input before the field is allowed, if requested
+fn read_dump_field(reader: &mut dyn Read, allow_eof: bool) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..])? {
+            0 if read == 0 && allow_eof => return Ok(None),
+            0 => fail!("Unexpected end of dump"),
+            n => read += n,
+        }
+    }
+    let mut data = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Gets estimated count of keys in database or its column family
+fn estimate_num_keys(db: &DB, cf: Option<&ColumnFamily>) -> Result<usize> {
+    const PROPERTY: &str = "rocksdb.estimate-num-keys";
+    let value = match cf {
+        Some(cf) => db.property_int_value_cf(cf, PROPERTY)?,
+        None => db.property_int_value(PROPERTY)?,
+    };
+
+    Ok(value.unwrap_or(0) as usize)
+}
+
+/// Creates read options for scrubbing: checksums are verified and read blocks don't evict cache
+fn scrub_read_options() -> ReadOptions {
+    let mut read_options = ReadOptions::default();
+    read_options.set_verify_checksums(true);
+    read_options.fill_cache(false);
+
+    read_options
+}
+
+/// Creates read options limiting iteration by range [start, end)
+fn range_read_options(start: &[u8], end: &[u8]) -> ReadOptions {
+    let mut read_options = ReadOptions::default();
+    read_options.set_iterate_lower_bound(start.to_vec());
+    read_options.set_iterate_upper_bound(end.to_vec());
+
+    read_options
+}
+
+/// Converts owned iterator item into key and value vectors
+fn into_item((key, value): (Box<[u8]>, Box<[u8]>)) -> Result<(Vec<u8>, Vec<u8>)> {
+    Ok((key.into_vec(), value.into_vec()))
+}
+
+/// Runs predicate for all items of iterator
+fn iterate_all(
+    iterator: impl Iterator<Item = (Box<[u8]>, Box<[u8]>)>,
+    predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>,
+) -> Result<bool> {
+    for (key, value) in iterator {
+        if !call_predicate(predicate, key.as_ref(), value.as_ref())? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Iterates range of keys with first byte belonging to given shard, until `stop` is set
+fn for_each_in_shard(
+    db: &DB,
+    shard: usize,
+    shards: usize,
+    mut predicate: Box<dyn FnMut(&[u8], &[u8]) -> Result<bool>>,
+    stop: &AtomicBool,
+) -> Result<bool> {
+    let first_byte = |shard: usize| (shard * 256 / shards) as u8;
+    let mut read_options = ReadOptions::default();
+    // First range has no lower bound to include empty key, last one has no upper
+    let lower = if shard > 0 { vec![first_byte(shard)] } else { Vec::new() };
+    if shard + 1 < shards {
+        read_options.set_iterate_upper_bound(vec![first_byte(shard + 1)]);
+    }
+
+    for (key, value) in db.iterator_opt(IteratorMode::From(&lower, Direction::Forward), read_options) {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        match call_predicate(&mut *predicate, key.as_ref(), value.as_ref()) {
+            Ok(true) => {},
+            result => {
+                stop.store(true, Ordering::Relaxed);
+                return result;
+            },
+        }
+    }
+
+    Ok(true)
+}
+
+/// Runs predicate for items of iterator positioned at the prefix, while keys start with it
+fn iterate_prefix(
+    iterator: impl Iterator<Item = (Box<[u8]>, Box<[u8]>)>,
+    prefix: &[u8],
+    predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>,
+) -> Result<bool> {
+    for (key, value) in iterator {
+        if !key.starts_with(prefix) {
+            break;
+        }
+        if !call_predicate(predicate, key.as_ref(), value.as_ref())? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Calls for_each predicate, adding hex of current key to its error
+fn call_predicate(
+    predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>,
+    key: &[u8],
+    value: &[u8],
+) -> Result<bool> {
+    predicate(key, value)
+        .map_err(|err| err.context(format!("for_each() predicate failed at key {}", hex::encode(key))).into())
 }
 
 /// Implementation of transaction support for key-value collection for RocksDB.
 impl<K: DbKey + Send + Sync> KvcTransactional<K> for RocksDb {
     fn begin_transaction(&self) -> Result<Box<dyn KvcTransaction<K>>> {
+        if self.read_only {
+            Err(StorageError::ReadOnly)?
+        }
         Ok(Box::new(RocksDbTransaction::new(Arc::clone(&self.db))))
     }
 }
@@ -148,45 +1545,266 @@ impl<K: DbKey + Send + Sync> KvcTransactional<K> for RocksDb {
 pub struct RocksDbTransaction {
     db: Arc<Option<DB>>,
     batch: Mutex<WriteBatch>,
+    max_bytes: Option<usize>,
+    flush_error: Mutex<Option<failure::Error>>,
 }
 
 /// Implementation of transaction for key-value collection for RocksDB.
 impl RocksDbTransaction {
     fn new(db: Arc<Option<DB>>) -> Self {
+        Self::with_max_bytes(db, None)
+    }
+
+    fn with_max_bytes(db: Arc<Option<DB>>, max_bytes: Option<usize>) -> Self {
         Self {
             db,
-            batch: Mutex::new(WriteBatch::default())
+            batch: Mutex::new(WriteBatch::default()),
+            max_bytes,
+            flush_error: Mutex::new(None),
+        }
+    }
+}
+
+impl RocksDbTransaction {
+    fn write_batch(&self, batch: WriteBatch, sync: bool) -> Result<()> {
+        if let Some(ref db) = *self.db {
+            let mut write_options = WriteOptions::default();
+            write_options.set_sync(sync);
+            db.write_opt(batch, &write_options)
+                .map_err(storage_error)
+        } else {
+            Err(StorageError::DbIsDropped)?
+        }
+    }
+
+    fn flush_if_exceeded(&self, batch: &mut WriteBatch) {
+        if let Some(max_bytes) = self.max_bytes {
+            if batch.size_in_bytes() >= max_bytes {
+                let mut flush_error = self.flush_error.lock().unwrap();
+                if flush_error.is_none() {
+                    if let Err(err) = self.write_batch(std::mem::take(batch), false) {
+                        *flush_error = Some(err);
+                    }
+                } else {
+                    batch.clear();
+                }
+            }
+        }
+    }
+
+    fn commit_with_sync(self: Box<Self>, sync: bool) -> Result<()> {
+        if let Some(err) = self.flush_error.lock().unwrap().take() {
+            return Err(err);
         }
+        let batch = std::mem::take(&mut *self.batch.lock().unwrap());
+        self.write_batch(batch, sync)
     }
 }
 
 impl<K: DbKey + Send + Sync> KvcTransaction<K> for RocksDbTransaction {
+    fn put(&self, key: &K, value: &[u8]) {
+        let mut batch = self.batch.lock().unwrap();
+        batch.put(key.key(), value);
+        self.flush_if_exceeded(&mut batch);
+    }
+
+    fn delete(&self, key: &K) {
+        let mut batch = self.batch.lock().unwrap();
+        batch.delete(key.key());
+        self.flush_if_exceeded(&mut batch);
+    }
+
+    fn clear(&self) {
+        self.batch.lock().unwrap()
+            .clear();
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        self.commit_with_sync(false)
+    }
+
+    fn commit_sync(self: Box<Self>) -> Result<()> {
+        self.commit_with_sync(true)
+    }
+
+    fn len(&self) -> usize {
+        self.batch.lock().unwrap().len()
+    }
+}
+
+/// Transaction over several column families of RocksDB. Unlike `KvcTransaction`, operations
+/// fail at once if column family is not of the transaction's database
+pub struct RocksDbMultiCfTransaction {
+    inner: RocksDbTransaction,
+}
+
+impl RocksDbMultiCfTransaction {
+    /// Adds put operation into transaction for default column family
+    pub fn put<K: DbKey>(&self, key: &K, value: &[u8]) {
+        self.inner.batch.lock().unwrap()
+            .put(key.key(), value);
+    }
+
+    /// Adds delete operation into transaction for default column family
+    pub fn delete<K: DbKey>(&self, key: &K) {
+        self.inner.batch.lock().unwrap()
+            .delete(key.key());
+    }
+
+    /// Adds put operation into transaction for given column family
+    pub fn put_cf<K: DbKey>(&self, cf: &RocksDbCf, key: &K, value: &[u8]) -> Result<()> {
+        let (_, handle) = self.cf_handle(cf)?;
+        self.inner.batch.lock().unwrap()
+            .put_cf(handle, key.key(), value);
+        Ok(())
+    }
+
+    /// Adds delete operation into transaction for given column family
+    pub fn delete_cf<K: DbKey>(&self, cf: &RocksDbCf, key: &K) -> Result<()> {
+        let (_, handle) = self.cf_handle(cf)?;
+        self.inner.batch.lock().unwrap()
+            .delete_cf(handle, key.key());
+        Ok(())
+    }
+
+    /// Removes all pending operations from transaction
+    pub fn clear(&self) {
+        self.inner.batch.lock().unwrap().clear();
+    }
+
+    /// Gets pending operations count
+    pub fn len(&self) -> usize {
+        self.inner.batch.lock().unwrap().len()
+    }
+
+    /// Returns true if pending operation count is zero; otherwise false
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Commits operations of all column families atomically
+    pub fn commit(self) -> Result<()> {
+        Box::new(self.inner).commit_with_sync(false)
+    }
+
+    /// Commits operations of all column families atomically, waiting for data being synced to disk
+    pub fn commit_sync(self) -> Result<()> {
+        Box::new(self.inner).commit_with_sync(true)
+    }
+
+    fn cf_handle<'a>(&self, cf: &'a RocksDbCf) -> Result<(&'a DB, &'a ColumnFamily)> {
+        if !Arc::ptr_eq(&self.inner.db, &cf.db) {
+            fail!("Column family {} belongs to another database", cf.name());
+        }
+        cf.writable_cf_handle()
+    }
+}
+
+/// Implementation of optimistic transaction support for RocksDB. Conflicts are detected by comparing
+/// values read in the transaction with the actual ones at commit; commits of optimistic transactions
+/// of the same instance are serialized, so of several racing transactions at most one succeeds.
+/// Plain writes and batch transactions are not serialized with the commit, so the write racing
+/// with the very commit (not with the transaction as a whole) may stay undetected.
+impl<K: DbKey + Send + Sync> KvcOptimisticTransactional<K> for RocksDb {
+    fn begin_optimistic_transaction(&self) -> Result<Box<dyn KvcReadableTransaction<K>>> {
+        if self.read_only {
+            Err(StorageError::ReadOnly)?
+        }
+        Ok(Box::new(RocksDbOptimisticTransaction::new(
+            Arc::clone(&self.db),
+            Arc::clone(&self.commit_lock)
+        )))
+    }
+}
+
+pub struct RocksDbOptimisticTransaction {
+    db: Arc<Option<DB>>,
+    commit_lock: Arc<Mutex<()>>,
+    batch: Mutex<WriteBatch>,
+    reads: Mutex<FnvHashMap<Vec<u8>, Option<Vec<u8>>>>,
+    writes: Mutex<FnvHashMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+/// Implementation of optimistic transaction for key-value collection for RocksDB.
+impl RocksDbOptimisticTransaction {
+    fn new(db: Arc<Option<DB>>, commit_lock: Arc<Mutex<()>>) -> Self {
+        Self {
+            db,
+            commit_lock,
+            batch: Mutex::new(WriteBatch::default()),
+            reads: Mutex::new(FnvHashMap::default()),
+            writes: Mutex::new(FnvHashMap::default()),
+        }
+    }
+
+    fn db(&self) -> Result<&DB> {
+        self.db.as_ref().as_ref()
+            .ok_or_else(|| StorageError::DbIsDropped.into())
+    }
+
+    fn commit_with_sync(self: Box<Self>, sync: bool) -> Result<()> {
+        let db = self.db()?;
+        let _guard = self.commit_lock.lock().unwrap();
+        for (key, value) in self.reads.lock().unwrap().iter() {
+            let actual = db.get_pinned(key).map_err(storage_error)?;
+            if actual.as_deref() != value.as_deref() {
+                Err(StorageError::TransactionConflict(hex::encode(key)))?
+            }
+        }
+        let batch = std::mem::take(&mut *self.batch.lock().unwrap());
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(sync);
+        db.write_opt(batch, &write_options)
+            .map_err(storage_error)
+    }
+}
+
+impl<K: DbKey + Send + Sync> KvcTransaction<K> for RocksDbOptimisticTransaction {
     fn put(&self, key: &K, value: &[u8]) {
         self.batch.lock().unwrap()
             .put(key.key(), value);
+        self.writes.lock().unwrap()
+            .insert(key.key().to_vec(), Some(value.to_vec()));
     }
 
     fn delete(&self, key: &K) {
         self.batch.lock().unwrap()
             .delete(key.key());
+        self.writes.lock().unwrap()
+            .insert(key.key().to_vec(), None);
     }
 
     fn clear(&self) {
         self.batch.lock().unwrap()
             .clear();
+        self.writes.lock().unwrap()
+            .clear();
     }
 
     fn commit(self: Box<Self>) -> Result<()> {
-        let batch = self.batch.into_inner().unwrap();
-        if let Some(ref db) = *self.db {
-            db.write(batch)
-            .map_err(|err| err.into())
-        } else {
-            Err(StorageError::DbIsDropped)?
-        }
+        self.commit_with_sync(false)
+    }
+
+    fn commit_sync(self: Box<Self>) -> Result<()> {
+        self.commit_with_sync(true)
     }
 
     fn len(&self) -> usize {
         self.batch.lock().unwrap().len()
     }
 }
+
+impl<K: DbKey + Send + Sync> KvcReadableTransaction<K> for RocksDbOptimisticTransaction {
+    fn try_get(&self, key: &K) -> Result<Option<DbSlice>> {
+        if let Some(value) = self.writes.lock().unwrap().get(key.key()) {
+            return Ok(value.clone().map(|value| value.into()));
+        }
+        let mut reads = self.reads.lock().unwrap();
+        if let Some(value) = reads.get(key.key()) {
+            return Ok(value.clone().map(|value| value.into()));
+        }
+        let value = self.db()?.get(key.key()).map_err(storage_error)?;
+        reads.insert(key.key().to_vec(), value.clone());
+        Ok(value.map(|value| value.into()))
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocksdb::backup::{BackupEngine, BackupEngineOptions, RestoreOptions};
+
+use ton_types::{error, Result};
+
+use crate::db::rocksdb::RocksDb;
+use crate::error::StorageError;
+
+/// Description of the backup kept by backup engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupInfo {
+    id: u32,
+    timestamp: SystemTime,
+    size: u64,
+}
+
+impl BackupInfo {
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Time of the backup creation
+    pub const fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// Size of the backup files in bytes
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Incremental backups of RocksDB databases, kept in separate directory
+pub struct RocksDbBackup {
+    engine: Mutex<BackupEngine>,
+    path: PathBuf,
+}
+
+impl Debug for RocksDbBackup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RocksDbBackup")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl RocksDbBackup {
+    /// Opens backup engine with given directory, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let engine = BackupEngine::open(&BackupEngineOptions::default(), &path)
+            .map_err(|err| error!("Cannot open backup engine {}: {}", path.display(), err))?;
+
+        Ok(Self { engine: Mutex::new(engine), path })
+    }
+
+    /// Gets directory of the backup engine
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates new backup of the database; only files absent in previous backups are copied.
+    /// Memtables are flushed before, so WAL is not needed for restore
+    pub fn create_backup(&self, db: &RocksDb) -> Result<()> {
+        self.engine.lock().unwrap()
+            .create_new_backup_flush(db.db()?, true)?;
+        Ok(())
+    }
+
+    /// Lists backups from oldest to newest
+    pub fn list_backups(&self) -> Vec<BackupInfo> {
+        self.engine.lock().unwrap()
+            .get_backup_info()
+            .into_iter()
+            .map(|info| BackupInfo {
+                id: info.backup_id,
+                timestamp: UNIX_EPOCH + Duration::from_secs(info.timestamp.max(0) as u64),
+                size: info.size,
+            })
+            .collect()
+    }
+
+    /// Deletes all backups but `keep` newest ones
+    pub fn purge_old_backups(&self, keep: usize) -> Result<()> {
+        self.engine.lock().unwrap()
+            .purge_old_backups(keep)?;
+        Ok(())
+    }
+
+    /// Restores the newest backup into given database directory, which must be absent or empty
+    pub fn restore_from_latest(&self, db_path: impl AsRef<Path>) -> Result<()> {
+        let db_path = db_path.as_ref();
+        Self::check_restore_target(db_path)?;
+        self.engine.lock().unwrap()
+            .restore_from_latest_backup(db_path, db_path, &RestoreOptions::default())?;
+        Ok(())
+    }
+
+    /// Restores backup with given id into given database directory, which must be absent or empty
+    pub fn restore_from(&self, backup_id: u32, db_path: impl AsRef<Path>) -> Result<()> {
+        let db_path = db_path.as_ref();
+        Self::check_restore_target(db_path)?;
+        self.engine.lock().unwrap()
+            .restore_from_backup(db_path, db_path, &RestoreOptions::default(), backup_id)?;
+        Ok(())
+    }
+
+    /// Refuses to restore over existing data, in particular over database currently opened
+    fn check_restore_target(db_path: &Path) -> Result<()> {
+        if db_path.exists() && std::fs::read_dir(db_path)?.next().is_some() {
+            Err(StorageError::DirectoryNotEmpty(db_path.display().to_string()))?
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+use std::fmt::{Debug, Formatter};
+
+use rocksdb::{
+    BlockBasedOptions, ChecksumType, DBCompactionStyle, DBRecoveryMode, MemtableFactory, MergeOperands, Options,
+    ReadOptions, UniversalCompactOptions
+};
+
+/// RocksDB defaults of rate limiter
+const RATE_LIMITER_REFILL_PERIOD_US: i64 = 100_000;
+const RATE_LIMITER_FAIRNESS: i32 = 10;
+
+/// Memtable representation
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemtableKind {
+    /// Default skiplist: ordered, supports concurrent writes and range scans
+    SkipList,
+    /// Hash table of skiplists keyed by key prefix. Requires prefix extractor, without it
+    /// degenerates into a single skiplist; range scans across prefixes are slow
+    HashSkipList {
+        bucket_count: usize,
+        height: i32,
+        branching_factor: i32,
+    },
+    /// Unsorted vector sorted on flush: fastest bulk inserts, but point lookups in memtable
+    /// are linear, so it suits write-only loads
+    Vector,
+}
+
+/// Mode of WAL replaying on opening after unclean shutdown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WalRecoveryMode {
+    /// Fails to open on torn last record, unless it is caused by unfinished write. Loses nothing
+    TolerateCorruptedTailRecords,
+    /// Fails to open on any corruption in WAL. Loses nothing, but may require manual repair
+    AbsoluteConsistency,
+    /// Stops replaying at the first corrupted record, so the database is consistent as of
+    /// a point in time. RocksDB default; loses writes after the corruption
+    PointInTime,
+    /// Skips any corrupted records and replays the rest. Loses corrupted writes and may
+    /// leave the database inconsistent (partially applied batches)
+    SkipAnyCorruptedRecord,
+}
+
+impl From<WalRecoveryMode> for DBRecoveryMode {
+    fn from(mode: WalRecoveryMode) -> Self {
+        match mode {
+            WalRecoveryMode::TolerateCorruptedTailRecords => DBRecoveryMode::TolerateCorruptedTailRecords,
+            WalRecoveryMode::AbsoluteConsistency => DBRecoveryMode::AbsoluteConsistency,
+            WalRecoveryMode::PointInTime => DBRecoveryMode::PointInTime,
+            WalRecoveryMode::SkipAnyCorruptedRecord => DBRecoveryMode::SkipAnyCorruptedRecord,
+        }
+    }
+}
+
+/// Checksum algorithm of blocks written into new SST files. Existing files keep their own one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChecksumKind {
+    /// RocksDB default, hardware accelerated on most CPUs
+    Crc32c,
+    XxHash,
+    /// Fewer undetected corruptions than 32-bit checksums
+    XxHash64,
+}
+
+impl From<ChecksumKind> for ChecksumType {
+    fn from(kind: ChecksumKind) -> Self {
+        match kind {
+            ChecksumKind::Crc32c => ChecksumType::CRC32c,
+            ChecksumKind::XxHash => ChecksumType::xxHash,
+            ChecksumKind::XxHash64 => ChecksumType::xxHash64,
+        }
+    }
+}
+
+/// Verification of block checksums on point reads
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChecksumVerification {
+    /// Blocks are not verified. Fastest, corruption is returned as data
+    Skip,
+    /// RocksDB default: blocks are verified when read from files; blocks taken from block cache
+    /// were verified when cached
+    Verify,
+    /// Blocks are verified and read bypassing block cache, so every read checks on-disk data.
+    /// Each read costs at least one file read and checksum computation, which may reduce read
+    /// throughput several times for cache-friendly loads
+    Paranoid,
+}
+
+impl Default for ChecksumVerification {
+    fn default() -> Self {
+        ChecksumVerification::Verify
+    }
+}
+
+impl ChecksumVerification {
+    /// Builds read options for the mode; None means default ones
+    pub(crate) fn read_options(self) -> Option<ReadOptions> {
+        let mut read_options = ReadOptions::default();
+        match self {
+            ChecksumVerification::Verify => return None,
+            ChecksumVerification::Skip => read_options.set_verify_checksums(false),
+            ChecksumVerification::Paranoid => {
+                read_options.set_verify_checksums(true);
+                read_options.fill_cache(false);
+            },
+        }
+
+        Some(read_options)
+    }
+}
+
+/// Merge function: combines existing value (if any) of the key with merge operands;
+/// None reports failure of merge
+pub type MergeFunction = fn(&[u8], Option<&[u8]>, &mut MergeOperands) -> Option<Vec<u8>>;
+
+/// Merge operator registered on opening of database
+#[derive(Clone)]
+pub struct MergeOperator {
+    name: &'static str,
+    full_merge: MergeFunction,
+    partial_merge: MergeFunction,
+}
+
+impl Debug for MergeOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MergeOperator({})", self.name)
+    }
+}
+
+/// Builder of RocksDB options applied on top of default ones
+#[derive(Debug, Clone, Default)]
+pub struct RocksDbOptions {
+    max_total_wal_size: Option<u64>,
+    wal_size_limit_mb: Option<u64>,
+    wal_ttl_seconds: Option<u64>,
+    max_subcompactions: Option<u32>,
+    compaction_readahead_size: Option<usize>,
+    enable_blob_files: Option<bool>,
+    min_blob_size: Option<u64>,
+    blob_file_size: Option<u64>,
+    universal_compaction: Option<(i32, i32)>,
+    compact_on_deletion: Option<(usize, usize, f64)>,
+    memtable: Option<MemtableKind>,
+    max_open_files: Option<i32>,
+    enable_pipelined_write: Option<bool>,
+    allow_concurrent_memtable_write: Option<bool>,
+    merge_operator: Option<MergeOperator>,
+    bloom_filter_bits_per_key: Option<i32>,
+    wal_recovery_mode: Option<WalRecoveryMode>,
+    paranoid_checks: Option<bool>,
+    checksum_type: Option<ChecksumKind>,
+    read_verification: ChecksumVerification,
+    write_rate_limit: Option<i64>,
+    create_missing_column_families: bool,
+    enable_statistics: bool,
+}
+
+impl RocksDbOptions {
+    /// Creates new builder without any overridden options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits total size of WAL files. Exceeding it forces flush of column families
+    /// whose data is held by the oldest WAL files
+    pub fn set_max_total_wal_size(mut self, size: u64) -> Self {
+        self.max_total_wal_size = Some(size);
+        self
+    }
+
+    /// Limits total size of archived WAL files in megabytes
+    pub fn set_wal_size_limit_mb(mut self, size_mb: u64) -> Self {
+        self.wal_size_limit_mb = Some(size_mb);
+        self
+    }
+
+    /// Sets time to live of archived WAL files in seconds
+    pub fn set_wal_ttl_seconds(mut self, seconds: u64) -> Self {
+        self.wal_ttl_seconds = Some(seconds);
+        self
+    }
+
+    /// Sets maximum number of threads a single compaction job may be split into.
+    /// Subcompactions run in the background compaction thread pool, so the value is effectively
+    /// bounded by the number of background jobs
+    pub fn set_max_subcompactions(mut self, count: u32) -> Self {
+        self.max_subcompactions = Some(count);
+        self
+    }
+
+    /// Sets size of readahead used by compaction inputs. Non-zero value makes compaction read
+    /// SST files in large sequential chunks
+    pub fn set_compaction_readahead_size(mut self, size: usize) -> Self {
+        self.compaction_readahead_size = Some(size);
+        self
+    }
+
+    /// Enables storing of large values in blob files outside of LSM tree
+    pub fn set_enable_blob_files(mut self, enable: bool) -> Self {
+        self.enable_blob_files = Some(enable);
+        self
+    }
+
+    /// Sets minimum size of value to be stored in blob file
+    pub fn set_min_blob_size(mut self, size: u64) -> Self {
+        self.min_blob_size = Some(size);
+        self
+    }
+
+    /// Sets size limit of blob files
+    pub fn set_blob_file_size(mut self, size: u64) -> Self {
+        self.blob_file_size = Some(size);
+        self
+    }
+
+    /// Switches to universal compaction style with given size ratio (in percents) and
+    /// maximum size amplification (in percents)
+    pub fn set_universal_compaction(mut self, size_ratio: i32, max_size_amplification_percent: i32) -> Self {
+        self.universal_compaction = Some((size_ratio, max_size_amplification_percent));
+        self
+    }
+
+    /// Enables deletion-triggered compaction: SST file is scheduled for compaction when any
+    /// `window_size` consecutive entries contain at least `num_dels_trigger` tombstones, or when
+    /// ratio of tombstones in the whole file reaches `deletion_ratio` (0 disables the ratio check).
+    /// This is the knob making space of pruned ranges reclaimed soon after deletion
+    pub fn set_compact_on_deletion(mut self, window_size: usize, num_dels_trigger: usize, deletion_ratio: f64) -> Self {
+        self.compact_on_deletion = Some((window_size, num_dels_trigger, deletion_ratio));
+        self
+    }
+
+    /// Sets memtable representation. Non-skiplist memtables don't support concurrent memtable
+    /// writes, so these are disabled for them
+    pub fn set_memtable(mut self, memtable: MemtableKind) -> Self {
+        self.memtable = Some(memtable);
+        self
+    }
+
+    /// Limits number of files kept open by table cache; -1 keeps all files open (unlimited).
+    /// With bounded value idle SST files are closed in LRU order and reopened on access, which
+    /// costs extra reads of index and filter blocks
+    pub fn set_max_open_files(mut self, count: i32) -> Self {
+        self.max_open_files = Some(count);
+        self
+    }
+
+    /// Enables pipelined writes: WAL writing and memtable insertion of consequent write groups
+    /// overlap, which increases multi-threaded write throughput
+    pub fn set_enable_pipelined_write(mut self, enable: bool) -> Self {
+        self.enable_pipelined_write = Some(enable);
+        self
+    }
+
+    /// Allows concurrent memtable writes. Supported by skiplist memtable only, so the option
+    /// is ignored when other memtable representation is set
+    pub fn set_allow_concurrent_memtable_write(mut self, allow: bool) -> Self {
+        self.allow_concurrent_memtable_write = Some(allow);
+        self
+    }
+
+    /// Registers merge operator backing `KvcWriteable::merge()`. Full merge applies operands to
+    /// existing value, partial merge combines operands without it. The name is stored in the
+    /// database, so it must be the same for every opening
+    pub fn set_merge_operator(
+        mut self,
+        name: &'static str,
+        full_merge: MergeFunction,
+        partial_merge: MergeFunction
+    ) -> Self {
+        self.merge_operator = Some(MergeOperator { name, full_merge, partial_merge });
+        self
+    }
+
+    /// Enables bloom filter of block-based tables with given bits per key (10 gives about 1%
+    /// false positives). It lets point reads and `may_exist()` skip SST files without the key
+    pub fn set_bloom_filter(mut self, bits_per_key: i32) -> Self {
+        self.bloom_filter_bits_per_key = Some(bits_per_key);
+        self
+    }
+
+    /// Sets mode of WAL replaying on opening. Not set, RocksDB default (point in time) is used
+    pub fn set_wal_recovery_mode(mut self, mode: WalRecoveryMode) -> Self {
+        self.wal_recovery_mode = Some(mode);
+        self
+    }
+
+    /// Enables or disables aggressive checks of data integrity (on by default). Enabled checks
+    /// make opening fail on corrupted files; disabling them lets damaged database be opened
+    pub fn set_paranoid_checks(mut self, enable: bool) -> Self {
+        self.paranoid_checks = Some(enable);
+        self
+    }
+
+    /// Sets checksum algorithm of blocks of new SST files
+    pub fn set_checksum_type(mut self, kind: ChecksumKind) -> Self {
+        self.checksum_type = Some(kind);
+        self
+    }
+
+    /// Sets verification of block checksums on point reads of the opened database
+    /// (`ChecksumVerification::Verify` by default). Consider `RocksDb::verify_checksum()`
+    /// run periodically as a cheaper alternative to `ChecksumVerification::Paranoid`
+    pub fn set_read_verification(mut self, verification: ChecksumVerification) -> Self {
+        self.read_verification = verification;
+        self
+    }
+
+    pub const fn read_verification(&self) -> ChecksumVerification {
+        self.read_verification
+    }
+
+    /// Limits rate of flush and compaction writes to given bytes per second, so bulk loading
+    /// doesn't saturate the disk. Not set, writes are not throttled. The limit of opened database
+    /// is changed by `RocksDb::set_write_rate_limit()`
+    pub fn set_write_rate_limit(mut self, bytes_per_sec: i64) -> Self {
+        self.write_rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// Makes opening with column families create the ones missing in existing database.
+    /// Unlike `create_if_missing` for the whole database, it is off by default
+    pub fn set_create_missing_column_families(mut self, create: bool) -> Self {
+        self.create_missing_column_families = create;
+        self
+    }
+
+    /// Enables collecting of statistics, available by `RocksDb::statistics()`.
+    /// Collecting statistics costs a few percents of performance
+    pub fn set_enable_statistics(mut self, enable: bool) -> Self {
+        self.enable_statistics = enable;
+        self
+    }
+
+    /// Applies overridden options to given RocksDB options
+    pub fn apply(&self, options: &mut Options) {
+        if let Some(size) = self.max_total_wal_size {
+            options.set_max_total_wal_size(size);
+        }
+        if let Some(size_mb) = self.wal_size_limit_mb {
+            options.set_wal_size_limit_mb(size_mb);
+        }
+        if let Some(seconds) = self.wal_ttl_seconds {
+            options.set_wal_ttl_seconds(seconds);
+        }
+        if let Some(count) = self.max_subcompactions {
+            options.set_max_subcompactions(count);
+        }
+        if let Some(size) = self.compaction_readahead_size {
+            options.set_compaction_readahead_size(size);
+        }
+        if let Some(enable) = self.enable_blob_files {
+            options.set_enable_blob_files(enable);
+        }
+        if let Some(size) = self.min_blob_size {
+            options.set_min_blob_size(size);
+        }
+        if let Some(size) = self.blob_file_size {
+            options.set_blob_file_size(size);
+        }
+        if let Some((size_ratio, max_size_amplification_percent)) = self.universal_compaction {
+            let mut universal_options = UniversalCompactOptions::default();
+            universal_options.set_size_ratio(size_ratio);
+            universal_options.set_max_size_amplification_percent(max_size_amplification_percent);
+            options.set_compaction_style(DBCompactionStyle::Universal);
+            options.set_universal_compaction_options(&universal_options);
+        }
+        if let Some((window_size, num_dels_trigger, deletion_ratio)) = self.compact_on_deletion {
+            options.add_compact_on_deletion_collector_factory(window_size, num_dels_trigger, deletion_ratio);
+        }
+        if let Some(count) = self.max_open_files {
+            options.set_max_open_files(count);
+        }
+        if let Some(enable) = self.enable_pipelined_write {
+            options.set_enable_pipelined_write(enable);
+        }
+        if let Some(allow) = self.allow_concurrent_memtable_write {
+            options.set_allow_concurrent_memtable_write(allow);
+        }
+        if let Some(ref merge_operator) = self.merge_operator {
+            options.set_merge_operator(merge_operator.name, merge_operator.full_merge, merge_operator.partial_merge);
+        }
+        if self.bloom_filter_bits_per_key.is_some() || self.checksum_type.is_some() {
+            let mut table_options = BlockBasedOptions::default();
+            if let Some(bits_per_key) = self.bloom_filter_bits_per_key {
+                table_options.set_bloom_filter(bits_per_key, false);
+            }
+            if let Some(kind) = self.checksum_type {
+                table_options.set_checksum_type(kind.into());
+            }
+            options.set_block_based_table_factory(&table_options);
+        }
+        if let Some(mode) = self.wal_recovery_mode {
+            options.set_wal_recovery_mode(mode.into());
+        }
+        if let Some(enable) = self.paranoid_checks {
+            options.set_paranoid_checks(enable);
+        }
+        if let Some(bytes_per_sec) = self.write_rate_limit {
+            set_rate_limiter(options, bytes_per_sec);
+        }
+        options.create_missing_column_families(self.create_missing_column_families);
+        if self.enable_statistics {
+            options.enable_statistics();
+        }
+        match self.memtable {
+            None | Some(MemtableKind::SkipList) => {},
+            Some(MemtableKind::HashSkipList { bucket_count, height, branching_factor }) => {
+                options.set_allow_concurrent_memtable_write(false);
+                options.set_memtable_factory(
+                    MemtableFactory::HashSkipList { bucket_count, height, branching_factor }
+                );
+            },
+            Some(MemtableKind::Vector) => {
+                options.set_allow_concurrent_memtable_write(false);
+                options.set_memtable_factory(MemtableFactory::Vector);
+            },
+        }
+    }
+}
+
+/// Sets limiter of flush and compaction writes with given bytes per second to given options
+pub(crate) fn set_rate_limiter(options: &mut Options, bytes_per_sec: i64) {
+    options.set_ratelimiter(bytes_per_sec, RATE_LIMITER_REFILL_PERIOD_US, RATE_LIMITER_FAIRNESS);
+}
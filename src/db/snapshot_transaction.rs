@@ -0,0 +1,66 @@
+use std::sync::{Arc, Mutex};
+
+use fnv::FnvHashMap;
+
+use ton_types::Result;
+
+use crate::db::traits::{DbKey, KvcReadable, KvcReadableTransaction, KvcTransaction};
+use crate::types::DbSlice;
+
+/// Transaction, which reads from the snapshot taken at its start and sees its own pending writes
+pub struct SnapshotTransaction<'db, K: DbKey + Send + Sync> {
+    snapshot: Arc<dyn KvcReadable<K> + 'db>,
+    transaction: Box<dyn KvcTransaction<K>>,
+    writes: Mutex<FnvHashMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl<'db, K: DbKey + Send + Sync> SnapshotTransaction<'db, K> {
+    /// Creates new transaction reading from given snapshot and writing into given transaction
+    pub fn new(snapshot: Arc<dyn KvcReadable<K> + 'db>, transaction: Box<dyn KvcTransaction<K>>) -> Self {
+        Self {
+            snapshot,
+            transaction,
+            writes: Mutex::new(FnvHashMap::default()),
+        }
+    }
+}
+
+impl<K: DbKey + Send + Sync> KvcTransaction<K> for SnapshotTransaction<'_, K> {
+    fn put(&self, key: &K, value: &[u8]) {
+        self.transaction.put(key, value);
+        self.writes.lock().unwrap()
+            .insert(key.key().to_vec(), Some(value.to_vec()));
+    }
+
+    fn delete(&self, key: &K) {
+        self.transaction.delete(key);
+        self.writes.lock().unwrap()
+            .insert(key.key().to_vec(), None);
+    }
+
+    fn clear(&self) {
+        self.transaction.clear();
+        self.writes.lock().unwrap().clear();
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        self.transaction.commit()
+    }
+
+    fn commit_sync(self: Box<Self>) -> Result<()> {
+        self.transaction.commit_sync()
+    }
+
+    fn len(&self) -> usize {
+        self.transaction.len()
+    }
+}
+
+impl<K: DbKey + Send + Sync> KvcReadableTransaction<K> for SnapshotTransaction<'_, K> {
+    fn try_get(&self, key: &K) -> Result<Option<DbSlice>> {
+        if let Some(value) = self.writes.lock().unwrap().get(key.key()) {
+            return Ok(value.clone().map(|value| value.into()));
+        }
+        self.snapshot.try_get(key)
+    }
+}
@@ -74,3 +74,37 @@ impl DbKey for U32Key {
         &self.key
     }
 }
+
+/// Checks `DbKey` contract for given keys, built from distinct values: `key()`, `as_string()`
+/// and `key_name()` are stable across repeated calls, `key_name()` is the same for all keys
+/// of the type, and distinct keys never produce equal raw keys
+#[cfg(test)]
+pub(crate) fn assert_dbkey_stable<K: DbKey>(keys: &[K]) {
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(key.key(), key.key(), "key() is not deterministic for {}", key.as_string());
+        assert_eq!(key.as_string(), key.as_string(), "as_string() is not deterministic");
+        assert_eq!(key.key_name(), key.key_name(), "key_name() is not deterministic");
+        assert_eq!(key.key_name(), keys[0].key_name(), "key_name() differs for keys of the same type");
+
+        for other in &keys[i + 1..] {
+            assert_ne!(
+                key.key(),
+                other.key(),
+                "{}({}) and {}({}) produce equal raw keys",
+                key.key_name(), key.as_string(), other.key_name(), other.as_string()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u32_key_is_stable() {
+        let keys: Vec<U32Key> = [0, 1, 255, 256, u32::max_value()].iter().map(|&value| value.into()).collect();
+        assert_dbkey_stable(&keys);
+        assert_eq!(keys[3].as_string(), "256");
+    }
+}
@@ -1,8 +1,9 @@
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use ton_types::Result;
+use ton_types::{fail, Result};
 
+use crate::db::snapshot_transaction::SnapshotTransaction;
 use crate::db::traits::DbKey;
 use crate::types::DbSlice;
 use crate::error::StorageError;
@@ -32,6 +33,17 @@ pub trait KvcReadable<K: DbKey + Send + Sync>: Kvc {
             .ok_or_else(|| StorageError::KeyNotFound(key.key_name(), key.as_string()).into())
     }
 
+    /// Gets value from collection by the key; missing key gives Ok(None), so only real failures
+    /// (I/O, corruption, dropped database) are reported as errors. Same as `try_get()`
+    fn get_optional(&self, key: &K) -> Result<Option<DbSlice>> {
+        self.try_get(key)
+    }
+
+    /// Gets value from collection by the key or given default value, if the key not found
+    fn get_or<'a>(&'a self, key: &K, default: &'a [u8]) -> Result<DbSlice<'a>> {
+        Ok(self.get_optional(key)?.unwrap_or_else(|| default.into()))
+    }
+
     /// Gets slice with given size starting from given offset from collection by the key
     fn get_slice(&self, key: &K, offset: u64, size: u64) -> Result<DbSlice> {
         self.get(key).and_then(|value| {
@@ -55,8 +67,92 @@ pub trait KvcReadable<K: DbKey + Send + Sync>: Kvc {
         Ok(self.try_get(key)?.is_some())
     }
 
+    /// Cheap probabilistic check of key presence: false means the key is certainly absent,
+    /// while true may be returned for absent key as well. The default implementation is exact
+    fn may_exist(&self, key: &K) -> bool {
+        self.contains(key).unwrap_or(true)
+    }
+
+    /// Gets values of given keys, in input order; missing keys map to None
+    fn get_multi(&self, keys: &[K]) -> Result<Vec<Option<DbSlice>>> {
+        keys.iter()
+            .map(|key| self.try_get(key))
+            .collect()
+    }
+
+    /// Determines presence of each of given keys, in input order
+    fn contains_multi(&self, keys: &[K]) -> Result<Vec<bool>> {
+        keys.iter()
+            .map(|key| self.contains(key))
+            .collect()
+    }
+
     /// Iterates over items in key-value collection, running predicate for each key-value pair
     fn for_each(&self, predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<bool>;
+
+    /// Gets iterator over items in key-value collection in key order. The default implementation
+    /// collects all items up front; implementations able to walk a cursor lazily override it
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_>> {
+        let mut items = Vec::new();
+        self.for_each(&mut |key, value| {
+            items.push(Ok((key.to_vec(), value.to_vec())));
+            Ok(true)
+        })?;
+        Ok(Box::new(items.into_iter()))
+    }
+
+    /// Iterates over items with keys greater than or equal to `start` in key order,
+    /// finishes when predicate returns false. Allows pagination by resuming iteration
+    /// right after the last processed key
+    fn for_each_from(
+        &self,
+        start: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        self.for_each(&mut |key, value| {
+            if key >= start {
+                predicate(key, value)
+            } else {
+                Ok(true)
+            }
+        })
+    }
+
+    /// Iterates over items with keys starting with given prefix, running predicate for each
+    /// key-value pair. Empty prefix iterates over all items
+    fn for_each_prefix(
+        &self,
+        prefix: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        self.for_each(&mut |key, value| {
+            if key.starts_with(prefix) {
+                predicate(key, value)
+            } else {
+                Ok(true)
+            }
+        })
+    }
+
+    /// Iterates over items with keys in range [start, end), running predicate for each
+    /// key-value pair. `end` is exclusive; inverted range iterates over nothing
+    fn for_each_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        predicate: &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>
+    ) -> Result<bool> {
+        if start >= end {
+            return Ok(true);
+        }
+        self.for_each(&mut |key, value| {
+            if key >= start && key < end {
+                predicate(key, value)
+            } else {
+                Ok(true)
+            }
+        })
+    }
 }
 
 /// Trait for writable key-value collections
@@ -66,6 +162,46 @@ pub trait KvcWriteable<K: DbKey + Send + Sync>: KvcReadable<K> {
 
     /// Deletes value from collection by the key
     fn delete(&self, key: &K) -> Result<()>;
+
+    /// Merges operand into value by the key with merge operator the collection is configured with
+    fn merge(&self, key: &K, _operand: &[u8]) -> Result<()> {
+        fail!("merge() is not supported by the collection (key {})", key.as_string())
+    }
+
+    /// Deletes all values with keys in half-open interval [from, to). For RocksDB it writes a single
+    /// range tombstone: point reads of deleted keys return nothing at once, while iteration costs
+    /// grow until the tombstone is compacted away
+    fn delete_range(&self, from: &[u8], to: &[u8]) -> Result<()>;
+
+    /// Deletes all values with keys starting with given prefix
+    fn delete_prefix(&self, prefix: &[u8]) -> Result<()> {
+        if let Some(end) = prefix_upper_bound(prefix) {
+            return self.delete_range(prefix, &end);
+        }
+        // No finite upper bound for prefix of 0xFF bytes only: delete up to the last existing key
+        let mut last_key = None;
+        self.for_each_prefix(prefix, &mut |key, _| {
+            last_key = Some(key.to_vec());
+            Ok(true)
+        })?;
+        if let Some(mut end) = last_key {
+            end.push(0);
+            self.delete_range(prefix, &end)?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the smallest key greater than all keys starting with given prefix, if exists
+pub fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last != 0xFF {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
 }
 
 /// Trait for key-value collections with the ability of take snapshots
@@ -78,6 +214,26 @@ pub trait KvcSnapshotable<K: DbKey + Send + Sync>: KvcWriteable<K> {
 pub trait KvcTransactional<K: DbKey + Send + Sync>: KvcSnapshotable<K> {
     /// Creates new transaction (batch)
     fn begin_transaction(&self) -> Result<Box<dyn KvcTransaction<K>>>;
+
+    /// Creates new transaction (batch), which reads from the snapshot taken at its start.
+    /// Reads observe pending operations of the transaction itself. Commit applies the batch
+    /// to the live collection
+    fn begin_transaction_with_snapshot<'db>(&'db self) -> Result<Box<dyn KvcReadableTransaction<K> + 'db>>
+    where
+        K: 'db
+    {
+        Ok(Box::new(SnapshotTransaction::new(self.snapshot()?, self.begin_transaction()?)))
+    }
+}
+
+/// Trait for key-value collections supporting optimistic transactions. Of several racing
+/// optimistic transactions at most one commits. Plain writes and batch transactions don't take
+/// part in the validation, so the one racing with the very commit of optimistic transaction
+/// (not with the transaction as a whole) may stay undetected by RocksDB implementation
+pub trait KvcOptimisticTransactional<K: DbKey + Send + Sync>: KvcTransactional<K> {
+    /// Creates new optimistic transaction. Its commit fails with `StorageError::TransactionConflict`,
+    /// if any key read by the transaction has been changed by another writer since the read
+    fn begin_optimistic_transaction(&self) -> Result<Box<dyn KvcReadableTransaction<K>>>;
 }
 
 /// Trait for transaction on key-value collection. The transaction must be committed before the
@@ -96,6 +252,13 @@ pub trait KvcTransaction<K: DbKey + Send + Sync> {
     /// Commits the transaction (batch)
     fn commit(self: Box<Self>) -> Result<()>;
 
+    /// Commits the transaction (batch), waiting for its data being synced to disk.
+    /// It survives abrupt termination, but is much slower than regular commit,
+    /// so it should be used for critical writes only
+    fn commit_sync(self: Box<Self>) -> Result<()> {
+        self.commit()
+    }
+
     /// Gets pending operations count
     fn len(&self) -> usize;
 
@@ -104,3 +267,16 @@ pub trait KvcTransaction<K: DbKey + Send + Sync> {
         self.len() == 0
     }
 }
+
+/// Trait for transaction, which is able to read values. Reads reflect pending operations
+/// of the transaction itself (read-your-writes)
+pub trait KvcReadableTransaction<K: DbKey + Send + Sync>: KvcTransaction<K> {
+    /// Tries to get value from the transaction or from the underlying collection
+    fn try_get(&self, key: &K) -> Result<Option<DbSlice>>;
+
+    /// Gets value from the transaction or from the underlying collection
+    fn get(&self, key: &K) -> Result<DbSlice> {
+        self.try_get(key)?
+            .ok_or_else(|| StorageError::KeyNotFound(key.key_name(), key.as_string()).into())
+    }
+}
@@ -1,20 +1,98 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::Entry;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
-use std::sync::{Arc, RwLock, Weak};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, Weak};
 
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
-use ton_types::{Cell, Result};
+use ton_types::{error, BagOfCells, Cell, Result, UInt256};
 
+use crate::cell_cache::{CacheStats, CellCache};
 use crate::cell_db::CellDb;
 use crate::dynamic_boc_diff_writer::{DynamicBocDiffFactory, DynamicBocDiffWriter};
-use crate::types::{CellId, StorageCell};
+use crate::types::{CellId, Reference, StorageCell};
+
+/// Statistics of stored cells, gathered by full scan
+#[derive(Debug, Clone, Default)]
+pub struct CellStats {
+    count: usize,
+    total_bytes: u64,
+    refcounts: BTreeMap<u32, usize>,
+}
+
+impl CellStats {
+    pub const fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Total length of serialized records
+    pub const fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// Count of cells for each reference count value
+    pub const fn refcounts(&self) -> &BTreeMap<u32, usize> {
+        &self.refcounts
+    }
+}
+
+/// Result of checking tree of cells stored under a root
+#[derive(Debug, Clone, Default)]
+pub struct TreeReport {
+    cells: usize,
+    max_depth: usize,
+    dangling: Vec<UInt256>,
+    cycles: Vec<CellId>,
+}
+
+impl TreeReport {
+    /// Count of distinct stored cells of the tree; shared subtrees are counted once
+    pub const fn cells(&self) -> usize {
+        self.cells
+    }
+
+    /// Length of the longest path of references from the root (0 for a single cell)
+    pub const fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Hashes of referenced cells missing in the store (the root too, if it is missing)
+    pub fn dangling(&self) -> &[UInt256] {
+        &self.dangling
+    }
+
+    /// Cells referenced by their own descendants. Well-formed trees have none
+    pub fn cycles(&self) -> &[CellId] {
+        &self.cycles
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.dangling.is_empty() && self.cycles.is_empty()
+    }
+}
+
+/// Cell being checked by `DynamicBocDb::check_tree()` with its references yet to check
+struct CheckedCell {
+    cell_id: CellId,
+    references: Vec<CellId>,
+    height: usize,
+}
 
 #[derive(Debug)]
 pub struct DynamicBocDb {
     db: Arc<CellDb>,
     cells: Arc<RwLock<FnvHashMap<CellId, Weak<StorageCell>>>>,
+    cache: Option<Arc<CellCache>>,
     diff_factory: DynamicBocDiffFactory,
+    /// Serializes read-modify-write of reference counts by writers of this instance
+    write_lock: Mutex<()>,
+    /// Worker threads of put_tree_parallel(), kept between calls
+    traversal_pool: Mutex<Option<Arc<ThreadPool>>>,
 }
 
 impl DynamicBocDb {
@@ -28,13 +106,30 @@ impl DynamicBocDb {
         Self::with_db(CellDb::with_path(path))
     }
 
+    /// Constructs new instance using RocksDB with given path and LRU cache of given capacity
+    pub fn with_cache_capacity<P: AsRef<Path>>(path: P, cache_capacity: usize) -> Self {
+        Self::with_db_and_cache(CellDb::with_path(path), Some(cache_capacity))
+    }
+
     /// Constructs new instance using given key-value collection implementation
     pub(crate) fn with_db(db: CellDb) -> Self {
+        Self::with_db_and_cache(db, None)
+    }
+
+    /// Constructs new instance using given key-value collection implementation
+    /// and optional LRU cache of given capacity
+    pub(crate) fn with_db_and_cache(db: CellDb, cache_capacity: Option<usize>) -> Self {
         let db = Arc::new(db);
+        let cache = cache_capacity
+            .filter(|capacity| *capacity > 0)
+            .map(|capacity| Arc::new(CellCache::with_capacity(capacity)));
         Self {
             db: Arc::clone(&db),
             cells: Arc::new(RwLock::new(FnvHashMap::default())),
-            diff_factory: DynamicBocDiffFactory::new(db),
+            cache: cache.clone(),
+            diff_factory: DynamicBocDiffFactory::new(db, cache),
+            write_lock: Mutex::new(()),
+            traversal_pool: Mutex::new(None),
         }
     }
 
@@ -46,7 +141,56 @@ impl DynamicBocDb {
         Arc::clone(&self.cells)
     }
 
-    /// Converts tree of cells into DynamicBoc
+    /// Returns hits and misses of cell cache, if it is enabled
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Drops all cached cells
+    pub fn clear_cache(&self) {
+        if let Some(ref cache) = self.cache {
+            cache.clear();
+        }
+    }
+
+    /// Returns count of stored cells. For RocksDB it is an estimation, which is cheap to get
+    pub fn cell_count(&self) -> Result<usize> {
+        self.db.len()
+    }
+
+    /// Sums lengths of all stored cell records. Scans the whole storage, so don't use it on hot path
+    pub fn total_cell_bytes_full_scan(&self) -> Result<u64> {
+        let mut total_bytes = 0;
+        self.db.for_each(&mut |_key, value| {
+            if !CellDb::is_orphan_delta(value) {
+                total_bytes += value.len() as u64;
+            }
+            Ok(true)
+        })?;
+
+        Ok(total_bytes)
+    }
+
+    /// Gathers exact count, size and reference count distribution of stored cells.
+    /// Scans and decodes the whole storage, so don't use it on hot path
+    pub fn cell_stats_full_scan(&self) -> Result<CellStats> {
+        let mut stats = CellStats::default();
+        self.db.for_each(&mut |key, value| {
+            if CellDb::is_orphan_delta(value) {
+                return Ok(true);
+            }
+            let refcount = CellDb::record_refcount(&CellId::try_from(key)?, value)?;
+            stats.count += 1;
+            stats.total_bytes += value.len() as u64;
+            *stats.refcounts.entry(refcount).or_insert(0) += 1;
+            Ok(true)
+        })?;
+
+        Ok(stats)
+    }
+
+    /// Converts tree of cells into DynamicBoc. The root gets one more reference,
+    /// as in put_tree(). Returns count of written cells
     pub fn save_as_dynamic_boc(self: &Arc<Self>, root_cell: Cell) -> Result<usize> {
         let diff_writer = self.diff_factory.construct();
 
@@ -55,11 +199,107 @@ impl DynamicBocDb {
             Arc::clone(&self.db),
             &diff_writer)?;
 
+        let _guard = self.write_lock.lock().expect("Poisoned Mutex");
         diff_writer.apply()?;
 
         Ok(written_count)
     }
 
+    /// Writes whole tree of cells atomically in one transaction. The root gets one more
+    /// reference, every new cell is counted once for each reference to it within the tree.
+    /// Cells which are already stored get their counts increased and are skipped with their
+    /// subtrees; presence is checked level by level in batches. Presence is checked under the
+    /// write lock, so concurrent writers of shared subtrees count them once. Returns count of
+    /// written cells
+    pub fn put_tree(&self, root_cell: Cell) -> Result<usize> {
+        let guard = self.write_lock.lock().expect("Poisoned Mutex");
+        let mut refs: FnvHashMap<CellId, (Cell, u32)> = FnvHashMap::default();
+        let mut level = vec![root_cell];
+        while !level.is_empty() {
+            let mut candidates = Vec::new();
+            for cell in level {
+                let cell_id = CellId::new(cell.repr_hash());
+                match refs.entry(cell_id.clone()) {
+                    Entry::Occupied(mut entry) => entry.get_mut().1 += 1,
+                    Entry::Vacant(entry) => {
+                        entry.insert((cell.clone(), 1));
+                        candidates.push((cell_id, cell));
+                    }
+                }
+            }
+            let ids: Vec<_> = candidates.iter().map(|(cell_id, _cell)| cell_id.clone()).collect();
+            let present = self.db.contains_batch(&ids)?;
+
+            let mut next_level = Vec::new();
+            for ((_cell_id, cell), present) in candidates.into_iter().zip(present) {
+                if present {
+                    continue;
+                }
+                for i in 0..cell.references_count() {
+                    next_level.push(cell.reference(i)?);
+                }
+            }
+            level = next_level;
+        }
+
+        self.put_referenced(&guard, refs)
+    }
+
+    /// Puts cells with counts of references to them in one transaction. Returns count of new cells.
+    /// Takes the write lock guard, under which presence of the cells has been checked
+    fn put_referenced(&self, _guard: &MutexGuard<()>, refs: FnvHashMap<CellId, (Cell, u32)>) -> Result<usize> {
+        let transaction = self.db.begin_transaction_with_snapshot()?;
+        let mut written_count = 0;
+        for (cell_id, (cell, count)) in refs.iter() {
+            if CellDb::put_cell_referenced(&*transaction, cell_id, cell.clone(), *count)? {
+                written_count += 1;
+            }
+        }
+        transaction.commit()?;
+
+        self.invalidate_cached(refs.keys());
+
+        Ok(written_count)
+    }
+
+    /// Same as put_tree(), but walks the tree with pool of given count of worker threads.
+    /// The pool is kept for next calls with the same count. Falls back to put_tree()
+    /// if the count is 1 or less
+    pub fn put_tree_parallel(&self, root_cell: Cell, threads: usize) -> Result<usize> {
+        if threads <= 1 {
+            return self.put_tree(root_cell);
+        }
+
+        let pool = self.traversal_pool(threads)?;
+        let guard = self.write_lock.lock().expect("Poisoned Mutex");
+        let traversal = ParallelTraversal::new(&self.db);
+        pool.scope(|scope| traversal.visit(scope, root_cell));
+        if let Some(err) = traversal.error.into_inner().expect("Poisoned Mutex") {
+            return Err(err);
+        }
+
+        self.put_referenced(&guard, traversal.refs.into_inner().expect("Poisoned Mutex"))
+    }
+
+    fn traversal_pool(&self, threads: usize) -> Result<Arc<ThreadPool>> {
+        let mut pool = self.traversal_pool.lock().expect("Poisoned Mutex");
+        if let Some(ref pool) = *pool {
+            if pool.current_num_threads() == threads {
+                return Ok(Arc::clone(pool));
+            }
+        }
+        let new_pool = Arc::new(
+            ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|index| format!("cell-traversal-{}", index))
+                .build()
+                .map_err(|err| error!("Cannot start cell tree traversal threads: {}", err))?
+        );
+        *pool = Some(Arc::clone(&new_pool));
+
+        Ok(new_pool)
+    }
+
     /// Gets root cell from key-value storage
     pub fn load_dynamic_boc(self: &Arc<Self>, root_cell_id: &CellId) -> Result<Cell> {
         let storage_cell = self.load_cell(root_cell_id)?;
@@ -67,29 +307,243 @@ impl DynamicBocDb {
         Ok(Cell::with_cell_impl_arc(storage_cell))
     }
 
+    /// Writes given roots with all their descendants into a single multi-root BOC file.
+    /// Returns count of unique cells written
+    pub fn export_bag(self: &Arc<Self>, roots: &[CellId], out: &Path) -> Result<usize> {
+        let mut root_cells = Vec::with_capacity(roots.len());
+        for root_id in roots {
+            root_cells.push(self.load_dynamic_boc(root_id)?);
+        }
+
+        let bag = BagOfCells::with_roots(root_cells.iter().collect());
+        let mut writer = BufWriter::new(File::create(out)?);
+        bag.write_to(&mut writer, false)?;
+        writer.flush()?;
+
+        Ok(bag.cells_count())
+    }
+
+    /// Marks all cells reachable from given roots and returns ids of stored cells which are not
+    pub fn collect_unreachable(&self, roots: &[UInt256]) -> Result<Vec<CellId>> {
+        let mut marked = FnvHashSet::default();
+        let mut stack: Vec<CellId> = roots.iter().cloned().map(CellId::from).collect();
+        while let Some(cell_id) = stack.pop() {
+            if marked.contains(&cell_id) {
+                continue;
+            }
+            let references = CellDb::deserialize_cell(&cell_id, self.db.get(&cell_id)?.as_ref())?.1;
+            for reference in references {
+                let child_id = CellId::from(reference.hash());
+                if !marked.contains(&child_id) {
+                    stack.push(child_id);
+                }
+            }
+            marked.insert(cell_id);
+        }
+
+        let mut unreachable = Vec::new();
+        self.db.for_each(&mut |key, _value| {
+            let cell_id = CellId::try_from(key)?;
+            if !marked.contains(&cell_id) {
+                unreachable.push(cell_id);
+            }
+            Ok(true)
+        })?;
+
+        Ok(unreachable)
+    }
+
+    /// Walks stored tree of cells under given root without loading cells into memory and reports
+    /// its size, depth, dangling references and reference cycles
+    pub fn check_tree(&self, root: &CellId) -> Result<TreeReport> {
+        let mut report = TreeReport::default();
+        let mut heights = FnvHashMap::default();
+        let mut missing = FnvHashSet::default();
+        let mut on_path = FnvHashSet::default();
+        let mut stack = Vec::new();
+        self.enter_checked_cell(root.clone(), &mut stack, &mut on_path, &mut missing, &mut report)?;
+        loop {
+            let child_id = match stack.last_mut() {
+                Some(checked) => checked.references.pop(),
+                None => break,
+            };
+            match child_id {
+                Some(child_id) => {
+                    if on_path.contains(&child_id) {
+                        report.cycles.push(child_id);
+                    } else if let Some(height) = heights.get(&child_id) {
+                        let parent = stack.last_mut().expect("Parent cell on stack");
+                        parent.height = parent.height.max(height + 1);
+                    } else if !missing.contains(&child_id) {
+                        self.enter_checked_cell(child_id, &mut stack, &mut on_path, &mut missing, &mut report)?;
+                    }
+                },
+                None => {
+                    let checked = stack.pop().expect("Checked cell on stack");
+                    on_path.remove(&checked.cell_id);
+                    if let Some(parent) = stack.last_mut() {
+                        parent.height = parent.height.max(checked.height + 1);
+                    }
+                    report.max_depth = report.max_depth.max(checked.height);
+                    heights.insert(checked.cell_id, checked.height);
+                },
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn enter_checked_cell(
+        &self,
+        cell_id: CellId,
+        stack: &mut Vec<CheckedCell>,
+        on_path: &mut FnvHashSet<CellId>,
+        missing: &mut FnvHashSet<CellId>,
+        report: &mut TreeReport,
+    ) -> Result<()> {
+        let data = match self.db.try_get(&cell_id)? {
+            Some(data) => data,
+            None => {
+                report.dangling.push(cell_id.as_hash().clone());
+                missing.insert(cell_id);
+                return Ok(());
+            },
+        };
+        let references = CellDb::deserialize_cell(&cell_id, data.as_ref())?.1.iter()
+            .map(|reference| CellId::from(reference.hash()))
+            .collect();
+        report.cells += 1;
+        on_path.insert(cell_id.clone());
+        stack.push(CheckedCell { cell_id, references, height: 0 });
+
+        Ok(())
+    }
+
+    /// Deletes given cells in one transaction
+    pub fn delete_cells(&self, ids: &[CellId]) -> Result<()> {
+        let _guard = self.write_lock.lock().expect("Poisoned Mutex");
+        let transaction = self.db.begin_transaction()?;
+        for cell_id in ids {
+            transaction.delete(cell_id);
+        }
+        transaction.commit()?;
+
+        self.invalidate_cached(ids.iter());
+
+        Ok(())
+    }
+
     pub(crate) fn diff_factory(&self) -> &DynamicBocDiffFactory {
         &self.diff_factory
     }
 
     pub(crate) fn load_cell(self: &Arc<Self>, cell_id: &CellId) -> Result<Arc<StorageCell>> {
+        if let Some(cell) = self.loaded_cell(cell_id) {
+            return Ok(cell);
+        }
+        let storage_cell = Arc::new(
+            CellDb::get_cell(&*self.db, &cell_id, Arc::clone(self))?
+        );
+        self.register_cell(cell_id, &storage_cell);
+
+        Ok(storage_cell)
+    }
+
+    /// Loads cell with its references resolved eagerly, level by level, up to `max_depth` levels
+    /// below the root (the whole tree, if not given). Cells of each level missing in memory are
+    /// read from the database in one batch
+    pub fn load_cell_tree(self: &Arc<Self>, root: &CellId, max_depth: Option<usize>) -> Result<Arc<StorageCell>> {
+        let root_cell = self.load_cell(root)?;
+        let mut visited = FnvHashSet::default();
+        visited.insert(root.clone());
+        let mut level = vec![root_cell.clone()];
+        let mut depth = 0;
+        while !level.is_empty() && max_depth.map_or(true, |max_depth| depth < max_depth) {
+            let mut missing = Vec::new();
+            let mut requested = FnvHashSet::default();
+            let mut loaded = FnvHashMap::default();
+            for cell in &level {
+                for reference in cell.references() {
+                    let cell_id = CellId::from(reference.hash());
+                    if let Reference::Loaded(child) = reference {
+                        loaded.insert(cell_id, child);
+                    } else if !loaded.contains_key(&cell_id) {
+                        match self.loaded_cell(&cell_id) {
+                            Some(child) => { loaded.insert(cell_id, child); },
+                            None => if requested.insert(cell_id.clone()) {
+                                missing.push(cell_id)
+                            },
+                        }
+                    }
+                }
+            }
+            missing.retain(|cell_id| !loaded.contains_key(cell_id));
+            for (cell_id, child) in missing.iter().zip(self.db.get_cells(&missing, Arc::clone(self))?) {
+                let child = Arc::new(child);
+                self.register_cell(cell_id, &child);
+                loaded.insert(cell_id.clone(), child);
+            }
+
+            let mut next_level = Vec::new();
+            for cell in &level {
+                for (index, reference) in cell.references().into_iter().enumerate() {
+                    let cell_id = CellId::from(reference.hash());
+                    let child = Arc::clone(&loaded[&cell_id]);
+                    cell.set_loaded_reference(index, Arc::clone(&child));
+                    if visited.insert(cell_id) {
+                        next_level.push(child);
+                    }
+                }
+            }
+            level = next_level;
+            depth += 1;
+        }
+
+        Ok(root_cell)
+    }
+
+    /// Gets cell from the map of cells alive in memory, or constructs it from cached data
+    fn loaded_cell(self: &Arc<Self>, cell_id: &CellId) -> Option<Arc<StorageCell>> {
         if let Some(cell) = self.cells.read()
             .expect("Poisoned RwLock")
             .get(&cell_id)
         {
-            if let Some(ref cell) = Weak::upgrade(&cell) {
-                return Ok(Arc::clone(cell));
+            if let Some(cell) = Weak::upgrade(&cell) {
+                return Some(cell);
             }
             // Even if the cell is disposed, we will load and store it later,
             // so we don't need to remove garbage here.
         }
-        let storage_cell = Arc::new(
-            CellDb::get_cell(&*self.db, &cell_id, Arc::clone(self))?
-        );
+        if let Some(ref cache) = self.cache {
+            if let Some(cached) = cache.get(cell_id) {
+                let references = cached.references().iter()
+                    .map(|hash| Reference::NeedToLoad(hash.clone()))
+                    .collect();
+                let cell = Arc::new(StorageCell::with_params(cached.cell_data().clone(), references, Arc::clone(self)));
+                self.cells.write()
+                    .expect("Poisoned RwLock")
+                    .insert(cell_id.clone(), Arc::downgrade(&cell));
+                return Some(cell);
+            }
+        }
+        None
+    }
+
+    fn register_cell(&self, cell_id: &CellId, storage_cell: &Arc<StorageCell>) {
         self.cells.write()
             .expect("Poisoned RwLock")
-            .insert(cell_id.clone(), Arc::downgrade(&storage_cell));
+            .insert(cell_id.clone(), Arc::downgrade(storage_cell));
+        if let Some(ref cache) = self.cache {
+            cache.insert(cell_id.clone(), storage_cell);
+        }
+    }
 
-        Ok(storage_cell)
+    fn invalidate_cached<'a>(&self, cell_ids: impl Iterator<Item = &'a CellId>) {
+        if let Some(ref cache) = self.cache {
+            for cell_id in cell_ids {
+                cache.invalidate(cell_id);
+            }
+        }
     }
 
     fn save_tree_of_cells_recursive(
@@ -99,12 +553,11 @@ impl DynamicBocDb {
         diff_writer: &DynamicBocDiffWriter
     ) -> Result<usize> {
         let cell_id = CellId::new(cell.repr_hash());
-        if cell_db.contains(&cell_id)? {
+        // Every reference is added, but a subtree is walked only once and only if it is new
+        if !diff_writer.add_cell(cell_id.clone(), cell.clone()) || cell_db.contains(&cell_id)? {
             return Ok(0);
         }
 
-        diff_writer.add_cell(cell_id, cell.clone());
-
         let mut count = 1;
         for i in 0..cell.references_count() {
             count += self.save_tree_of_cells_recursive(
@@ -118,6 +571,58 @@ impl DynamicBocDb {
     }
 }
 
+/// Shared state of parallel cell tree traversal. Every reference is visited by a task
+/// of the pool, but children are spawned only by the first visit of a new cell
+struct ParallelTraversal<'a> {
+    db: &'a CellDb,
+    /// Visited cells with counts of references to them within the tree
+    refs: Mutex<FnvHashMap<CellId, (Cell, u32)>>,
+    /// First error occurred; the rest of the tree is skipped after it
+    error: Mutex<Option<failure::Error>>,
+}
+
+impl<'a> ParallelTraversal<'a> {
+    fn new(db: &'a CellDb) -> Self {
+        Self {
+            db,
+            refs: Mutex::new(FnvHashMap::default()),
+            error: Mutex::new(None),
+        }
+    }
+
+    fn visit<'s>(&'s self, scope: &rayon::Scope<'s>, cell: Cell) {
+        if self.error.lock().expect("Poisoned Mutex").is_some() {
+            return;
+        }
+        if let Err(err) = self.process(scope, cell) {
+            self.error.lock().expect("Poisoned Mutex").get_or_insert(err);
+        }
+    }
+
+    fn process<'s>(&'s self, scope: &rayon::Scope<'s>, cell: Cell) -> Result<()> {
+        let cell_id = CellId::new(cell.repr_hash());
+        match self.refs.lock().expect("Poisoned Mutex").entry(cell_id.clone()) {
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().1 += 1;
+                return Ok(());
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((cell.clone(), 1));
+            }
+        }
+        if self.db.contains_batch(&[cell_id])?[0] {
+            return Ok(());
+        }
+
+        for i in 0..cell.references_count() {
+            let child = cell.reference(i)?;
+            scope.spawn(move |scope| self.visit(scope, child));
+        }
+
+        Ok(())
+    }
+}
+
 impl Deref for DynamicBocDb {
     type Target = Arc<CellDb>;
 
@@ -1,30 +1,51 @@
+use std::collections::hash_map::Entry;
 use std::sync::{Arc, RwLock};
 
 use fnv::FnvHashMap;
 
 use ton_types::{Cell, Result};
 
+use crate::cell_cache::CellCache;
 use crate::cell_db::CellDb;
 use crate::types::CellId;
 
 #[derive(Debug)]
 pub(super) struct DynamicBocDiff {
     db: Arc<CellDb>,
-    diff: RwLock<FnvHashMap<CellId, Option<Cell>>>,
+    cache: Option<Arc<CellCache>>,
+    /// Added cells with counts of references to them, or None for deleted cells
+    diff: RwLock<FnvHashMap<CellId, Option<(Cell, u32)>>>,
 }
 
 impl DynamicBocDiff {
-    pub fn new(db: Arc<CellDb>) -> Self {
+    pub fn new(db: Arc<CellDb>, cache: Option<Arc<CellCache>>) -> Self {
         Self {
             db,
+            cache,
             diff: RwLock::new(FnvHashMap::default()),
         }
     }
 
-    pub fn add_cell(&self, cell_id: CellId, cell: Cell) {
-        self.diff.write()
-            .expect("Poisoned RwLock")
-            .insert(cell_id, Some(cell));
+    /// Adds one reference to the cell. Returns true, if the cell is added first time
+    pub fn add_cell(&self, cell_id: CellId, cell: Cell) -> bool {
+        let mut write_guard = self.diff.write()
+            .expect("Poisoned RwLock");
+        match write_guard.entry(cell_id) {
+            Entry::Occupied(mut entry) => match entry.get_mut() {
+                Some((_cell, refs)) => {
+                    *refs += 1;
+                    false
+                }
+                deleted => {
+                    *deleted = Some((cell, 1));
+                    true
+                }
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(Some((cell, 1)));
+                true
+            }
+        }
     }
 
     pub fn delete_cell(&self, cell_id: &CellId) {
@@ -36,18 +57,32 @@ impl DynamicBocDiff {
     }
 
     pub fn apply(self) -> Result<()> {
-        let transaction = self.db.begin_transaction()?;
+        let transaction = self.db.begin_transaction_with_snapshot()?;
 
+        let mut changed = Vec::new();
         for (cell_id, cell_opt) in self.diff.write()
             .expect("Poisoned RwLock")
             .drain()
         {
             match cell_opt {
-                Some(cell) => CellDb::put_cell(&*transaction, &cell_id, cell)?,
+                Some((cell, refs)) => {
+                    CellDb::put_cell_referenced(&*transaction, &cell_id, cell, refs)?;
+                }
                 None => transaction.delete(&cell_id),
             }
+            if self.cache.is_some() {
+                changed.push(cell_id);
+            }
+        }
+
+        transaction.commit()?;
+
+        if let Some(ref cache) = self.cache {
+            for cell_id in changed {
+                cache.invalidate(&cell_id);
+            }
         }
 
-        transaction.commit()
+        Ok(())
     }
 }
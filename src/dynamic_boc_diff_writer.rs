@@ -2,6 +2,7 @@ use std::sync::{Arc, RwLock, Weak};
 
 use ton_types::{Cell, Result};
 
+use crate::cell_cache::CellCache;
 use crate::cell_db::CellDb;
 use crate::dynamic_boc_diff::DynamicBocDiff;
 use crate::types::CellId;
@@ -9,13 +10,15 @@ use crate::types::CellId;
 #[derive(Debug)]
 pub(super) struct DynamicBocDiffFactory {
     db: Arc<CellDb>,
+    cache: Option<Arc<CellCache>>,
     diff: RwLock<Weak<DynamicBocDiff>>,
 }
 
 impl DynamicBocDiffFactory {
-    pub fn new(db: Arc<CellDb>) -> Self {
+    pub fn new(db: Arc<CellDb>, cache: Option<Arc<CellCache>>) -> Self {
         Self {
             db,
+            cache,
             diff: RwLock::new(Weak::new()),
         }
     }
@@ -28,7 +31,7 @@ impl DynamicBocDiffFactory {
             // match Weak::upgrade(&guard) {
                 // Some(diff) => diff,
                 // None => {
-                    let diff = Arc::new(DynamicBocDiff::new(Arc::clone(&self.db)));
+                    let diff = Arc::new(DynamicBocDiff::new(Arc::clone(&self.db), self.cache.clone()));
                     // *guard = Arc::downgrade(&diff);
                     diff
                 // }
@@ -46,7 +49,8 @@ impl DynamicBocDiffWriter {
         Self { diff }
     }
 
-    pub fn add_cell(&self, cell_id: CellId, cell: Cell) {
+    /// Adds one reference to the cell. Returns true, if the cell is added first time
+    pub fn add_cell(&self, cell_id: CellId, cell: Cell) -> bool {
         self.diff.add_cell(cell_id, cell)
     }
 
@@ -1,7 +1,9 @@
+use crate::types::CellId;
+
 #[derive(Debug, PartialEq, failure::Fail)]
 pub enum StorageError {
-    /// Key not found  
-    #[fail(display = "Key not found: {}({})", 0, 1)]
+    /// Key not found in collection; contains key name and key itself
+    #[fail(display = "{} not found: {}", 0, 1)]
     KeyNotFound(&'static str, String),
 
     /// Reference not loaded
@@ -19,4 +21,72 @@ pub enum StorageError {
     /// Reading out of buffer range
     #[fail(display = "Reading out of buffer range")]
     OutOfRange,
+
+    /// Operation was cancelled
+    #[fail(display = "Operation was cancelled")]
+    Cancelled,
+
+    /// Raw key has invalid length
+    #[fail(display = "Invalid length of {} raw key: {} bytes, expected {}", 0, 1, 2)]
+    InvalidKeyLength(&'static str, usize, usize),
+
+    /// Database is opened in read-only mode
+    #[fail(display = "Operation is not permitted on database opened in read-only mode")]
+    ReadOnly,
+
+    /// I/O error in underlying database
+    #[fail(display = "Database I/O error: {}", 0)]
+    Io(String),
+
+    /// Underlying database detected data corruption
+    #[fail(display = "Database corruption: {}", 0)]
+    Corruption(String),
+
+    /// Stored cell record is corrupted
+    #[fail(display = "Checksum mismatch in stored record of cell {}", id)]
+    CellChecksumMismatch { id: CellId },
+
+    /// Optimistic transaction conflicts with concurrent write
+    #[fail(display = "Transaction conflict: key {} was changed by another writer", 0)]
+    TransactionConflict(String),
+
+    /// Target path must not exist
+    #[fail(display = "Path already exists: {}", 0)]
+    PathAlreadyExists(String),
+
+    /// Target directory must be absent or empty
+    #[fail(display = "Directory is not empty: {}", 0)]
+    DirectoryNotEmpty(String),
+
+    /// Operation has not completed in time
+    #[fail(display = "Operation timed out: {}", 0)]
+    Timeout(String),
+
+    /// Stored value cannot be deserialized
+    #[fail(display = "Cannot deserialize value of {}", 0)]
+    Deserialization(String),
+
+    /// Cell has more references than allowed
+    #[fail(display = "Cell has too many references: {}", 0)]
+    TooManyReferences(usize),
+
+    /// Reference count doesn't match cell type
+    #[fail(display = "{} cell can't have {} references", 0, 1)]
+    InvalidCellReferences(String, usize),
+
+    /// Stored record of cell is empty
+    #[fail(display = "Stored record of cell {} is empty", id)]
+    EmptyCellData { id: CellId },
+
+    /// Stored record of cell can't be read
+    #[fail(display = "Stored record of cell {} is corrupted: {}", id, reason)]
+    CorruptedCell { id: CellId, reason: String },
+
+    /// Operation requires database to be closed
+    #[fail(display = "Database is open: {}", 0)]
+    DbIsOpen(String),
+
+    /// Queue is full and the operation would block
+    #[fail(display = "Operation would block: queue is full")]
+    WouldBlock,
 }
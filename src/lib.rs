@@ -4,6 +4,7 @@ pub mod block_handle_db;
 pub mod block_index_db;
 pub mod block_info_db;
 pub mod catchain_persistent_db;
+pub mod cell_cache;
 pub mod cell_db;
 pub mod db;
 pub mod dynamic_boc_db;
@@ -13,10 +14,12 @@ pub mod error;
 pub mod lt_db;
 pub mod lt_desc_db;
 pub mod node_state_db;
+pub mod shard_state_store;
 pub mod shardstate_db;
 pub mod shardstate_persistent_db;
 pub mod status_db;
 pub mod traits;
+pub mod typed_db;
 pub mod types;
 
 mod macros;
@@ -64,6 +64,28 @@ macro_rules! db_impl_cbor {
             pub fn put_value(&self, key: &$key_type, value: impl std::borrow::Borrow<$value_type>) -> ton_types::Result<()> {
                 self.put(key, &serde_cbor::to_vec(value.borrow())?)
             }
+
+            /// Tries to get value from given collection or its snapshot
+            #[allow(dead_code)]
+            pub fn try_get_value_from<T>(db: &T, key: &$key_type) -> ton_types::Result<Option<$value_type>>
+            where
+                T: $crate::db::traits::KvcReadable<$key_type> + ?Sized
+            {
+                if let Some(db_slice) = db.try_get(key)? {
+                    return Ok(Some(serde_cbor::from_slice(db_slice.as_ref())?));
+                }
+
+                Ok(None)
+            }
+
+            /// Gets value from given collection or its snapshot
+            #[allow(dead_code)]
+            pub fn get_value_from<T>(db: &T, key: &$key_type) -> ton_types::Result<$value_type>
+            where
+                T: $crate::db::traits::KvcReadable<$key_type> + ?Sized
+            {
+                Ok(serde_cbor::from_slice(db.get(key)?.as_ref())?)
+            }
         }
     }
 }
@@ -1,4 +1,77 @@
+use std::path::Path;
+
+use ton_block::BlockIdExt;
+use ton_types::Result;
+
 use crate::db_impl_base;
 use crate::db::traits::KvcWriteable;
+use crate::traits::Serializable;
 
 db_impl_base!(NodeStateDb, KvcWriteable, &'static str);
+
+const LAST_APPLIED_MC_BLOCK: &str = "LastAppliedMcBlockId";
+const INIT_MC_BLOCK: &str = "InitMcBlockId";
+const SHARDS_CLIENT_MC_BLOCK: &str = "ShardsClientMcBlockId";
+
+/// Typed storage of well-known node state pointers
+#[derive(Debug)]
+pub struct NodeStateStore {
+    db: NodeStateDb,
+}
+
+impl NodeStateStore {
+    /// Constructs new instance using in-memory key-value collection
+    pub fn in_memory() -> Self {
+        Self::with_db(NodeStateDb::in_memory())
+    }
+
+    /// Constructs new instance using RocksDB with given path
+    pub fn with_path<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_db(NodeStateDb::with_path(path))
+    }
+
+    /// Constructs new instance using given database
+    pub fn with_db(db: NodeStateDb) -> Self {
+        Self { db }
+    }
+
+    pub const fn db(&self) -> &NodeStateDb {
+        &self.db
+    }
+
+    pub fn get_last_applied_mc_block(&self) -> Result<Option<BlockIdExt>> {
+        self.get_block_id(LAST_APPLIED_MC_BLOCK)
+    }
+
+    pub fn set_last_applied_mc_block(&self, block_id_ext: &BlockIdExt) -> Result<()> {
+        self.put_block_id(LAST_APPLIED_MC_BLOCK, block_id_ext)
+    }
+
+    pub fn get_init_mc_block(&self) -> Result<Option<BlockIdExt>> {
+        self.get_block_id(INIT_MC_BLOCK)
+    }
+
+    pub fn set_init_mc_block(&self, block_id_ext: &BlockIdExt) -> Result<()> {
+        self.put_block_id(INIT_MC_BLOCK, block_id_ext)
+    }
+
+    pub fn get_shards_client_mc_block(&self) -> Result<Option<BlockIdExt>> {
+        self.get_block_id(SHARDS_CLIENT_MC_BLOCK)
+    }
+
+    pub fn set_shards_client_mc_block(&self, block_id_ext: &BlockIdExt) -> Result<()> {
+        self.put_block_id(SHARDS_CLIENT_MC_BLOCK, block_id_ext)
+    }
+
+    fn get_block_id(&self, key: &'static str) -> Result<Option<BlockIdExt>> {
+        Ok(if let Some(db_slice) = self.db.try_get(&key)? {
+            Some(BlockIdExt::from_slice(db_slice.as_ref())?)
+        } else {
+            None
+        })
+    }
+
+    fn put_block_id(&self, key: &'static str, block_id_ext: &BlockIdExt) -> Result<()> {
+        self.db.put(&key, block_id_ext.to_vec()?.as_slice())
+    }
+}
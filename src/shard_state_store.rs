@@ -0,0 +1,41 @@
+use ton_block::{BlockIdExt, ShardIdent};
+use ton_types::Result;
+
+use crate::db_impl_base;
+use crate::db::traits::KvcWriteable;
+use crate::shardstate_db::DbEntry;
+use crate::traits::Serializable;
+use crate::types::{CellId, ShardIdentKey};
+
+db_impl_base!(ShardStateStore, KvcWriteable, ShardIdentKey);
+
+/// Storage of the latest persisted shard state root for each shard
+impl ShardStateStore {
+    /// Stores root cell id and block id of the latest shard state of given shard
+    pub fn set_state(&self, shard: &ShardIdent, root: &CellId, block_id_ext: &BlockIdExt) -> Result<()> {
+        let db_entry = DbEntry::with_params(root.clone(), block_id_ext.clone());
+        self.put(&ShardIdentKey::new(shard)?, db_entry.to_vec()?.as_slice())
+    }
+
+    /// Gets root cell id and block id of the latest shard state of given shard
+    pub fn get_state(&self, shard: &ShardIdent) -> Result<Option<(CellId, BlockIdExt)>> {
+        Ok(if let Some(db_slice) = self.try_get(&ShardIdentKey::new(shard)?)? {
+            let db_entry = DbEntry::from_slice(db_slice.as_ref())?;
+            Some((db_entry.cell_id, db_entry.block_id_ext))
+        } else {
+            None
+        })
+    }
+
+    /// Iterates over latest shard states of all shards
+    pub fn for_each_state(
+        &self,
+        mut predicate: impl FnMut(ShardIdent, CellId, BlockIdExt) -> Result<bool>
+    ) -> Result<bool> {
+        self.for_each(&mut |key, value| {
+            let shard = ShardIdent::from_slice(key)?;
+            let db_entry = DbEntry::from_slice(value)?;
+            predicate(shard, db_entry.cell_id, db_entry.block_id_ext)
+        })
+    }
+}
@@ -2,22 +2,24 @@ use std::io::{Cursor, Read, Write};
 use std::ops::Deref;
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
 
 use fnv::FnvHashSet;
 
 use ton_block::{BlockIdExt, UnixTime32};
 use ton_types::{Cell, Result};
 
-use crate::block_handle_db::BlockHandleDb;
+use crate::block_handle_db::{BlockHandleDb, BlockHandleStorage};
 use crate::cell_db::CellDb;
 use crate::db::memorydb::MemoryDb;
 use crate::db::rocksdb::RocksDb;
 use crate::db::traits::{DbKey, KvcSnapshotable};
 use crate::dynamic_boc_db::DynamicBocDb;
 use crate::dynamic_boc_diff_writer::DynamicBocDiffWriter;
+use crate::error::StorageError;
 use crate::traits::Serializable;
-use crate::types::{BlockId, CellId, Reference};
+use crate::types::{BlockId, BlockIdHashing, CellId, Reference};
 
 pub struct ShardStateDb {
     shardstate_db: Arc<dyn KvcSnapshotable<BlockId>>,
@@ -122,14 +124,20 @@ pub(crate) trait AllowStateGcResolver: Send + Sync {
 struct AllowStateGcResolverImpl {
     // dynamic_boc_db: Arc<DynamicBocDb>,
     block_handle_db: Arc<BlockHandleDb>,
+    hashing: BlockIdHashing,
     shard_state_ttl: AtomicU32,
 }
 
 impl AllowStateGcResolverImpl {
-    pub fn with_data(/*dynamic_boc_db: Arc<DynamicBocDb>,*/ block_handle_db: Arc<BlockHandleDb>) -> Self {
+    pub fn with_data(
+        /*dynamic_boc_db: Arc<DynamicBocDb>,*/
+        block_handle_db: Arc<BlockHandleDb>,
+        hashing: BlockIdHashing,
+    ) -> Self {
         Self {
             // dynamic_boc_db,
             block_handle_db,
+            hashing,
             shard_state_ttl: AtomicU32::new(3600 * 24),
         }
     }
@@ -147,7 +155,7 @@ impl AllowStateGcResolverImpl {
 
 impl AllowStateGcResolver for AllowStateGcResolverImpl {
     fn allow_state_gc(&self, block_id_ext: &BlockIdExt, gc_utime: UnixTime32) -> Result<bool> {
-        let block_id = BlockId::from(block_id_ext);
+        let block_id = BlockId::with_hashing(block_id_ext.clone(), self.hashing);
         let block_meta = self.block_handle_db.get_value(&block_id)?;
 
         // TODO: Implement more sophisticated logic of decision shard state garbage collecting
@@ -156,6 +164,58 @@ impl AllowStateGcResolver for AllowStateGcResolverImpl {
     }
 }
 
+/// Pacing of garbage collection: after each `cells_per_step` processed cells GC sleeps for `pause`
+#[derive(Debug, Clone, Default)]
+pub struct GcPacing {
+    cells_per_step: usize,
+    pause: Duration,
+}
+
+impl GcPacing {
+    /// Creates pacing which sleeps for `pause` after every `cells_per_step` processed cells
+    pub const fn with_params(cells_per_step: usize, pause: Duration) -> Self {
+        Self { cells_per_step, pause }
+    }
+
+    /// Creates pacing without any pauses
+    pub const fn none() -> Self {
+        Self::with_params(0, Duration::from_millis(0))
+    }
+
+    pub const fn cells_per_step(&self) -> usize {
+        self.cells_per_step
+    }
+
+    pub const fn pause(&self) -> Duration {
+        self.pause
+    }
+}
+
+struct GcPacer<'a> {
+    pacing: &'a GcPacing,
+    cancelled: &'a AtomicBool,
+    processed: usize,
+}
+
+impl<'a> GcPacer<'a> {
+    fn new(pacing: &'a GcPacing, cancelled: &'a AtomicBool) -> Self {
+        Self { pacing, cancelled, processed: 0 }
+    }
+
+    /// Accounts one processed cell, sleeping or aborting when needed
+    fn step(&mut self) -> Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Err(StorageError::Cancelled)?;
+        }
+        self.processed += 1;
+        if self.pacing.cells_per_step > 0 && self.processed % self.pacing.cells_per_step == 0 {
+            std::thread::sleep(self.pacing.pause);
+        }
+
+        Ok(())
+    }
+}
+
 pub struct GC {
     shardstate_db: Arc<dyn KvcSnapshotable<BlockId>>,
     dynamic_boc_db: Arc<DynamicBocDb>,
@@ -163,14 +223,15 @@ pub struct GC {
 }
 
 impl GC {
-    pub fn new(db: &ShardStateDb, block_handle_db: Arc<BlockHandleDb>) -> Self {
+    pub fn new(db: &ShardStateDb, block_handle_storage: &BlockHandleStorage) -> Self {
         Self::with_data(
             db.shardstate_db(),
             db.dynamic_boc_db(),
             Arc::new(
                 AllowStateGcResolverImpl::with_data(
                     // db.dynamic_boc_db(),
-                    block_handle_db
+                    Arc::clone(block_handle_storage.block_handle_db()),
+                    block_handle_storage.hashing(),
                 )
             )
         )
@@ -189,13 +250,24 @@ impl GC {
     }
 
     pub fn collect(&self) -> Result<usize> {
-        let (marked, to_sweep) = self.mark(UnixTime32::now())?;
-        let result = self.sweep(to_sweep, marked);
+        self.collect_paced(&GcPacing::none(), &AtomicBool::new(false))
+    }
+
+    /// Collects garbage sleeping according to given pacing. Setting `cancelled` flag aborts
+    /// collecting with `StorageError::Cancelled`; shard states swept before that stay deleted.
+    pub fn collect_paced(&self, pacing: &GcPacing, cancelled: &AtomicBool) -> Result<usize> {
+        let mut pacer = GcPacer::new(pacing, cancelled);
+        let (marked, to_sweep) = self.mark(UnixTime32::now(), &mut pacer)?;
+        let result = self.sweep(to_sweep, marked, &mut pacer);
 
         result
     }
 
-    fn mark(&self, gc_utime: UnixTime32) -> Result<(FnvHashSet<CellId>, Vec<(BlockId, CellId)>)> {
+    fn mark(
+        &self,
+        gc_utime: UnixTime32,
+        pacer: &mut GcPacer,
+    ) -> Result<(FnvHashSet<CellId>, Vec<(BlockId, CellId)>)> {
         let mut to_mark = Vec::new();
         let mut to_sweep = Vec::new();
         let shardstates = self.shardstate_db.snapshot()?;
@@ -220,40 +292,53 @@ impl GC {
         let mut marked = FnvHashSet::default();
         if to_sweep.len() > 0 {
             for cell_id in to_mark {
-                self.mark_subtree_recursive(cell_id, &mut marked)?;
+                self.mark_subtree_recursive(cell_id, &mut marked, pacer)?;
             }
         }
 
         Ok((marked, to_sweep))
     }
 
-    fn mark_subtree_recursive(&self, cell_id: CellId, marked: &mut FnvHashSet<CellId>) -> Result<()> {
+    fn mark_subtree_recursive(
+        &self,
+        cell_id: CellId,
+        marked: &mut FnvHashSet<CellId>,
+        pacer: &mut GcPacer,
+    ) -> Result<()> {
         if marked.contains(&cell_id) {
             return Ok(());
         }
+        pacer.step()?;
 
         let references = self.load_cell_references(&cell_id)?;
         marked.insert(cell_id);
 
         for reference in references {
-            self.mark_subtree_recursive(reference.hash().into(), marked)?;
+            self.mark_subtree_recursive(reference.hash().into(), marked, pacer)?;
         }
 
         Ok(())
     }
 
-    fn sweep(&self, to_sweep: Vec<(BlockId, CellId)>, marked: FnvHashSet<CellId>) -> Result<usize> {
+    fn sweep(
+        &self,
+        to_sweep: Vec<(BlockId, CellId)>,
+        mut marked: FnvHashSet<CellId>,
+        pacer: &mut GcPacer,
+    ) -> Result<usize> {
         if to_sweep.len() < 1 {
             return Ok(0);
         }
 
-        let diff_writer = self.dynamic_boc_db.diff_factory().construct();
+        // Every shard state is swept in a separate diff, so cancelling leaves storage consistent.
+        // Swept cells are marked to avoid loading them again for the next shard states.
         let mut deleted_count = 0;
         for (block_id, cell_id) in to_sweep {
-            deleted_count += self.sweep_cells_recursive(&diff_writer, cell_id, &marked)?;
+            let diff_writer = self.dynamic_boc_db.diff_factory().construct();
+            deleted_count += self.sweep_cells_recursive(&diff_writer, cell_id, &mut marked, pacer)?;
+            diff_writer.apply()?;
             self.shardstate_db.delete(&block_id)?;
         }
-        diff_writer.apply()?;
 
         Ok(deleted_count)
     }
@@ -262,17 +347,20 @@ impl GC {
         &self,
         diff_writer: &DynamicBocDiffWriter,
         cell_id: CellId,
-        marked: &FnvHashSet<CellId>,
+        marked: &mut FnvHashSet<CellId>,
+        pacer: &mut GcPacer,
     ) -> Result<usize> {
         if marked.contains(&cell_id) {
             return Ok(0);
         }
+        pacer.step()?;
 
         let mut deleted_count = 0;
         let references = self.load_cell_references(&cell_id)?;
         for reference in references {
-            deleted_count += self.sweep_cells_recursive(diff_writer, reference.hash().into(), marked)?;
+            deleted_count += self.sweep_cells_recursive(diff_writer, reference.hash().into(), marked, pacer)?;
         }
+        marked.insert(cell_id.clone());
 
         diff_writer.delete_cell(&cell_id);
         deleted_count += 1;
@@ -283,6 +371,6 @@ impl GC {
     fn load_cell_references(&self, cell_id: &CellId) -> Result<Vec<Reference>> {
         let slice = self.dynamic_boc_db.cell_db().get(cell_id)?;
 
-        Ok(CellDb::deserialize_cell(slice.as_ref())?.1)
+        Ok(CellDb::deserialize_cell(cell_id, slice.as_ref())?.1)
     }
 }
@@ -0,0 +1,101 @@
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use ton_types::Result;
+
+use crate::db::memorydb::MemoryDb;
+use crate::db::rocksdb::RocksDb;
+use crate::db::traits::{DbKey, KvcReadable, KvcTransaction, KvcTransactional};
+use crate::error::StorageError;
+
+/// Key-value collection of values serialized by bincode
+pub struct TypedDb<K: DbKey + Send + Sync, V: Serialize + DeserializeOwned> {
+    db: Box<dyn KvcTransactional<K> + Send + Sync>,
+    phantom: PhantomData<fn() -> V>,
+}
+
+impl<K: DbKey + Send + Sync, V: Serialize + DeserializeOwned> TypedDb<K, V> {
+    /// Constructs new instance over given key-value collection
+    pub fn with_db(db: Box<dyn KvcTransactional<K> + Send + Sync>) -> Self {
+        Self { db, phantom: PhantomData }
+    }
+
+    /// Constructs new instance using in-memory key-value collection
+    pub fn in_memory() -> Self {
+        Self::with_db(Box::new(MemoryDb::new()))
+    }
+
+    /// Constructs new instance using RocksDB with given path
+    pub fn with_path<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_db(Box::new(RocksDb::with_path(path)))
+    }
+
+    /// Tries to get value by the key; returns Ok(None) if the key not found
+    pub fn try_get(&self, key: &K) -> Result<Option<V>> {
+        Self::try_get_from(&*self.db, key)
+    }
+
+    /// Gets value by the key
+    pub fn get(&self, key: &K) -> Result<V> {
+        Self::get_from(&*self.db, key)
+    }
+
+    /// Puts value by the key
+    pub fn put(&self, key: &K, value: &V) -> Result<()> {
+        self.db.put(key, &Self::serialize(value)?)
+    }
+
+    /// Puts value by the key into given transaction
+    pub fn put_to<T: KvcTransaction<K> + ?Sized>(transaction: &T, key: &K, value: &V) -> Result<()> {
+        transaction.put(key, &Self::serialize(value)?);
+        Ok(())
+    }
+
+    /// Deletes value by the key
+    pub fn delete(&self, key: &K) -> Result<()> {
+        self.db.delete(key)
+    }
+
+    /// Tries to get value from given collection or its snapshot
+    pub fn try_get_from<T: KvcReadable<K> + ?Sized>(db: &T, key: &K) -> Result<Option<V>> {
+        db.try_get(key)?
+            .map(|data| Self::deserialize(key, data.as_ref()))
+            .transpose()
+    }
+
+    /// Gets value from given collection or its snapshot
+    pub fn get_from<T: KvcReadable<K> + ?Sized>(db: &T, key: &K) -> Result<V> {
+        Self::deserialize(key, db.get(key)?.as_ref())
+    }
+
+    fn serialize(value: &V) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn deserialize(key: &K, data: &[u8]) -> Result<V> {
+        bincode::deserialize(data)
+            .map_err(|err| StorageError::Deserialization(
+                format!("{} {}: {}", key.key_name(), key.as_string(), err)
+            ).into())
+    }
+}
+
+impl<K: DbKey + Send + Sync, V: Serialize + DeserializeOwned> Debug for TypedDb<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedDb")
+            .field("db", &self.db)
+            .finish()
+    }
+}
+
+impl<K: DbKey + Send + Sync, V: Serialize + DeserializeOwned> Deref for TypedDb<K, V> {
+    type Target = dyn KvcTransactional<K> + Send + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        self.db.deref()
+    }
+}
@@ -9,21 +9,6 @@ use crate::block_handle_db::BlockHandleCache;
 use crate::traits::Serializable;
 use crate::types::BlockMeta;
 
-const FLAG_DATA: u32 = 1;
-const FLAG_PROOF: u32 = 1 << 1;
-const FLAG_PROOF_LINK: u32 = 1 << 2;
-const FLAG_EXT_DB: u32 = 1 << 3;
-const FLAG_STATE: u32 = 1 << 4;
-const FLAG_PERSISTENT_STATE: u32 = 1 << 5;
-const FLAG_NEXT_1: u32 = 1 << 6;
-const FLAG_NEXT_2: u32 = 1 << 7;
-const FLAG_PREV_1: u32 = 1 << 8;
-const FLAG_PREV_2: u32 = 1 << 9;
-const FLAG_APPLIED: u32 = 1 << 10;
-const FLAG_KEY_BLOCK: u32 = 1 << 11;
-const FLAG_MOVED_TO_ARCHIVE: u32 = 1 << 13;
-const FLAG_INDEXED: u32 = 1 << 14;
-
 /// Meta information related to block
 #[derive(Debug)]
 pub struct BlockHandle {
@@ -36,7 +21,7 @@ pub struct BlockHandle {
 
 impl BlockHandle {
     pub fn new(id: BlockIdExt, block_handle_cache: BlockHandleCache) -> Self {
-        Self::with_values(id, BlockMeta::default(), block_handle_cache)
+        Self::with_values(id, BlockMeta::created_now(), block_handle_cache)
     }
 
     pub fn with_values(id: BlockIdExt, meta: BlockMeta, block_handle_cache: BlockHandleCache) -> Self {
@@ -56,7 +41,7 @@ impl BlockHandle {
     pub fn fetch_shard_state(&self, ss: &ShardStateUnsplit) -> Result<()> {
         self.meta.gen_utime().store(ss.gen_time(), Ordering::SeqCst);
         if ss.read_custom()?.map(|c| c.after_key_block).unwrap_or(false) {
-            self.set_flags(FLAG_KEY_BLOCK);
+            self.set_flags(BlockMeta::FLAG_KEY_BLOCK);
         }
         self.meta.set_fetched();
         Ok(())
@@ -65,7 +50,7 @@ impl BlockHandle {
     fn fetch_info(&self, info: &BlockInfo) -> Result<()> {
         self.meta.gen_utime().store(info.gen_utime().0, Ordering::SeqCst);
         if info.key_block() {
-            self.set_flags(FLAG_KEY_BLOCK);
+            self.set_flags(BlockMeta::FLAG_KEY_BLOCK);
         }
         self.meta.set_fetched();
         Ok(())
@@ -79,49 +64,49 @@ impl BlockHandle {
 
     // TODO: Give correct name due to actual meaning (not "inited", but "saved" or "stored")
     pub fn set_data_inited(&self) -> bool {
-        self.set_flags(FLAG_DATA)
+        self.set_flags(BlockMeta::FLAG_DATA)
     }
 
     // TODO: Give correct name due to actual meaning (not "inited", but "saved" or "stored")
     pub fn set_proof_inited(&self) -> bool {
-        self.set_flags(FLAG_PROOF)
+        self.set_flags(BlockMeta::FLAG_PROOF)
     }
 
     // TODO: Give correct name due to actual meaning (not "inited", but "saved" or "stored")
     pub fn set_proof_link_inited(&self) -> bool {
-        self.set_flags(FLAG_PROOF_LINK)
+        self.set_flags(BlockMeta::FLAG_PROOF_LINK)
     }
 
     pub fn set_processed_in_ext_db(&self) -> bool {
-        self.set_flags(FLAG_EXT_DB)
+        self.set_flags(BlockMeta::FLAG_EXT_DB)
     }
 
     pub fn set_state_inited(&self) -> bool {
-        self.set_flags(FLAG_STATE)
+        self.set_flags(BlockMeta::FLAG_STATE)
     }
 
     pub fn set_persistent_state_inited(&self) -> bool {
-        self.set_flags(FLAG_PERSISTENT_STATE)
+        self.set_flags(BlockMeta::FLAG_PERSISTENT_STATE)
     }
 
     pub fn set_next1_inited(&self) -> bool {
-        self.set_flags(FLAG_NEXT_1)
+        self.set_flags(BlockMeta::FLAG_NEXT_1)
     }
 
     pub fn set_next2_inited(&self) -> bool {
-        self.set_flags(FLAG_NEXT_2)
+        self.set_flags(BlockMeta::FLAG_NEXT_2)
     }
 
     pub fn set_prev1_inited(&self) -> bool {
-        self.set_flags(FLAG_PREV_1)
+        self.set_flags(BlockMeta::FLAG_PREV_1)
     }
 
     pub fn set_prev2_inited(&self) -> bool {
-        self.set_flags(FLAG_PREV_2)
+        self.set_flags(BlockMeta::FLAG_PREV_2)
     }
 
     pub fn set_applied(&self) -> bool {
-        self.set_flags(FLAG_APPLIED)
+        self.set_flags(BlockMeta::FLAG_APPLIED)
     }
 
     pub fn id(&self) -> &BlockIdExt {
@@ -133,12 +118,12 @@ impl BlockHandle {
     }
 
     pub fn set_indexed(&self) -> bool {
-        self.set_flags(FLAG_INDEXED)
+        self.set_flags(BlockMeta::FLAG_INDEXED)
     }
 
     // TODO: Give correct name due to actual meaning (not "inited", but "saved" or "stored")
     pub fn data_inited(&self) -> bool {
-        self.flags_all(FLAG_DATA)
+        self.flags_all(BlockMeta::FLAG_DATA)
     }
 
     // TODO: Give correct name due to actual meaning (not "inited", but "saved" or "stored")
@@ -146,7 +131,7 @@ impl BlockHandle {
         if cfg!(feature = "local_test") {
             true
         } else {
-            self.flags_all(FLAG_PROOF)
+            self.flags_all(BlockMeta::FLAG_PROOF)
         }
     }
 
@@ -155,7 +140,7 @@ impl BlockHandle {
         if cfg!(feature = "local_test") {
             true
         } else {
-            self.flags_all(FLAG_PROOF_LINK)
+            self.flags_all(BlockMeta::FLAG_PROOF_LINK)
         }
     }
 
@@ -170,39 +155,39 @@ impl BlockHandle {
     }
 
     pub fn processed_in_ext_db(&self) -> bool {
-        self.flags_all(FLAG_EXT_DB)
+        self.flags_all(BlockMeta::FLAG_EXT_DB)
     }
 
     pub fn state_inited(&self) -> bool {
-        self.flags_all(FLAG_STATE)
+        self.flags_all(BlockMeta::FLAG_STATE)
     }
 
     pub fn persistent_state_inited(&self) -> bool {
-        self.flags_all(FLAG_PERSISTENT_STATE)
+        self.flags_all(BlockMeta::FLAG_PERSISTENT_STATE)
     }
 
     pub fn next1_inited(&self) -> bool {
-        self.flags_all(FLAG_NEXT_1)
+        self.flags_all(BlockMeta::FLAG_NEXT_1)
     }
 
     pub fn next2_inited(&self) -> bool {
-        self.flags_all(FLAG_NEXT_2)
+        self.flags_all(BlockMeta::FLAG_NEXT_2)
     }
 
     pub fn prev1_inited(&self) -> bool {
-        self.flags_all(FLAG_PREV_1)
+        self.flags_all(BlockMeta::FLAG_PREV_1)
     }
 
     pub fn prev2_inited(&self) -> bool {
-        self.flags_all(FLAG_PREV_2)
+        self.flags_all(BlockMeta::FLAG_PREV_2)
     }
 
     pub fn applied(&self) -> bool {
-        self.flags_all(FLAG_APPLIED)
+        self.flags_all(BlockMeta::FLAG_APPLIED)
     }
 
     pub fn indexed(&self) -> bool {
-        self.flags_all(FLAG_INDEXED)
+        self.flags_all(BlockMeta::FLAG_INDEXED)
     }
 
     pub fn gen_lt(&self) -> u64 {
@@ -243,11 +228,12 @@ impl BlockHandle {
     }
 
     pub fn moved_to_archive(&self) -> bool {
-        self.flags_all(FLAG_MOVED_TO_ARCHIVE)
+        self.flags_all(BlockMeta::FLAG_MOVED_TO_ARCHIVE)
     }
 
+    /// Returns true, if the block was already marked as moved to archive
     pub fn set_moved_to_archive(&self) -> bool {
-        self.set_flags(FLAG_MOVED_TO_ARCHIVE)
+        self.meta.set_moved_to_archive()
     }
 
     pub fn fetched(&self) -> bool {
@@ -256,7 +242,7 @@ impl BlockHandle {
 
     pub fn is_key_block(&self) -> Result<bool> {
         if self.fetched() {
-            Ok(self.flags_all(FLAG_KEY_BLOCK))
+            Ok(self.flags_all(BlockMeta::FLAG_KEY_BLOCK))
         } else {
             fail!("Data is not inited yet")
         }
@@ -270,19 +256,14 @@ impl BlockHandle {
         &self.temp_lock
     }
 
-    #[inline]
-    fn flags(&self) -> u32 {
-        self.meta.flags().load(Ordering::SeqCst)
-    }
-
     #[inline]
     fn flags_all(&self, flags: u32) -> bool {
-        self.flags() & flags == flags
+        self.meta.has_flags(flags)
     }
 
     #[inline]
     fn set_flags(&self, flags: u32) -> bool {
-        self.meta.flags().fetch_or(flags, Ordering::SeqCst) & flags == flags
+        self.meta.set_flags(flags)
     }
 }
 
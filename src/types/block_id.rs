@@ -3,8 +3,77 @@ use std::fmt::{Display, Formatter};
 use sha2::{Digest, Sha256};
 
 use ton_block::BlockIdExt;
+use ton_types::{fail, Result};
 
 use crate::db::traits::DbKey;
+use crate::traits::Serializable;
+
+/// Length of serialized BlockIdExt
+const BLOCK_ID_EXT_LEN: usize = 80;
+
+/// Length of key made of raw fields of BlockIdExt
+const RAW_KEY_LEN: usize = 80;
+
+/// Scheme of deriving database key from BlockIdExt. Keys of different schemes differ,
+/// so database must be always used with the scheme it was built with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockIdHashing {
+    /// SHA-256 over id fields, 32-byte keys
+    Sha256,
+    /// Concatenation of id fields, which are unique already: 80-byte keys with no hashing cost
+    Raw,
+}
+
+impl Default for BlockIdHashing {
+    fn default() -> Self {
+        BlockIdHashing::Sha256
+    }
+}
+
+impl BlockIdHashing {
+    /// Gets marker of the scheme to be stored in database
+    pub const fn marker(self) -> u8 {
+        match self {
+            BlockIdHashing::Sha256 => 0,
+            BlockIdHashing::Raw => 1,
+        }
+    }
+
+    /// Gets scheme by its stored marker
+    pub fn from_marker(marker: u8) -> Result<Self> {
+        match marker {
+            0 => Ok(BlockIdHashing::Sha256),
+            1 => Ok(BlockIdHashing::Raw),
+            _ => fail!("Unknown block id hashing scheme {}", marker),
+        }
+    }
+
+    fn key(self, block_id_ext: &BlockIdExt) -> Vec<u8> {
+        let workchain_id = block_id_ext.shard_id.workchain_id().to_le_bytes();
+        let shard = block_id_ext.shard_id.shard_prefix_with_tag().to_le_bytes();
+        let seq_no = block_id_ext.seq_no.to_le_bytes();
+        match self {
+            BlockIdHashing::Sha256 => {
+                let mut hasher = Sha256::new();
+                hasher.input(workchain_id);
+                hasher.input(shard);
+                hasher.input(seq_no);
+                hasher.input(block_id_ext.root_hash.as_slice());
+                hasher.input(block_id_ext.file_hash.as_slice());
+                hasher.result().to_vec()
+            }
+            BlockIdHashing::Raw => {
+                let mut key = Vec::with_capacity(RAW_KEY_LEN);
+                key.extend_from_slice(&workchain_id);
+                key.extend_from_slice(&shard);
+                key.extend_from_slice(&seq_no);
+                key.extend_from_slice(block_id_ext.root_hash.as_slice());
+                key.extend_from_slice(block_id_ext.file_hash.as_slice());
+                key
+            }
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct BlockId {
@@ -13,22 +82,38 @@ pub struct BlockId {
 }
 
 impl BlockId {
+    /// Creates block id with key derived by given scheme
+    pub fn with_hashing(block_id_ext: BlockIdExt, hashing: BlockIdHashing) -> Self {
+        let key = hashing.key(&block_id_ext);
+        Self { key, block_id_ext }
+    }
+
     pub const fn block_id_ext(&self) -> &BlockIdExt {
         &self.block_id_ext
     }
+
+    /// Rebuilds block id from stored key and value, which ends with serialized BlockIdExt.
+    /// Hashing scheme is recognized by key length. Fails if the value has no BlockIdExt or it doesn't match the key
+    pub fn from_key_and_value(key: &[u8], value: &[u8]) -> Result<Self> {
+        if value.len() < BLOCK_ID_EXT_LEN {
+            fail!("Value of {} bytes has no stored BlockIdExt", value.len());
+        }
+        let hashing = if key.len() == RAW_KEY_LEN { BlockIdHashing::Raw } else { BlockIdHashing::Sha256 };
+        let block_id = Self::with_hashing(
+            BlockIdExt::from_slice(&value[value.len() - BLOCK_ID_EXT_LEN..])?,
+            hashing
+        );
+        if block_id.key != key {
+            fail!("Stored BlockIdExt {} doesn't match key {}", block_id.block_id_ext, hex::encode(key));
+        }
+
+        Ok(block_id)
+    }
 }
 
 impl From<BlockIdExt> for BlockId {
     fn from(block_id_ext: BlockIdExt) -> Self {
-        let mut hasher = Sha256::new();
-        hasher.input(block_id_ext.shard_id.workchain_id().to_le_bytes());
-        hasher.input(block_id_ext.shard_id.shard_prefix_with_tag().to_le_bytes());
-        hasher.input(block_id_ext.seq_no.to_le_bytes());
-        hasher.input(block_id_ext.root_hash.as_slice());
-        hasher.input(block_id_ext.file_hash.as_slice());
-        let key = hasher.result().to_vec();
-
-        Self { key, block_id_ext }
+        Self::with_hashing(block_id_ext, BlockIdHashing::default())
     }
 }
 
@@ -57,3 +142,39 @@ impl DbKey for BlockId {
         &self.key
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ton_block::ShardIdent;
+    use ton_types::UInt256;
+
+    use super::*;
+    use crate::db::traits::assert_dbkey_stable;
+
+    fn block_ids(hashing: BlockIdHashing) -> Result<Vec<BlockId>> {
+        let shard = ShardIdent::with_tagged_prefix(0, 0x8000_0000_0000_0000)?;
+        let ids = vec![
+            BlockIdExt::default(),
+            BlockIdExt::with_params(shard.clone(), 1, UInt256::default(), UInt256::default()),
+            BlockIdExt::with_params(shard.clone(), 2, UInt256::default(), UInt256::default()),
+            BlockIdExt::with_params(shard.clone(), 1, UInt256::from([1; 32]), UInt256::default()),
+            BlockIdExt::with_params(shard, 1, UInt256::default(), UInt256::from([1; 32])),
+        ];
+
+        Ok(ids.into_iter().map(|id| BlockId::with_hashing(id, hashing)).collect())
+    }
+
+    #[test]
+    fn test_block_id_is_stable() -> Result<()> {
+        for &hashing in &[BlockIdHashing::Sha256, BlockIdHashing::Raw] {
+            let keys = block_ids(hashing)?;
+            assert_dbkey_stable(&keys);
+            for key in keys {
+                let value = key.block_id_ext().to_vec()?;
+                assert_eq!(BlockId::from_key_and_value(key.key(), &value)?, key);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use std::io::{Read, Write};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use tokio::sync::RwLock;
 
@@ -7,6 +8,9 @@ use ton_types::{ByteOrderRead, Result};
 
 use crate::traits::Serializable;
 
+/// Encoding version with creation and archiving times. Version 0 has no them
+const VERSION_TIMESTAMPS: u8 = 1;
+
 #[derive(Debug, Default)]
 pub struct BlockMeta {
     flags: AtomicU32,
@@ -14,11 +18,28 @@ pub struct BlockMeta {
     gen_lt: AtomicU64,
     masterchain_ref_seq_no: AtomicU32,
     fetched: AtomicBool,
+    created_at: AtomicU32,
+    archived_at: AtomicU32,
     moving_to_archive_started: AtomicBool,
     temp_lock: RwLock<()>,
 }
 
 impl BlockMeta {
+    pub const FLAG_DATA: u32 = 1;
+    pub const FLAG_PROOF: u32 = 1 << 1;
+    pub const FLAG_PROOF_LINK: u32 = 1 << 2;
+    pub const FLAG_EXT_DB: u32 = 1 << 3;
+    pub const FLAG_STATE: u32 = 1 << 4;
+    pub const FLAG_PERSISTENT_STATE: u32 = 1 << 5;
+    pub const FLAG_NEXT_1: u32 = 1 << 6;
+    pub const FLAG_NEXT_2: u32 = 1 << 7;
+    pub const FLAG_PREV_1: u32 = 1 << 8;
+    pub const FLAG_PREV_2: u32 = 1 << 9;
+    pub const FLAG_APPLIED: u32 = 1 << 10;
+    pub const FLAG_KEY_BLOCK: u32 = 1 << 11;
+    pub const FLAG_MOVED_TO_ARCHIVE: u32 = 1 << 13;
+    pub const FLAG_INDEXED: u32 = 1 << 14;
+
     pub fn with_data(flags: u32, gen_utime: u32, gen_lt: u64, masterchain_ref_seq_no: u32, fetched: bool) -> Self {
         Self {
             flags: AtomicU32::new(flags),
@@ -26,6 +47,8 @@ impl BlockMeta {
             gen_lt: AtomicU64::new(gen_lt),
             masterchain_ref_seq_no: AtomicU32::new(masterchain_ref_seq_no),
             fetched: AtomicBool::new(fetched),
+            created_at: AtomicU32::new(0),
+            archived_at: AtomicU32::new(0),
             moving_to_archive_started: AtomicBool::new(false),
             temp_lock: RwLock::new(()),
         }
@@ -47,6 +70,79 @@ impl BlockMeta {
         &self.masterchain_ref_seq_no
     }
 
+    /// Unix time of the handle creation; zero for handles stored before it was tracked
+    pub const fn created_at(&self) -> &AtomicU32 {
+        &self.created_at
+    }
+
+    /// Unix time of marking the block as moved to archive; zero if not marked or not tracked
+    pub const fn archived_at(&self) -> &AtomicU32 {
+        &self.archived_at
+    }
+
+    /// Creates empty meta of new handle, with creation time set to now
+    pub fn created_now() -> Self {
+        let meta = Self::default();
+        meta.created_at.store(now(), Ordering::SeqCst);
+        meta
+    }
+
+    /// Returns true, if all given flags are set
+    pub fn has_flags(&self, flags: u32) -> bool {
+        self.flags.load(Ordering::SeqCst) & flags == flags
+    }
+
+    /// Sets given flags atomically. Returns true, if all of them were already set
+    pub fn set_flags(&self, flags: u32) -> bool {
+        self.flags.fetch_or(flags, Ordering::SeqCst) & flags == flags
+    }
+
+    pub fn has_data(&self) -> bool {
+        self.has_flags(Self::FLAG_DATA)
+    }
+
+    pub fn has_proof(&self) -> bool {
+        self.has_flags(Self::FLAG_PROOF)
+    }
+
+    pub fn has_proof_link(&self) -> bool {
+        self.has_flags(Self::FLAG_PROOF_LINK)
+    }
+
+    pub fn has_state(&self) -> bool {
+        self.has_flags(Self::FLAG_STATE)
+    }
+
+    pub fn has_persistent_state(&self) -> bool {
+        self.has_flags(Self::FLAG_PERSISTENT_STATE)
+    }
+
+    pub fn is_applied(&self) -> bool {
+        self.has_flags(Self::FLAG_APPLIED)
+    }
+
+    /// Note: the flag is valid only for fetched block
+    pub fn is_key_block(&self) -> bool {
+        self.has_flags(Self::FLAG_KEY_BLOCK)
+    }
+
+    pub fn is_moved_to_archive(&self) -> bool {
+        self.has_flags(Self::FLAG_MOVED_TO_ARCHIVE)
+    }
+
+    /// Returns true, if the block was already marked as moved to archive
+    pub fn set_moved_to_archive(&self) -> bool {
+        let was_set = self.set_flags(Self::FLAG_MOVED_TO_ARCHIVE);
+        if !was_set {
+            self.archived_at.store(now(), Ordering::SeqCst);
+        }
+        was_set
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.has_flags(Self::FLAG_INDEXED)
+    }
+
     pub fn fetched(&self) -> bool {
         self.fetched.load(Ordering::SeqCst)
     }
@@ -62,8 +158,9 @@ impl Serializable for BlockMeta {
         writer.write_all(&self.gen_utime.load(Ordering::SeqCst).to_le_bytes())?;
         writer.write_all(&self.gen_lt.load(Ordering::SeqCst).to_le_bytes())?;
         writer.write_all(&self.masterchain_ref_seq_no.load(Ordering::SeqCst).to_le_bytes())?;
-        writer.write_all(&[self.fetched() as u8])?;
-
+        writer.write_all(&[self.fetched() as u8 | (VERSION_TIMESTAMPS << 1)])?;
+        writer.write_all(&self.created_at.load(Ordering::SeqCst).to_le_bytes())?;
+        writer.write_all(&self.archived_at.load(Ordering::SeqCst).to_le_bytes())?;
 
         Ok(())
     }
@@ -73,10 +170,20 @@ impl Serializable for BlockMeta {
         let gen_utime = reader.read_le_u32()?;
         let gen_lt = reader.read_le_u64()?;
         let masterchain_ref_seq_no = reader.read_le_u32()?;
-        let fetched = reader.read_byte()? != 0;
+        // Lowest bit is fetched flag, the rest is version of encoding
+        let fetched_and_version = reader.read_byte()?;
+        let fetched = fetched_and_version & 1 != 0;
         let bm = Self::with_data(flags, gen_utime, gen_lt, masterchain_ref_seq_no, fetched);
-
+        if fetched_and_version >> 1 >= VERSION_TIMESTAMPS {
+            bm.created_at.store(reader.read_le_u32()?, Ordering::SeqCst);
+            bm.archived_at.store(reader.read_le_u32()?, Ordering::SeqCst);
+        }
 
         Ok(bm)
     }
 }
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as u32)
+}
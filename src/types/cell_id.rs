@@ -1,6 +1,10 @@
-use ton_types::types::UInt256;
+use std::convert::TryFrom;
 use std::fmt::{Display, Formatter, Debug};
+
+use ton_types::types::UInt256;
+
 use crate::db::traits::DbKey;
+use crate::error::StorageError;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct CellId {
@@ -11,6 +15,16 @@ impl CellId {
     pub const fn new(hash: UInt256) -> Self {
         Self { hash }
     }
+
+    /// Constructs cell id from representation hash of the cell
+    pub const fn from_hash(hash: UInt256) -> Self {
+        Self::new(hash)
+    }
+
+    /// Returns representation hash of the cell
+    pub const fn as_hash(&self) -> &UInt256 {
+        &self.hash
+    }
 }
 
 impl Display for CellId {
@@ -46,3 +60,14 @@ impl Into<UInt256> for CellId {
         self.hash
     }
 }
+
+impl TryFrom<&[u8]> for CellId {
+    type Error = StorageError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let hash = <[u8; 32]>::try_from(value)
+            .map_err(|_| StorageError::InvalidKeyLength("CellId", value.len(), 32))?;
+
+        Ok(Self::from_hash(hash.into()))
+    }
+}
@@ -3,10 +3,27 @@ use std::ops::Deref;
 
 /// Represents memory slice, returned by database (in a case of RocksDB), or vector, in a case of MemoryDb
 pub enum DbSlice<'a> {
+    /// Borrows value pinned in RocksDB block cache, so it can't outlive the database
     RocksDb(DBPinnableSlice<'a>),
+    /// Owns value bytes
     Vector(Vec<u8>)
 }
 
+impl DbSlice<'_> {
+    /// Copies value bytes into a new vector
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.as_ref().to_vec()
+    }
+
+    /// Converts into vector of value bytes. Owned bytes are moved without copying
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            DbSlice::RocksDb(slice) => slice.as_ref().to_vec(),
+            DbSlice::Vector(vector) => vector,
+        }
+    }
+}
+
 impl AsRef<[u8]> for DbSlice<'_> {
     fn as_ref(&self) -> &[u8] {
         match self {
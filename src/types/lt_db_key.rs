@@ -26,3 +26,25 @@ impl DbKey for LtDbKey {
         self.0.as_slice()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::traits::assert_dbkey_stable;
+
+    #[test]
+    fn test_lt_db_key_is_stable() -> Result<()> {
+        let left = ShardIdent::with_tagged_prefix(0, 0x4000_0000_0000_0000)?;
+        let right = ShardIdent::with_tagged_prefix(0, 0xC000_0000_0000_0000)?;
+        let keys = vec![
+            LtDbKey::with_values(&left, 0)?,
+            LtDbKey::with_values(&left, 1)?,
+            LtDbKey::with_values(&right, 0)?,
+            LtDbKey::with_values(&right, 1)?,
+            LtDbKey::with_values(&ShardIdent::masterchain(), 0)?,
+        ];
+        assert_dbkey_stable(&keys);
+
+        Ok(())
+    }
+}
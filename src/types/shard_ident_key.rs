@@ -30,3 +30,26 @@ impl DbKey for ShardIdentKey {
         self.0.as_slice()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::traits::assert_dbkey_stable;
+
+    #[test]
+    fn test_shard_ident_key_is_stable() -> Result<()> {
+        let shards = vec![
+            ShardIdent::masterchain(),
+            ShardIdent::with_tagged_prefix(0, 0x8000_0000_0000_0000)?,
+            ShardIdent::with_tagged_prefix(0, 0x4000_0000_0000_0000)?,
+            ShardIdent::with_tagged_prefix(0, 0xC000_0000_0000_0000)?,
+        ];
+        let keys = shards.iter().map(ShardIdentKey::new).collect::<Result<Vec<_>>>()?;
+        assert_dbkey_stable(&keys);
+        for (key, shard) in keys.iter().zip(&shards) {
+            assert_eq!(key.as_string(), format!("{}", shard));
+        }
+
+        Ok(())
+    }
+}
@@ -5,6 +5,8 @@ use crate::db::traits::DbKey;
 #[derive(Debug, AsRefStr)]
 pub enum StatusKey {
     // TODO: Reserved for DynamicBocDb
+    /// Scheme of block id keys of block handle database, see `BlockIdHashing`
+    BlockIdHashing,
 }
 
 impl DbKey for StatusKey {
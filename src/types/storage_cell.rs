@@ -10,7 +10,7 @@ use crate::{
 #[derive(Debug)]
 pub struct StorageCell {
     cell_data: CellData,
-    references: RwLock<Vec<Reference>>,
+    references: Vec<RwLock<Reference>>,
     boc_db: Arc<DynamicBocDb>,
 }
 
@@ -24,7 +24,7 @@ impl StorageCell {
     ) -> Self {
         Self {
             cell_data,
-            references: RwLock::new(references),
+            references: references.into_iter().map(RwLock::new).collect(),
             boc_db,
         }
     }
@@ -39,18 +39,40 @@ impl StorageCell {
         self.hash(MAX_LEVEL as usize)
     }
 
+    /// Gets referenced cell. Loaded cell is memoized in the reference slot, so subsequent
+    /// accesses don't query the database. If the slot is loaded concurrently, the cell loaded
+    /// first is kept and returned
     pub(crate) fn reference(&self, index: usize) -> Result<Arc<StorageCell>> {
-        let hash = match &self.references.read().expect("Poisoned RwLock")[index]
-        {
+        let slot = &self.references[index];
+        let hash = match &*slot.read().expect("Poisoned RwLock") {
             Reference::Loaded(cell) => return Ok(Arc::clone(cell)),
             Reference::NeedToLoad(hash) => hash.clone()
         };
 
-        let cell_id = CellId::from(hash.clone());
-        let storage_cell = self.boc_db.load_cell(&cell_id)?;
-        self.references.write().expect("Poisoned RwLock")[index] = Reference::Loaded(Arc::clone(&storage_cell));
+        let storage_cell = self.boc_db.load_cell(&CellId::from(hash))?;
 
-        Ok(storage_cell)
+        Ok(Self::memoize(slot, storage_cell))
+    }
+
+    /// Gets copy of references as they are now: loaded or not
+    pub(crate) fn references(&self) -> Vec<Reference> {
+        self.references.iter()
+            .map(|slot| slot.read().expect("Poisoned RwLock").clone())
+            .collect()
+    }
+
+    pub(crate) fn set_loaded_reference(&self, index: usize, cell: Arc<StorageCell>) {
+        Self::memoize(&self.references[index], cell);
+    }
+
+    fn memoize(slot: &RwLock<Reference>, cell: Arc<StorageCell>) -> Arc<StorageCell> {
+        let mut reference = slot.write().expect("Poisoned RwLock");
+        if let Reference::Loaded(loaded) = &*reference {
+            return Arc::clone(loaded);
+        }
+        *reference = Reference::Loaded(Arc::clone(&cell));
+
+        cell
     }
 }
 
@@ -68,7 +90,7 @@ impl CellImpl for StorageCell {
     }
 
     fn references_count(&self) -> usize {
-        self.references.read().expect("Poisoned RwLock").len()
+        self.references.len()
     }
 
     fn reference(&self, index: usize) -> Result<Cell> {
@@ -96,9 +118,9 @@ impl CellImpl for StorageCell {
     }
 }
 
-fn references_hashes_equal(left: &Vec<Reference>, right: &Vec<Reference>) -> bool {
-    for i in 0..left.len() {
-        if left[i].hash() != right[i].hash() {
+fn references_hashes_equal(left: &[RwLock<Reference>], right: &[RwLock<Reference>]) -> bool {
+    for (left, right) in left.iter().zip(right) {
+        if left.read().expect("Poisoned RwLock").hash() != right.read().expect("Poisoned RwLock").hash() {
             return false;
         }
     }
@@ -119,9 +141,7 @@ impl PartialEq for StorageCell {
             return false;
         }
 
-        let self_guard = self.references.read().expect("Poisoned RwLock");
-        let other_guard = other.references.read().expect("Poisoned RwLock");
-        self_guard.len() == other_guard.len()
-            && references_hashes_equal(&self_guard, &other_guard)
+        self.references.len() == other.references.len()
+            && references_hashes_equal(&self.references, &other.references)
     }
 }
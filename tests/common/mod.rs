@@ -0,0 +1,39 @@
+#![allow(dead_code)]
+
+use ton_types::{BuilderData, Cell};
+
+use ton_node_storage::types::CellId;
+
+pub fn build_cell(data: u32, references: &[Cell]) -> Cell {
+    let mut builder = BuilderData::new();
+    builder.append_u32(data).unwrap();
+    for reference in references {
+        builder.checked_append_reference(reference.clone()).unwrap();
+    }
+    builder.into_cell().unwrap()
+}
+
+pub fn cell_id(cell: &Cell) -> CellId {
+    CellId::new(cell.repr_hash())
+}
+
+/// Builds tree of four levels, where leaves and middle cells are shared between parents
+pub fn build_shared_tree(seed: u32) -> Cell {
+    let leaves: Vec<_> = (0..16).map(|i| build_cell(seed + i, &[])).collect();
+    let middles: Vec<_> = (0..16)
+        .map(|i| build_cell(seed + 100 + i as u32, &[
+            leaves[i % 16].clone(),
+            leaves[(i * 3 + 1) % 16].clone(),
+            leaves[(i * 5 + 2) % 16].clone(),
+        ]))
+        .collect();
+    let tops: Vec<_> = (0..4)
+        .map(|i| build_cell(seed + 200 + i as u32, &[
+            middles[i * 3].clone(),
+            middles[i * 3 + 1].clone(),
+            middles[i * 3 + 4].clone(),
+            middles[(i * 7) % 16].clone(),
+        ]))
+        .collect();
+    build_cell(seed + 300, &tops)
+}
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use ton_types::Result;
+
+use ton_node_storage::archives::package::{read_package_from_file, Package};
+use ton_node_storage::archives::package_entry::{PackageCompression, PackageEntry};
+
+#[tokio::test]
+async fn test_package_reads_compressed_and_stored_entries() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = Arc::new(dir.path().join("test.pack"));
+    let data: Vec<u8> = (0..8192u32).map(|i| (i % 13) as u8).collect();
+
+    let package = Package::open_with_compression(Arc::clone(&path), false, true, PackageCompression::Zstd(3)).await?;
+    let mut offsets = Vec::new();
+    package.append_entry(&PackageEntry::with_data("compressed".to_string(), data.clone()),
+        |offset, _| { offsets.push(offset); Ok(()) }
+    ).await?;
+    package.append_entry_with_compression(
+        &PackageEntry::with_data("stored".to_string(), data.clone()),
+        PackageCompression::Stored,
+        |offset, _| { offsets.push(offset); Ok(()) }
+    ).await?;
+    // Compressed entry takes less space than the stored one
+    assert!(offsets[1] - offsets[0] < package.size() - offsets[1]);
+    drop(package);
+
+    let package = Package::open(Arc::clone(&path), true, false).await?;
+    for (offset, filename) in offsets.iter().zip(&["compressed", "stored"]) {
+        let entry = package.read_entry(*offset).await?;
+        assert_eq!(entry.filename(), filename);
+        assert_eq!(entry.data(), &data);
+    }
+
+    let mut reader = read_package_from_file(&*path).await?;
+    let mut filenames = Vec::new();
+    while let Some(entry) = reader.next().await? {
+        assert_eq!(entry.data(), &data);
+        filenames.push(entry.filename().clone());
+    }
+    assert_eq!(filenames, vec!["compressed", "stored"]);
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ton_api::ton::PublicKey;
+use ton_block::{BlockIdExt, ShardIdent};
+use ton_types::{Result, UInt256};
+
+use ton_node_storage::archives::archive_manager::ArchiveManager;
+use ton_node_storage::archives::archive_writer::{ArchiveWriter, Backpressure};
+use ton_node_storage::archives::package_entry_id::PackageEntryId;
+use ton_node_storage::block_handle_db::{BlockHandleDb, BlockHandleStorage};
+use ton_node_storage::status_db::StatusDb;
+use ton_node_storage::types::BlockHandle;
+
+const BLOCKS: u32 = 500;
+
+fn entry_id(handle: &BlockHandle) -> PackageEntryId<BlockIdExt, UInt256, PublicKey> {
+    PackageEntryId::Block(handle.id().clone())
+}
+
+fn entry_data(seq_no: u32) -> Vec<u8> {
+    format!("block {};", seq_no).repeat(1 + seq_no as usize % 100).into_bytes()
+}
+
+#[test]
+fn test_flush_and_stop_writes_all_queued_entries() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let root = Arc::new(PathBuf::from(dir.path()));
+    let mut runtime = tokio::runtime::Builder::new().basic_scheduler().enable_all().build()?;
+
+    let storage = BlockHandleStorage::new(Arc::new(BlockHandleDb::in_memory()), &StatusDb::in_memory())?;
+    let handles = (0..BLOCKS)
+        .map(|seq_no| {
+            let handle = storage.load_block_handle(&BlockIdExt::with_params(
+                ShardIdent::masterchain(), seq_no, UInt256::from([seq_no as u8; 32]), UInt256::default()
+            ))?;
+            handle.meta().set_fetched();
+            Ok(handle)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    {
+        let archive_manager = Arc::new(runtime.block_on(ArchiveManager::with_durability(Arc::clone(&root), true))?);
+        // Queue is much shorter than the count of entries, so enqueueing waits for the writer
+        let writer = ArchiveWriter::start(Arc::clone(&archive_manager), 8, Backpressure::Block)?;
+        for (seq_no, handle) in handles.iter().enumerate() {
+            writer.enqueue(Arc::clone(handle), entry_id(handle), entry_data(seq_no as u32))?;
+        }
+        writer.flush_and_stop()?;
+        assert!(handles.iter().all(|handle| handle.moved_to_archive()));
+
+        assert!(writer.enqueue(Arc::clone(&handles[0]), entry_id(&handles[0]), entry_data(0)).is_err());
+        assert!(writer.flush_and_stop().is_err());
+    }
+
+    // Entries are read back by a new instance over the same directory
+    let archive_manager = runtime.block_on(ArchiveManager::with_data(Arc::clone(&root)))?;
+    for (seq_no, handle) in handles.iter().enumerate() {
+        let data = runtime.block_on(archive_manager.get_file(handle, &entry_id(handle)))?;
+        assert_eq!(data, entry_data(seq_no as u32));
+    }
+    assert!(runtime.block_on(archive_manager.verify())?.is_empty());
+
+    Ok(())
+}
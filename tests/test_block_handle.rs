@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use ton_block::{BlockIdExt, ShardIdent};
+use ton_types::{Result, UInt256};
+
+use ton_node_storage::block_handle_db::{configure_archived_meta_filter, BlockHandleDb, BlockHandleStorage};
+use ton_node_storage::db::rocksdb::RocksDb;
+use ton_node_storage::db::traits::KvcReadable;
+use ton_node_storage::status_db::StatusDb;
+use ton_node_storage::types::{BlockId, BlockIdHashing};
+
+#[test]
+fn test_set_moved_to_archive_records_archive_time() -> Result<()> {
+    let storage = BlockHandleStorage::new(Arc::new(BlockHandleDb::in_memory()), &StatusDb::in_memory())?;
+    let handle = storage.load_block_handle(&BlockIdExt::default())?;
+    assert_eq!(handle.meta().archived_at().load(Ordering::SeqCst), 0);
+
+    assert!(!handle.set_moved_to_archive());
+    assert!(handle.moved_to_archive());
+    let archived_at = handle.meta().archived_at().load(Ordering::SeqCst);
+    assert_ne!(archived_at, 0);
+
+    assert!(handle.set_moved_to_archive());
+    assert_eq!(handle.meta().archived_at().load(Ordering::SeqCst), archived_at);
+
+    Ok(())
+}
+
+#[test]
+fn test_hashing_marker_is_verified_and_kept_apart_from_meta() -> Result<()> {
+    let block_handle_db = Arc::new(BlockHandleDb::in_memory());
+    let status_db = StatusDb::in_memory();
+    let storage = BlockHandleStorage::with_hashing(Arc::clone(&block_handle_db), &status_db, BlockIdHashing::Raw)?;
+    let handle = storage.load_block_handle(&BlockIdExt::default())?;
+    storage.store_block_handle(&handle)?;
+
+    let mut records = 0;
+    block_handle_db.for_each(&mut |key, value| {
+        // Marker of the scheme is not there, so every record is meta of a block
+        assert_eq!(BlockId::from_key_and_value(key, value)?.block_id_ext(), &BlockIdExt::default());
+        records += 1;
+        Ok(true)
+    })?;
+    assert_eq!(records, 1);
+
+    assert!(BlockHandleStorage::new(Arc::clone(&block_handle_db), &status_db).is_err());
+    assert!(BlockHandleStorage::with_hashing(block_handle_db, &status_db, BlockIdHashing::Raw).is_ok());
+
+    Ok(())
+}
+
+fn block_id(shard: &ShardIdent, seq_no: u32) -> BlockIdExt {
+    BlockIdExt::with_params(shard.clone(), seq_no, UInt256::from([seq_no as u8; 32]), UInt256::default())
+}
+
+fn stored_seq_nos(db: &RocksDb) -> Result<Vec<(bool, u32)>> {
+    let mut stored = Vec::new();
+    db.for_each(&mut |key, value| {
+        let id = BlockId::from_key_and_value(key, value)?.block_id_ext().clone();
+        stored.push((id.shard().is_masterchain(), id.seq_no()));
+        Ok(true)
+    })?;
+    stored.sort();
+
+    Ok(stored)
+}
+
+#[test]
+fn test_compaction_expires_archived_meta_below_gc_horizon() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let gc_horizon = Arc::new(AtomicU32::new(0));
+    let masterchain = ShardIdent::masterchain();
+    let workchain = ShardIdent::with_tagged_prefix(0, 0x8000_0000_0000_0000)?;
+    {
+        let block_handle_db = Arc::new(BlockHandleDb::with_path_and_gc_horizon(dir.path(), Arc::clone(&gc_horizon)));
+        let storage = BlockHandleStorage::new(block_handle_db, &StatusDb::in_memory())?;
+        for seq_no in 1..=10 {
+            // Masterchain blocks with odd seqno and every other shard block are archived
+            let handle = storage.load_block_handle(&block_id(&masterchain, seq_no))?;
+            if seq_no % 2 == 1 {
+                handle.set_moved_to_archive();
+            }
+            storage.store_block_handle(&handle)?;
+
+            // Shard block is checked against masterchain seqno it refers to, not its own
+            let handle = storage.load_block_handle(&block_id(&workchain, seq_no + 100))?;
+            handle.meta().masterchain_ref_seq_no().store(seq_no, Ordering::SeqCst);
+            if seq_no % 2 == 0 {
+                handle.set_moved_to_archive();
+            }
+            storage.store_block_handle(&handle)?;
+        }
+    }
+
+    let db = RocksDb::with_options(dir.path(), |options| {
+        configure_archived_meta_filter(options, Arc::clone(&gc_horizon))
+    });
+    db.compact_range(None, None)?;
+    assert_eq!(stored_seq_nos(&db)?.len(), 20);
+
+    // Horizon is read by the filter on every check, so raising it takes effect without reopening
+    gc_horizon.store(6, Ordering::SeqCst);
+    db.compact_range(None, None)?;
+    let mut expected = Vec::new();
+    for seq_no in 1..=10 {
+        if seq_no >= 6 || seq_no % 2 == 0 {
+            expected.push((true, seq_no));
+        }
+        if seq_no >= 6 || seq_no % 2 == 1 {
+            expected.push((false, seq_no + 100));
+        }
+    }
+    expected.sort();
+    assert_eq!(stored_seq_nos(&db)?, expected);
+
+    Ok(())
+}
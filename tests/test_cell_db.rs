@@ -0,0 +1,359 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Barrier};
+
+use ton_block::{MerkleProof, Serializable};
+use ton_types::{BuilderData, Cell, CellType, Result, UInt256};
+
+use ton_node_storage::cell_db::CellDb;
+use ton_node_storage::db::traits::{Kvc, KvcReadable, KvcTransaction, KvcTransactional, KvcWriteable};
+use ton_node_storage::dynamic_boc_db::DynamicBocDb;
+use ton_node_storage::types::CellId;
+
+mod common;
+
+use common::{build_cell, build_shared_tree, cell_id};
+
+fn refcount(db: &DynamicBocDb, cell: &Cell) -> Result<Option<u32>> {
+    Ok(db.cell_db().describe_cell(&cell_id(cell))?.refcount())
+}
+
+#[test]
+fn test_gc_keeps_child_shared_by_remaining_parent() -> Result<()> {
+    let db = DynamicBocDb::in_memory();
+    let grandchild = build_cell(3, &[]);
+    let child = build_cell(2, &[grandchild.clone()]);
+    let parent1 = build_cell(10, &[child.clone()]);
+    let parent2 = build_cell(20, &[child.clone()]);
+
+    assert_eq!(db.put_tree(parent1.clone())?, 3);
+    assert_eq!(db.put_tree(parent2.clone())?, 1);
+    assert_eq!(refcount(&db, &child)?, Some(2));
+    assert_eq!(refcount(&db, &grandchild)?, Some(1));
+
+    let cell_db = db.cell_db();
+    let transaction = cell_db.begin_transaction_with_snapshot()?;
+    assert_eq!(cell_db.dec_ref(&*transaction, &cell_id(&parent1))?, 0);
+    transaction.commit()?;
+
+    assert_eq!(cell_db.gc()?, 1);
+    assert!(!cell_db.contains(&cell_id(&parent1))?);
+    assert_eq!(refcount(&db, &child)?, Some(1));
+    assert!(cell_db.contains(&cell_id(&grandchild))?);
+
+    let transaction = cell_db.begin_transaction_with_snapshot()?;
+    cell_db.dec_ref(&*transaction, &cell_id(&parent2))?;
+    transaction.commit()?;
+
+    assert_eq!(cell_db.gc()?, 3);
+    assert!(cell_db.is_empty()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_put_tree_counts_shared_child_once_per_reference() -> Result<()> {
+    let db = DynamicBocDb::in_memory();
+    let child = build_cell(1, &[]);
+    let root = build_cell(2, &[child.clone(), child.clone()]);
+
+    assert_eq!(db.put_tree(root.clone())?, 2);
+    assert_eq!(refcount(&db, &root)?, Some(1));
+    assert_eq!(refcount(&db, &child)?, Some(2));
+
+    // Putting stored tree again holds its root once more, without touching the subtree
+    assert_eq!(db.put_tree(root.clone())?, 0);
+    assert_eq!(refcount(&db, &root)?, Some(2));
+    assert_eq!(refcount(&db, &child)?, Some(2));
+
+    Ok(())
+}
+
+#[test]
+fn test_save_as_dynamic_boc_counts_references() -> Result<()> {
+    let db = Arc::new(DynamicBocDb::in_memory());
+    let child = build_cell(1, &[]);
+    let parent1 = build_cell(2, &[child.clone()]);
+    let parent2 = build_cell(3, &[child.clone()]);
+
+    assert_eq!(db.save_as_dynamic_boc(parent1)?, 2);
+    assert_eq!(db.save_as_dynamic_boc(parent2)?, 1);
+    assert_eq!(refcount(&db, &child)?, Some(2));
+
+    Ok(())
+}
+
+#[test]
+fn test_refcount_updates_in_one_transaction_accumulate() -> Result<()> {
+    let db = DynamicBocDb::in_memory();
+    let cell = build_cell(1, &[]);
+    db.put_tree(cell.clone())?;
+
+    let cell_db = db.cell_db();
+    let transaction = cell_db.begin_transaction_with_snapshot()?;
+    assert_eq!(cell_db.inc_ref(&*transaction, &cell_id(&cell))?, 2);
+    assert_eq!(cell_db.inc_ref(&*transaction, &cell_id(&cell))?, 3);
+    assert_eq!(cell_db.dec_ref(&*transaction, &cell_id(&cell))?, 2);
+    transaction.commit()?;
+
+    assert_eq!(refcount(&db, &cell)?, Some(2));
+
+    Ok(())
+}
+
+#[test]
+fn test_delete_if_refcount() -> Result<()> {
+    let db = DynamicBocDb::in_memory();
+    let child = build_cell(1, &[]);
+    let root = build_cell(2, &[child.clone()]);
+    db.put_tree(root.clone())?;
+
+    let cell_db = db.cell_db();
+    let transaction = cell_db.begin_transaction_with_snapshot()?;
+    assert!(!cell_db.delete_if_refcount(&*transaction, &cell_id(&root), 0)?);
+    // Increment pending in the same transaction is seen by the comparison
+    cell_db.inc_ref(&*transaction, &cell_id(&root))?;
+    assert!(!cell_db.delete_if_refcount(&*transaction, &cell_id(&root), 1)?);
+    assert!(cell_db.delete_if_refcount(&*transaction, &cell_id(&root), 2)?);
+    assert!(!cell_db.delete_if_refcount(&*transaction, &cell_id(&root), 2)?);
+    transaction.commit()?;
+
+    assert!(!cell_db.contains(&cell_id(&root))?);
+    assert_eq!(refcount(&db, &child)?, Some(0));
+    assert_eq!(cell_db.gc()?, 1);
+    assert!(cell_db.is_empty()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_delete_if_refcount_skips_orphan_deltas() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = DynamicBocDb::with_path(dir.path());
+    let child = build_cell(1, &[]);
+    let root = build_cell(2, &[child.clone()]);
+    db.put_tree(root.clone())?;
+
+    // Child record is replaced with delta merged into its absent record
+    let cell_db = db.cell_db();
+    cell_db.delete(&cell_id(&child))?;
+    cell_db.merge_ref(&cell_id(&child), 1)?;
+    let absent = build_cell(3, &[]);
+    cell_db.merge_ref(&cell_id(&absent), 1)?;
+
+    let transaction = cell_db.begin_transaction_with_snapshot()?;
+    assert!(!cell_db.delete_if_refcount(&*transaction, &cell_id(&absent), 1)?);
+    assert!(cell_db.delete_if_refcount(&*transaction, &cell_id(&root), 1)?);
+    transaction.commit()?;
+
+    assert!(!cell_db.contains(&cell_id(&root))?);
+    assert_eq!(cell_db.gc()?, 2);
+    assert!(!cell_db.contains(&cell_id(&child))?);
+    assert!(!cell_db.contains(&cell_id(&absent))?);
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_merge_increments_are_exact() -> Result<()> {
+    const THREADS: u32 = 8;
+    const INCREMENTS: u32 = 1000;
+
+    let dir = tempfile::tempdir()?;
+    let db = Arc::new(DynamicBocDb::with_path(dir.path()));
+    let cell = build_cell(1, &[]);
+    db.put_tree(cell.clone())?;
+
+    let workers: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            let cell_id = cell_id(&cell);
+            std::thread::spawn(move || -> Result<()> {
+                for _ in 0..INCREMENTS {
+                    db.cell_db().merge_ref(&cell_id, 1)?;
+                }
+                db.cell_db().merge_ref(&cell_id, -1)
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("Merging thread panicked")?;
+    }
+
+    assert_eq!(refcount(&db, &cell)?, Some(1 + THREADS * (INCREMENTS - 1)));
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_into_absent_cell_is_removed_by_gc() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = DynamicBocDb::with_path(dir.path());
+    let absent = build_cell(1, &[]);
+    let cell_db = db.cell_db();
+
+    cell_db.merge_ref(&cell_id(&absent), 1)?;
+    assert_eq!(cell_db.gc()?, 1);
+    assert!(!cell_db.contains(&cell_id(&absent))?);
+
+    // Delta merged into absent record doesn't prevent the cell from being put later
+    cell_db.merge_ref(&cell_id(&absent), 1)?;
+    assert_eq!(db.put_tree(absent.clone())?, 1);
+    assert_eq!(refcount(&db, &absent)?, Some(1));
+
+    Ok(())
+}
+
+fn collect_references(cell: &Cell, cells: &mut Vec<(CellId, Cell)>) -> Result<()> {
+    cells.push((cell_id(cell), cell.clone()));
+    for i in 0..cell.references_count() {
+        collect_references(&cell.reference(i)?, cells)?;
+    }
+    Ok(())
+}
+
+fn assert_same_tree(loaded: &Cell, expected: &Cell) -> Result<()> {
+    assert_eq!(loaded.repr_hash(), expected.repr_hash());
+    assert_eq!(loaded.data(), expected.data());
+    assert_eq!(loaded.references_count(), expected.references_count());
+    for i in 0..expected.references_count() {
+        assert_same_tree(&loaded.reference(i)?, &expected.reference(i)?)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_put_cells_writes_tree_in_one_transaction() -> Result<()> {
+    let db = Arc::new(DynamicBocDb::in_memory());
+    let root = build_shared_tree(0);
+    let mut cells = Vec::new();
+    collect_references(&root, &mut cells)?;
+    let unique: HashSet<_> = cells.iter().map(|(id, _cell)| id.clone()).collect();
+    assert!(unique.len() < cells.len(), "Tree must have shared cells");
+
+    let cell_db = db.cell_db();
+    let transaction = cell_db.begin_transaction_with_snapshot()?;
+    assert_eq!(CellDb::put_cells(&*transaction, cells.clone())?, unique.len());
+    assert!(cell_db.is_empty()?);
+    transaction.commit()?;
+    assert_eq!(cell_db.len()?, unique.len());
+
+    // Each item is one reference to its cell
+    for (id, _cell) in cells.iter() {
+        let expected = cells.iter().filter(|(other, _cell)| other == id).count() as u32;
+        assert_eq!(cell_db.describe_cell(id)?.refcount(), Some(expected));
+    }
+    assert_same_tree(&db.load_dynamic_boc(&cell_id(&root))?, &root)?;
+
+    Ok(())
+}
+
+fn build_library_cell(library_hash: &UInt256) -> Cell {
+    let mut builder = BuilderData::new();
+    builder.set_type(CellType::LibraryReference);
+    builder.append_u8(u8::from(CellType::LibraryReference)).unwrap();
+    builder.append_raw(library_hash.as_slice(), 256).unwrap();
+    builder.into_cell().unwrap()
+}
+
+fn assert_same_kind(loaded: &Cell, expected: &Cell) {
+    assert_eq!(loaded.cell_type(), expected.cell_type());
+    assert_eq!(loaded.level_mask(), expected.level_mask());
+    assert_eq!(loaded.repr_hash(), expected.repr_hash());
+    assert_eq!(loaded.data(), expected.data());
+}
+
+#[test]
+fn test_library_cell_round_trip() -> Result<()> {
+    let db = Arc::new(DynamicBocDb::in_memory());
+    let library = build_library_cell(&build_cell(1, &[]).repr_hash());
+    let root = build_cell(2, &[library.clone()]);
+    db.put_tree(root.clone())?;
+
+    let loaded = db.load_dynamic_boc(&cell_id(&root))?.reference(0)?;
+    assert_eq!(loaded.cell_type(), CellType::LibraryReference);
+    assert_eq!(loaded.references_count(), 0);
+    assert_same_kind(&loaded, &library);
+
+    Ok(())
+}
+
+#[test]
+fn test_pruned_branch_and_merkle_proof_round_trip() -> Result<()> {
+    let db = Arc::new(DynamicBocDb::in_memory());
+    let pruned = build_shared_tree(0);
+    let kept = build_cell(1000, &[]);
+    let root = build_cell(2000, &[kept.clone(), pruned.clone()]);
+    let pruned_hash = pruned.repr_hash();
+    let proof = MerkleProof::create(&root, |hash| hash != &pruned_hash)?.serialize()?;
+    assert_eq!(proof.cell_type(), CellType::MerkleProof);
+    db.put_tree(proof.clone())?;
+
+    let loaded = db.load_dynamic_boc(&cell_id(&proof))?;
+    assert_same_kind(&loaded, &proof);
+    let loaded_root = loaded.reference(0)?;
+    let expected_root = proof.reference(0)?;
+    assert_same_kind(&loaded_root, &expected_root);
+    assert_same_kind(&loaded_root.reference(0)?, &kept);
+
+    // Pruned branch keeps its level and the hash of the pruned subtree
+    let loaded_pruned = loaded_root.reference(1)?;
+    assert_eq!(loaded_pruned.cell_type(), CellType::PrunedBranch);
+    assert_eq!(loaded_pruned.references_count(), 0);
+    assert_same_kind(&loaded_pruned, &expected_root.reference(1)?);
+    assert_eq!(loaded_pruned.hash(0), pruned_hash);
+
+    Ok(())
+}
+
+fn check_concurrent_put_tree_counts_shared_subtree_once(parallel: bool) -> Result<()> {
+    const THREADS: u32 = 8;
+
+    let shared = build_shared_tree(0);
+    let roots: Vec<_> = (0..THREADS).map(|i| build_cell(1000 + i, &[shared.clone()])).collect();
+    let expected = DynamicBocDb::in_memory();
+    for root in roots.iter() {
+        expected.put_tree(root.clone())?;
+    }
+
+    let db = Arc::new(DynamicBocDb::in_memory());
+    let barrier = Arc::new(Barrier::new(THREADS as usize));
+    let writers: Vec<_> = roots.iter().cloned()
+        .map(|root| {
+            let db = Arc::clone(&db);
+            let barrier = Arc::clone(&barrier);
+            std::thread::spawn(move || -> Result<usize> {
+                barrier.wait();
+                if parallel {
+                    db.put_tree_parallel(root, 2)
+                } else {
+                    db.put_tree(root)
+                }
+            })
+        })
+        .collect();
+    let mut written = 0;
+    for writer in writers {
+        written += writer.join().expect("Writing thread panicked")?;
+    }
+
+    let mut cells = Vec::new();
+    for root in roots.iter() {
+        collect_references(root, &mut cells)?;
+    }
+    assert_eq!(written, db.cell_db().len()?);
+    for (id, _cell) in cells.iter() {
+        assert_eq!(db.cell_db().describe_cell(id)?.refcount(), expected.cell_db().describe_cell(id)?.refcount());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_put_tree_counts_shared_subtree_once() -> Result<()> {
+    check_concurrent_put_tree_counts_shared_subtree_once(false)
+}
+
+#[test]
+fn test_concurrent_put_tree_parallel_counts_shared_subtree_once() -> Result<()> {
+    check_concurrent_put_tree_counts_shared_subtree_once(true)
+}
@@ -0,0 +1,59 @@
+use ton_types::Result;
+
+use ton_node_storage::db::memorydb::MemoryDb;
+use ton_node_storage::db::rocksdb::RocksDb;
+use ton_node_storage::db::traits::{KvcReadable, KvcWriteable};
+
+const KEYS: &[&[u8]] = &[
+    b"\x01\x00", b"\x01\x05", b"\x02\x00", b"\x02\xFF", b"\x03", b"\xFF\x01", b"\xFF\xFF",
+];
+
+fn stored_keys(db: &dyn KvcWriteable<&'static [u8]>) -> Result<Vec<Vec<u8>>> {
+    let mut keys = Vec::new();
+    db.for_each(&mut |key, _value| {
+        keys.push(key.to_vec());
+        Ok(true)
+    })?;
+
+    Ok(keys)
+}
+
+fn check_delete_range(db: &dyn KvcWriteable<&'static [u8]>) -> Result<()> {
+    for key in KEYS {
+        db.put(key, key)?;
+    }
+
+    // Lower bound is deleted, upper one survives
+    db.delete_range(b"\x01\x05", b"\x02\xFF")?;
+    assert_eq!(
+        stored_keys(db)?,
+        vec![b"\x01\x00".to_vec(), b"\x02\xFF".to_vec(), b"\x03".to_vec(), b"\xFF\x01".to_vec(), b"\xFF\xFF".to_vec()]
+    );
+    assert!(!db.contains(&&b"\x01\x05"[..])?);
+    assert!(!db.contains(&&b"\x02\x00"[..])?);
+    assert_eq!(db.get(&&b"\x02\xFF"[..])?.as_ref(), b"\x02\xFF");
+
+    // Empty and reversed ranges delete nothing
+    db.delete_range(b"\x03", b"\x03")?;
+    db.delete_range(b"\x03", b"\x01")?;
+    assert_eq!(stored_keys(db)?.len(), 5);
+
+    db.delete_prefix(b"\x02")?;
+    // Prefix made of 0xFF bytes only has no finite upper bound
+    db.delete_prefix(b"\xFF")?;
+    assert_eq!(stored_keys(db)?, vec![b"\x01\x00".to_vec(), b"\x03".to_vec()]);
+    assert!(!db.contains(&&b"\xFF\xFF"[..])?);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_delete_range_keeps_keys_outside() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    check_delete_range(&RocksDb::with_path(dir.path()))
+}
+
+#[test]
+fn test_memorydb_delete_range_keeps_keys_outside() -> Result<()> {
+    check_delete_range(&MemoryDb::new())
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use ton_types::{Cell, Result};
+
+use ton_node_storage::db::traits::{KvcReadable, KvcWriteable};
+use ton_node_storage::dynamic_boc_db::DynamicBocDb;
+use ton_node_storage::types::CellId;
+
+mod common;
+
+use common::{build_cell, build_shared_tree, cell_id};
+
+fn stored_records(db: &DynamicBocDb) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+    let mut records = BTreeMap::new();
+    db.cell_db().for_each(&mut |key, value| {
+        records.insert(key.to_vec(), value.to_vec());
+        Ok(true)
+    })?;
+
+    Ok(records)
+}
+
+#[test]
+fn test_put_tree_parallel_stores_same_cells_as_serial() -> Result<()> {
+    let root = build_shared_tree(0);
+
+    let serial = DynamicBocDb::in_memory();
+    let serial_count = serial.put_tree(root.clone())?;
+
+    let parallel = DynamicBocDb::in_memory();
+    assert_eq!(parallel.put_tree_parallel(root.clone(), 4)?, serial_count);
+
+    // Records carry reference counts, so shared cells must be counted the same way
+    assert_eq!(stored_records(&parallel)?, stored_records(&serial)?);
+
+    // Second tree sharing nothing is put by the same worker threads
+    let other_root = build_shared_tree(1000);
+    assert_eq!(parallel.put_tree_parallel(other_root.clone(), 4)?, serial.put_tree(other_root)?);
+    assert_eq!(stored_records(&parallel)?, stored_records(&serial)?);
+
+    Ok(())
+}
+
+fn count_cells(cell: &Cell) -> Result<usize> {
+    let mut count = 1;
+    for i in 0..cell.references_count() {
+        count += count_cells(&cell.reference(i)?)?;
+    }
+    Ok(count)
+}
+
+#[test]
+fn test_cached_cells_dont_keep_storage_alive() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = Arc::new(DynamicBocDb::with_cache_capacity(dir.path(), 1000));
+    let root = build_shared_tree(0);
+    let root_id = cell_id(&root);
+    let expected = count_cells(&root)?;
+    db.put_tree(root)?;
+
+    assert_eq!(count_cells(&db.load_dynamic_boc(&root_id)?)?, expected);
+    let misses = db.cache_stats().expect("Cache is enabled").misses();
+    assert_eq!(count_cells(&db.load_dynamic_boc(&root_id)?)?, expected);
+    let stats = db.cache_stats().expect("Cache is enabled");
+    assert_eq!(stats.misses(), misses);
+    assert!(stats.hits() > 0);
+
+    let weak = Arc::downgrade(&db);
+    drop(db);
+    assert!(weak.upgrade().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_collect_unreachable_keeps_subtree_shared_with_live_root() -> Result<()> {
+    let db = Arc::new(DynamicBocDb::in_memory());
+    let shared_leaf = build_cell(1, &[]);
+    let shared = build_cell(2, &[shared_leaf.clone()]);
+    let live_root = build_cell(10, &[shared.clone(), build_cell(11, &[])]);
+    let own_leaf = build_cell(21, &[]);
+    let dead_root = build_cell(20, &[shared.clone(), own_leaf.clone()]);
+    db.put_tree(live_root.clone())?;
+    db.put_tree(dead_root.clone())?;
+
+    let mut unreachable = db.collect_unreachable(&[live_root.repr_hash()])?;
+    unreachable.sort_by(|a, b| a.as_hash().as_slice().cmp(b.as_hash().as_slice()));
+    let mut expected = vec![cell_id(&dead_root), cell_id(&own_leaf)];
+    expected.sort_by(|a, b| a.as_hash().as_slice().cmp(b.as_hash().as_slice()));
+    assert_eq!(unreachable, expected);
+
+    // Nothing is unreachable, while both roots are live
+    assert!(db.collect_unreachable(&[live_root.repr_hash(), dead_root.repr_hash()])?.is_empty());
+
+    db.delete_cells(&unreachable)?;
+    assert!(!db.cell_db().contains(&cell_id(&dead_root))?);
+    assert!(db.cell_db().contains(&cell_id(&shared_leaf))?);
+    assert_eq!(count_cells(&db.load_dynamic_boc(&cell_id(&live_root))?)?, 4);
+    assert!(db.collect_unreachable(&[live_root.repr_hash()])?.is_empty());
+
+    Ok(())
+}
+
+fn delete_all_records(db: &DynamicBocDb) -> Result<()> {
+    for key in stored_records(db)?.keys() {
+        db.cell_db().delete(&CellId::try_from(key.as_slice())?)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_load_cell_tree_resolves_references_up_to_depth() -> Result<()> {
+    let root = build_shared_tree(0);
+    let root_id = cell_id(&root);
+    let expected = count_cells(&root)?;
+
+    // Records are deleted after loading, so only references resolved by then can be walked
+    let db = Arc::new(DynamicBocDb::in_memory());
+    db.put_tree(root.clone())?;
+    let tree = Cell::with_cell_impl_arc(db.load_cell_tree(&root_id, None)?);
+    delete_all_records(&db)?;
+    assert_eq!(count_cells(&tree)?, expected);
+
+    let db = Arc::new(DynamicBocDb::in_memory());
+    db.put_tree(root)?;
+    let top = Cell::with_cell_impl_arc(db.load_cell_tree(&root_id, Some(1))?);
+    delete_all_records(&db)?;
+    let child = top.reference(0)?;
+    assert!(child.references_count() > 0);
+    assert!(child.reference(0).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_walked_references_are_memoized() -> Result<()> {
+    const THREADS: usize = 4;
+
+    let root = build_shared_tree(0);
+    let root_id = cell_id(&root);
+    let expected = count_cells(&root)?;
+
+    // Children walked once are kept by their parents, so the second walk reads nothing:
+    // records are deleted in between
+    let db = Arc::new(DynamicBocDb::in_memory());
+    db.put_tree(root.clone())?;
+    let loaded = db.load_dynamic_boc(&root_id)?;
+    let walkers: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let loaded = loaded.clone();
+            std::thread::spawn(move || count_cells(&loaded))
+        })
+        .collect();
+    for walker in walkers {
+        assert_eq!(walker.join().expect("Walking thread panicked")?, expected);
+    }
+    delete_all_records(&db)?;
+    assert_eq!(count_cells(&loaded)?, expected);
+
+    // Children not walked yet are read from the database
+    let db = Arc::new(DynamicBocDb::in_memory());
+    db.put_tree(root)?;
+    let loaded = db.load_dynamic_boc(&root_id)?;
+    delete_all_records(&db)?;
+    assert!(count_cells(&loaded).is_err());
+
+    Ok(())
+}
+
+fn distinct_cells(cell: &Cell, cells: &mut HashSet<CellId>) -> Result<()> {
+    if cells.insert(cell_id(cell)) {
+        for i in 0..cell.references_count() {
+            distinct_cells(&cell.reference(i)?, cells)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_tree_counts_shared_cells_once() -> Result<()> {
+    let root = build_shared_tree(0);
+    let mut cells = HashSet::new();
+    distinct_cells(&root, &mut cells)?;
+    let db = DynamicBocDb::in_memory();
+    db.put_tree(root.clone())?;
+
+    let report = db.check_tree(&cell_id(&root))?;
+    assert_eq!(report.cells(), cells.len());
+    assert_eq!(report.max_depth(), 3);
+    assert!(report.is_complete());
+
+    // Leaf is the deepest cell, so checking it alone gives zero depth
+    let leaf = root.reference(0)?.reference(0)?.reference(0)?;
+    let report = db.check_tree(&cell_id(&leaf))?;
+    assert_eq!((report.cells(), report.max_depth()), (1, 0));
+
+    Ok(())
+}
+
+#[test]
+fn test_check_tree_reports_dangling_references() -> Result<()> {
+    let root = build_shared_tree(0);
+    let db = DynamicBocDb::in_memory();
+    db.put_tree(root.clone())?;
+    let full = db.check_tree(&cell_id(&root))?;
+
+    // Leaf shared by several parents is reported once
+    let leaf = root.reference(0)?.reference(0)?.reference(0)?;
+    db.cell_db().delete(&cell_id(&leaf))?;
+    let report = db.check_tree(&cell_id(&root))?;
+    assert_eq!(report.dangling(), &[leaf.repr_hash()]);
+    assert_eq!(report.cells(), full.cells() - 1);
+    assert!(report.cycles().is_empty());
+    assert!(!report.is_complete());
+
+    let absent = build_cell(1000, &[]);
+    let report = db.check_tree(&cell_id(&absent))?;
+    assert_eq!(report.dangling(), &[absent.repr_hash()]);
+    assert_eq!(report.cells(), 0);
+
+    Ok(())
+}
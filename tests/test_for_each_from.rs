@@ -0,0 +1,76 @@
+use ton_types::Result;
+
+use ton_node_storage::db::memorydb::MemoryDb;
+use ton_node_storage::db::rocksdb::RocksDb;
+use ton_node_storage::db::traits::{KvcReadable, KvcSnapshotable, KvcWriteable, U32Key};
+
+const COUNT: u32 = 100;
+const PAGE_SIZE: usize = 7;
+
+fn fill(db: &dyn KvcWriteable<U32Key>) -> Result<()> {
+    for i in 0..COUNT {
+        db.put(&U32Key::with_value(i), &i.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Reads up to PAGE_SIZE keys starting from given one
+fn read_page(db: &dyn KvcReadable<U32Key>, start: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut keys = Vec::new();
+    db.for_each_from(start, &mut |key, _value| {
+        keys.push(key.to_vec());
+        Ok(keys.len() < PAGE_SIZE)
+    })?;
+
+    Ok(keys)
+}
+
+fn check_pagination(db: &dyn KvcReadable<U32Key>) -> Result<()> {
+    let mut all = Vec::new();
+    db.for_each(&mut |key, _value| {
+        all.push(key.to_vec());
+        Ok(true)
+    })?;
+    assert_eq!(all.len(), COUNT as usize);
+
+    // Each page resumes right after the last key seen, which falls between stored keys
+    let mut paged = Vec::new();
+    let mut start = Vec::new();
+    loop {
+        let page = read_page(db, &start)?;
+        if page.is_empty() {
+            break;
+        }
+        assert!(page.len() <= PAGE_SIZE);
+        start = page.last().unwrap().clone();
+        start.push(0);
+        paged.extend(page);
+    }
+    assert_eq!(paged, all);
+
+    // Start key is inclusive
+    assert_eq!(read_page(db, &all[10])?, all[10..10 + PAGE_SIZE].to_vec());
+    assert_eq!(read_page(db, &[0xFF; 5])?, Vec::<Vec<u8>>::new());
+
+    Ok(())
+}
+
+#[test]
+fn test_for_each_from_pages_rocksdb() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::with_path(dir.path());
+    fill(&db)?;
+    check_pagination(&db)?;
+
+    let snapshot = db.snapshot()?;
+    db.delete(&U32Key::with_value(0))?;
+    check_pagination(&*snapshot)
+}
+
+#[test]
+fn test_for_each_from_pages_memorydb() -> Result<()> {
+    let db = MemoryDb::new();
+    fill(&db)?;
+    check_pagination(&db)
+}
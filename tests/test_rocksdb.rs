@@ -0,0 +1,471 @@
+use std::sync::{Arc, Barrier, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use ton_types::{error, Result};
+
+use ton_node_storage::db::rocksdb::RocksDb;
+use ton_node_storage::db::rocksdb_options::RocksDbOptions;
+use ton_node_storage::db::traits::{DbKey, Kvc, KvcReadable, KvcSnapshotable, KvcTransaction, KvcTransactional, KvcWriteable, U32Key};
+use ton_node_storage::error::StorageError;
+use ton_node_storage::types::DbSlice;
+
+#[test]
+fn test_background_error_of_healthy_db() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::with_path(dir.path());
+    db.put(&"key", b"value")?;
+
+    assert_eq!(db.background_error()?, None);
+    assert_eq!(db.get(&"key")?.as_ref(), b"value");
+
+    Ok(())
+}
+
+#[test]
+fn test_resume_reopens_db_in_place() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut db = RocksDb::open_with_cfs(dir.path(), &["default", "extra"])?;
+    db.put(&"key", b"value")?;
+    db.cf("extra")?.put(&"other", b"extra")?;
+
+    // Shared database is left intact
+    let snapshot = db.owned_snapshot()?;
+    let err = db.resume().unwrap_err();
+    assert_eq!(err.downcast_ref::<StorageError>(), Some(&StorageError::HasActiveTransactions));
+    drop(snapshot);
+
+    db.resume()?;
+    assert_eq!(db.background_error()?, None);
+    assert_eq!(db.get(&"key")?.as_ref(), b"value");
+    assert_eq!(db.cf("extra")?.get(&"other")?.as_ref(), b"extra");
+    db.put(&"key", b"new value")?;
+    drop(db);
+
+    let mut db = RocksDb::open_read_only(dir.path(), false)?;
+    assert_eq!(db.get(&"key")?.as_ref(), b"new value");
+    let err = db.resume().unwrap_err();
+    assert_eq!(err.downcast_ref::<StorageError>(), Some(&StorageError::ReadOnly));
+
+    Ok(())
+}
+
+#[test]
+fn test_write_rate_limit_is_accepted() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let options = RocksDbOptions::new().set_write_rate_limit(1024 * 1024);
+    let db = RocksDb::with_db_options(dir.path(), &options);
+    for i in 0..1000u32 {
+        db.put(&U32Key::with_value(i), &[0xAB; 1024])?;
+    }
+    db.flush()?;
+
+    for i in 0..1000u32 {
+        assert_eq!(db.get(&U32Key::with_value(i))?.as_ref(), &[0xAB; 1024][..]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_write_rate_limit_is_changed_in_place() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let options = RocksDbOptions::new().set_write_rate_limit(1024 * 1024);
+    let mut db = RocksDb::with_db_options(dir.path(), &options);
+    db.put(&U32Key::with_value(0), &[0xAB; 1024])?;
+
+    let snapshot = db.owned_snapshot()?;
+    let err = db.set_write_rate_limit(64 * 1024 * 1024).unwrap_err();
+    assert_eq!(err.downcast_ref::<StorageError>(), Some(&StorageError::HasActiveTransactions));
+    drop(snapshot);
+    assert!(db.set_write_rate_limit(0).is_err());
+
+    db.set_write_rate_limit(64 * 1024 * 1024)?;
+    assert_eq!(db.get(&U32Key::with_value(0))?.as_ref(), &[0xAB; 1024][..]);
+    for i in 1..1000u32 {
+        db.put(&U32Key::with_value(i), &[0xCD; 1024])?;
+    }
+    db.flush()?;
+    assert_eq!(db.get(&U32Key::with_value(999))?.as_ref(), &[0xCD; 1024][..]);
+
+    Ok(())
+}
+
+#[test]
+fn test_compact_range_is_refused_on_read_only_db() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::with_path(dir.path());
+    for i in 0..100u32 {
+        db.put(&U32Key::with_value(i), &i.to_le_bytes())?;
+    }
+    db.flush()?;
+    let end = U32Key::with_value(99);
+    db.compact_range(None, Some(end.key()))?;
+    assert_eq!(db.get(&end)?.as_ref(), &99u32.to_le_bytes()[..]);
+
+    let read_only = RocksDb::open_read_only(dir.path(), false)?;
+    let err = read_only.compact_range(None, None).unwrap_err();
+    assert_eq!(err.downcast_ref::<StorageError>(), Some(&StorageError::ReadOnly));
+
+    Ok(())
+}
+
+#[test]
+fn test_owned_snapshot_outlives_dropped_db() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut db = RocksDb::with_path(dir.path());
+    for i in 0..1000u32 {
+        db.put(&U32Key::with_value(i), &i.to_le_bytes())?;
+    }
+    let snapshot = db.owned_snapshot()?;
+    db.put(&U32Key::with_value(1000), &1000u32.to_le_bytes())?;
+
+    // Database can't be closed under the snapshot
+    let err = db.destroy().unwrap_err();
+    assert_eq!(err.downcast_ref::<StorageError>(), Some(&StorageError::HasActiveTransactions));
+
+    let barrier = Arc::new(Barrier::new(2));
+    let reader = {
+        let barrier = Arc::clone(&barrier);
+        std::thread::spawn(move || -> Result<()> {
+            barrier.wait();
+            for _ in 0..10 {
+                for i in 0..1000u32 {
+                    let value = KvcReadable::<U32Key>::get(&snapshot, &U32Key::with_value(i))?;
+                    assert_eq!(value.as_ref(), &i.to_le_bytes()[..]);
+                }
+                assert!(!KvcReadable::<U32Key>::contains(&snapshot, &U32Key::with_value(1000))?);
+            }
+            Ok(())
+        })
+    };
+    barrier.wait();
+    drop(db);
+    reader.join().expect("Snapshot reading thread panicked")?;
+
+    // Database is closed with the last snapshot, so it can be opened again
+    let db = RocksDb::open(dir.path())?;
+    assert_eq!(db.get(&U32Key::with_value(1000))?.as_ref(), &1000u32.to_le_bytes()[..]);
+
+    Ok(())
+}
+
+#[test]
+fn test_destroy_blocking_times_out_and_leaves_db_intact() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut db = RocksDb::with_path(dir.path());
+    db.put(&U32Key::with_value(1), b"value")?;
+    assert_eq!(db.active_transactions(), 0);
+
+    let transaction = KvcTransactional::<U32Key>::begin_transaction(&db)?;
+    assert_eq!(db.active_transactions(), 1);
+
+    let started = Instant::now();
+    let err = db.destroy_blocking(Duration::from_millis(100)).unwrap_err();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::Timeout(_))));
+
+    // Neither the database nor the transaction is affected by the timeout
+    transaction.put(&U32Key::with_value(2), b"other");
+    transaction.commit()?;
+    assert_eq!(db.active_transactions(), 0);
+    assert_eq!(db.get(&U32Key::with_value(1))?.as_ref(), b"value");
+    assert_eq!(db.get(&U32Key::with_value(2))?.as_ref(), b"other");
+
+    db.destroy_blocking(Duration::from_millis(100))?;
+    let err = db.get(&U32Key::with_value(1)).unwrap_err();
+    assert_eq!(err.downcast_ref::<StorageError>(), Some(&StorageError::DbIsDropped));
+    assert!(RocksDb::open_read_only(dir.path(), false).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_destroy_blocking_waits_for_snapshot_dropped_by_other_thread() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut db = RocksDb::with_path(dir.path());
+    db.put(&U32Key::with_value(1), b"value")?;
+    let snapshot = db.owned_snapshot()?;
+    assert_eq!(db.active_transactions(), 1);
+
+    let barrier = Arc::new(Barrier::new(2));
+    let holder = {
+        let barrier = Arc::clone(&barrier);
+        std::thread::spawn(move || {
+            barrier.wait();
+            std::thread::sleep(Duration::from_millis(50));
+            drop(snapshot);
+        })
+    };
+    barrier.wait();
+    db.destroy_blocking(Duration::from_secs(10))?;
+    holder.join().expect("Snapshot holding thread panicked");
+
+    // Lock file is released before the files are removed, so the path is free for a new database
+    let db = RocksDb::with_path(dir.path());
+    assert!(!db.contains(&U32Key::with_value(1))?);
+
+    Ok(())
+}
+
+#[test]
+fn test_clone_to_copies_column_families_into_new_path() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::open_with_cfs(dir.path().join("src"), &["default", "extra"])?;
+    db.put(&"a1", b"default")?;
+    db.put(&"b1", b"skipped")?;
+    db.cf("extra")?.put(&"a2", b"extra")?;
+    db.cf("extra")?.put(&"b2", b"skipped")?;
+
+    let dst = dir.path().join("dst");
+    db.clone_to(&dst, Some(b"a"))?;
+    let err = db.clone_to(&dst, None).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<StorageError>(),
+        Some(&StorageError::PathAlreadyExists(dst.display().to_string()))
+    );
+
+    let cloned = RocksDb::with_all_column_families(&dst, &RocksDbOptions::new())?;
+    assert_eq!(cloned.get(&"a1")?.as_ref(), b"default");
+    assert!(!cloned.contains(&"b1")?);
+    let extra = cloned.cf("extra")?;
+    assert_eq!(extra.get(&"a2")?.as_ref(), b"extra");
+    assert!(!extra.contains(&"b2")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_get_multi_keeps_input_order_and_reports_missing_keys() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::with_path(dir.path());
+    for i in (0..100u32).step_by(2) {
+        db.put(&U32Key::with_value(i), &i.to_le_bytes())?;
+    }
+    let keys: Vec<U32Key> = [10, 3, 0, 98, 99, 42].iter().map(|&i| U32Key::with_value(i)).collect();
+    let expected = vec![Some(10u32), None, Some(0), Some(98), None, Some(42)];
+
+    let check = |values: Vec<Option<DbSlice>>| {
+        let values: Vec<_> = values.iter()
+            .map(|value| value.as_ref().map(|value| {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(value.as_ref());
+                u32::from_le_bytes(bytes)
+            }))
+            .collect();
+        assert_eq!(values, expected);
+    };
+    check(db.get_multi(&keys)?);
+
+    let snapshot = KvcSnapshotable::<U32Key>::snapshot(&db)?;
+    db.put(&U32Key::with_value(3), &3u32.to_le_bytes())?;
+    check(snapshot.get_multi(&keys)?);
+    assert!(db.get_multi(&keys)?[1].is_some());
+    assert!(db.get_multi(&Vec::<U32Key>::new())?.is_empty());
+
+    Ok(())
+}
+
+fn all_items(db: &RocksDb) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut items = Vec::new();
+    KvcReadable::<U32Key>::for_each(db, &mut |key, value| {
+        items.push((key.to_vec(), value.to_vec()));
+        Ok(true)
+    })?;
+
+    Ok(items)
+}
+
+#[test]
+fn test_export_and_import_reproduce_contents() -> Result<()> {
+    const COUNT: u32 = 25_000;
+
+    let src_dir = tempfile::tempdir()?;
+    let src = RocksDb::with_path(src_dir.path());
+    for i in 0..COUNT {
+        // Values of varied sizes, empty ones included
+        src.put(&U32Key::with_value(i), &vec![i as u8; (i % 300) as usize])?;
+    }
+
+    let mut dump = Vec::new();
+    assert_eq!(src.export_to_writer(&mut dump)?, COUNT as u64);
+
+    // Import spans several batches
+    let dst_dir = tempfile::tempdir()?;
+    let dst = RocksDb::with_path(dst_dir.path());
+    assert_eq!(dst.import_from_reader(&mut &dump[..])?, COUNT as u64);
+    assert_eq!(dst.exact_len()?, COUNT as usize);
+    assert_eq!(all_items(&dst)?, all_items(&src)?);
+
+    // Dump cut inside a record is refused, clean end of empty dump is not
+    let other_dir = tempfile::tempdir()?;
+    let other = RocksDb::with_path(other_dir.path());
+    assert!(other.import_from_reader(&mut &dump[..dump.len() - 1]).is_err());
+    assert!(other.import_from_reader(&mut &dump[..6]).is_err());
+    assert_eq!(other.import_from_reader(&mut &[][..])?, 0);
+
+    Ok(())
+}
+
+/// Checks that cell and meta written by one multi-CF transaction are both present or both absent
+fn check_block_persisted(path: &std::path::Path, expected: bool) -> Result<()> {
+    let db = RocksDb::open_with_cfs(path, &["default", "meta"])?;
+    assert_eq!(db.contains(&"cell")?, expected);
+    assert_eq!(db.cf("meta")?.contains(&"block")?, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_multi_cf_transaction_is_all_or_nothing() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    {
+        let db = RocksDb::open_with_cfs(dir.path(), &["default", "meta"])?;
+        let meta = db.cf("meta")?;
+        let transaction = db.begin_multi_cf_transaction()?;
+        transaction.put(&"cell", b"cell data");
+        transaction.put_cf(&meta, &"block", b"block meta")?;
+        assert_eq!(transaction.len(), 2);
+
+        // Neither write is visible before commit
+        assert!(!db.contains(&"cell")?);
+        assert!(!meta.contains(&"block")?);
+
+        // Process is killed between the writes: the batch is lost with both of them
+        drop(transaction);
+    }
+    check_block_persisted(dir.path(), false)?;
+
+    {
+        let db = RocksDb::open_with_cfs(dir.path(), &["default", "meta"])?;
+        let meta = db.cf("meta")?;
+        let transaction = db.begin_multi_cf_transaction()?;
+        transaction.put(&"cell", b"cell data");
+        transaction.put_cf(&meta, &"block", b"block meta")?;
+        transaction.commit_sync()?;
+    }
+    check_block_persisted(dir.path(), true)?;
+
+    {
+        let db = RocksDb::open_with_cfs(dir.path(), &["default", "meta"])?;
+        let meta = db.cf("meta")?;
+        let transaction = db.begin_multi_cf_transaction()?;
+        transaction.delete(&"cell");
+        transaction.delete_cf(&meta, &"block")?;
+        transaction.commit()?;
+    }
+    check_block_persisted(dir.path(), false)
+}
+
+#[test]
+fn test_multi_cf_transaction_rejects_column_family_of_other_db() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::open_with_cfs(dir.path().join("first"), &["default", "meta"])?;
+    let other = RocksDb::open_with_cfs(dir.path().join("second"), &["default", "meta"])?;
+    let other_meta = other.cf("meta")?;
+
+    let transaction = db.begin_multi_cf_transaction()?;
+    transaction.put(&"cell", b"cell data");
+    assert!(transaction.put_cf(&other_meta, &"block", b"block meta").is_err());
+    assert!(transaction.delete_cf(&other_meta, &"block").is_err());
+    assert_eq!(transaction.len(), 1);
+    transaction.commit()?;
+
+    assert!(db.contains(&"cell")?);
+    assert!(!other_meta.contains(&"block")?);
+
+    Ok(())
+}
+
+fn check_contains_multi(db: &dyn KvcReadable<U32Key>, present: &[bool]) -> Result<()> {
+    // Keys in reverse order with a duplicate, to check the result follows the input
+    let keys: Vec<_> = (0..present.len() as u32).rev().chain(Some(0)).map(U32Key::with_value).collect();
+    let expected: Vec<_> = present.iter().rev().chain(present.first()).cloned().collect();
+    assert_eq!(db.contains_multi(&keys)?, expected);
+    assert_eq!(db.contains_multi(&Vec::<U32Key>::new())?, Vec::<bool>::new());
+
+    Ok(())
+}
+
+#[test]
+fn test_contains_multi_reports_present_and_absent_keys() -> Result<()> {
+    let present: Vec<_> = (0..50).map(|i| i % 3 != 1).collect();
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::open_with_cfs(dir.path(), &["default", "extra"])?;
+    let extra = db.cf("extra")?;
+    for (i, &is_present) in present.iter().enumerate() {
+        if is_present {
+            db.put(&U32Key::with_value(i as u32), b"value")?;
+            extra.put(&U32Key::with_value(i as u32), b"value")?;
+        }
+    }
+
+    check_contains_multi(&db, &present)?;
+    check_contains_multi(&extra, &present)?;
+    let snapshot = db.snapshot()?;
+    let extra_snapshot = extra.snapshot()?;
+    for i in 0..present.len() as u32 {
+        db.put(&U32Key::with_value(i), b"value")?;
+        extra.delete(&U32Key::with_value(i))?;
+    }
+    check_contains_multi(&*snapshot, &present)?;
+    check_contains_multi(&*extra_snapshot, &present)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_for_each_parallel_visits_same_keys_as_serial_scan() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::with_path(dir.path());
+    for i in 0..5000u32 {
+        db.put(&U32Key::with_value(i), &i.to_le_bytes())?;
+    }
+    let mut serial: Vec<_> = all_items(&db)?.into_iter().map(|(key, _value)| key).collect();
+    serial.sort();
+
+    for &shards in &[0, 1, 3, 16, 256, 1000] {
+        let visited = Arc::new(Mutex::new(Vec::new()));
+        let completed = db.for_each_parallel(shards, || {
+            let visited = Arc::clone(&visited);
+            Box::new(move |key: &[u8], value: &[u8]| -> Result<bool> {
+                assert_eq!(key, value);
+                visited.lock().unwrap().push(key.to_vec());
+                Ok(true)
+            })
+        })?;
+        assert!(completed);
+        let mut visited = visited.lock().unwrap().clone();
+        visited.sort();
+        assert_eq!(visited, serial, "{} shards", shards);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_for_each_parallel_stops_all_shards() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::with_path(dir.path());
+    for i in 0..100_000u32 {
+        db.put(&U32Key::with_value(i), &i.to_le_bytes())?;
+    }
+
+    let visited = Arc::new(AtomicUsize::new(0));
+    let completed = db.for_each_parallel(16, || {
+        let visited = Arc::clone(&visited);
+        Box::new(move |_key: &[u8], _value: &[u8]| -> Result<bool> {
+            let count = visited.fetch_add(1, Ordering::SeqCst);
+            Ok(count < 100)
+        })
+    })?;
+    assert!(!completed);
+    assert!(visited.load(Ordering::SeqCst) < 100_000);
+
+    let result = db.for_each_parallel(16, || {
+        Box::new(|_key: &[u8], _value: &[u8]| -> Result<bool> { Err(error!("Stopped by predicate")) })
+    });
+    assert!(result.is_err());
+
+    Ok(())
+}
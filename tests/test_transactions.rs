@@ -0,0 +1,153 @@
+use std::sync::{Arc, Barrier};
+
+use ton_types::Result;
+
+use ton_node_storage::db::memorydb::MemoryDb;
+use ton_node_storage::db::rocksdb::RocksDb;
+use ton_node_storage::db::traits::{
+    KvcOptimisticTransactional, KvcReadable, KvcTransaction, KvcTransactional, KvcWriteable, U32Key
+};
+use ton_node_storage::error::StorageError;
+
+/// Runs two optimistic transactions, which both read and update the same key, committing them
+/// at once. Returns the value got by the key and count of committed transactions
+fn race_increments<T>(db: Arc<T>) -> Result<(u32, usize)>
+where
+    T: KvcOptimisticTransactional<U32Key> + KvcWriteable<U32Key> + 'static
+{
+    let key = U32Key::with_value(1);
+    db.put(&key, &0u32.to_le_bytes())?;
+
+    let barrier = Arc::new(Barrier::new(2));
+    let workers: Vec<_> = (0..2)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            let barrier = Arc::clone(&barrier);
+            std::thread::spawn(move || -> Result<bool> {
+                let key = U32Key::with_value(1);
+                let transaction = db.begin_optimistic_transaction()?;
+                let mut value = [0; 4];
+                value.copy_from_slice(transaction.get(&key)?.as_ref());
+                transaction.put(&key, &(u32::from_le_bytes(value) + 1).to_le_bytes());
+                // Both transactions have read the key before any of them commits
+                barrier.wait();
+                match transaction.commit() {
+                    Ok(()) => Ok(true),
+                    Err(err) => {
+                        assert!(matches!(
+                            err.downcast_ref::<StorageError>(),
+                            Some(StorageError::TransactionConflict(_))
+                        ));
+                        Ok(false)
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut committed = 0;
+    for worker in workers {
+        if worker.join().expect("Transaction thread panicked")? {
+            committed += 1;
+        }
+    }
+    let mut value = [0; 4];
+    value.copy_from_slice(db.get(&key)?.as_ref());
+
+    Ok((u32::from_le_bytes(value), committed))
+}
+
+#[test]
+fn test_one_of_racing_rocksdb_transactions_commits() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = Arc::new(RocksDb::with_path(dir.path()));
+    for _ in 0..20 {
+        let (value, committed) = race_increments(Arc::clone(&db))?;
+        assert_eq!(committed, 1);
+        assert_eq!(value, 1);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_one_of_racing_memorydb_transactions_commits() -> Result<()> {
+    let db = Arc::new(MemoryDb::new());
+    for _ in 0..20 {
+        let (value, committed) = race_increments(Arc::clone(&db))?;
+        assert_eq!(committed, 1);
+        assert_eq!(value, 1);
+    }
+
+    Ok(())
+}
+
+/// Checks that transaction reads the state taken at its start along with its own pending writes
+fn check_snapshot_reads<T: KvcTransactional<U32Key>>(db: &T) -> Result<()> {
+    let (kept, changed, written, deleted) =
+        (U32Key::with_value(1), U32Key::with_value(2), U32Key::with_value(3), U32Key::with_value(4));
+    db.put(&kept, b"kept")?;
+    db.put(&changed, b"before")?;
+    db.put(&deleted, b"deleted")?;
+
+    let transaction = db.begin_transaction_with_snapshot()?;
+    // Concurrent writes after the start are not seen by the transaction
+    db.put(&changed, b"after")?;
+    db.put(&written, b"concurrent")?;
+    assert_eq!(transaction.get(&changed)?.as_ref(), b"before");
+    assert!(transaction.try_get(&written)?.is_none());
+
+    transaction.put(&written, b"own");
+    transaction.delete(&deleted);
+    assert_eq!(transaction.get(&written)?.as_ref(), b"own");
+    assert!(transaction.try_get(&deleted)?.is_none());
+    assert_eq!(transaction.get(&kept)?.as_ref(), b"kept");
+    // Nothing is applied before commit
+    assert_eq!(db.get(&written)?.as_ref(), b"concurrent");
+    assert!(db.contains(&deleted)?);
+
+    transaction.commit()?;
+    assert_eq!(db.get(&written)?.as_ref(), b"own");
+    assert!(!db.contains(&deleted)?);
+    assert_eq!(db.get(&changed)?.as_ref(), b"after");
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_snapshot_transaction_reads() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    check_snapshot_reads(&RocksDb::with_path(dir.path()))
+}
+
+#[test]
+fn test_memorydb_snapshot_transaction_reads() -> Result<()> {
+    check_snapshot_reads(&MemoryDb::new())
+}
+
+#[test]
+fn test_chunked_transaction_keeps_batch_bounded() -> Result<()> {
+    const MAX_BYTES: usize = 64 * 1024;
+    const VALUE_SIZE: usize = 100;
+    const COUNT: u32 = 10_000;
+
+    let dir = tempfile::tempdir()?;
+    let db = RocksDb::with_path(dir.path());
+    let transaction = db.begin_transaction_chunked::<U32Key>(MAX_BYTES)?;
+    let mut peak = 0;
+    for i in 0..COUNT {
+        transaction.put(&U32Key::with_value(i), &[i as u8; VALUE_SIZE]);
+        peak = peak.max(transaction.len());
+    }
+    // Every operation takes more than the value itself, so chunk holds less than this
+    assert!(peak <= MAX_BYTES / VALUE_SIZE, "peak chunk of {} operations", peak);
+
+    // Chunks are written before commit
+    assert!(db.contains(&U32Key::with_value(0))?);
+    transaction.commit()?;
+    for i in 0..COUNT {
+        assert_eq!(db.get(&U32Key::with_value(i))?.as_ref(), &[i as u8; VALUE_SIZE][..]);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use serde_derive::{Deserialize, Serialize};
+use ton_types::Result;
+
+use ton_node_storage::db::traits::{KvcReadable, KvcSnapshotable, KvcTransaction, KvcTransactional, KvcWriteable, U32Key};
+use ton_node_storage::error::StorageError;
+use ton_node_storage::typed_db::TypedDb;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Sample {
+    seq_no: u32,
+    name: String,
+    flags: Option<u64>,
+    hashes: Vec<[u8; 4]>,
+}
+
+fn sample(seq_no: u32) -> Sample {
+    Sample {
+        seq_no,
+        name: format!("sample {}", seq_no),
+        flags: if seq_no % 2 == 0 { Some(seq_no as u64 * 1000) } else { None },
+        hashes: (0..seq_no as u8).map(|i| [i; 4]).collect(),
+    }
+}
+
+fn check_round_trip(db: &TypedDb<U32Key, Sample>) -> Result<()> {
+    for seq_no in 0..10 {
+        db.put(&U32Key::with_value(seq_no), &sample(seq_no))?;
+    }
+    for seq_no in 0..10 {
+        assert_eq!(db.get(&U32Key::with_value(seq_no))?, sample(seq_no));
+    }
+
+    let transaction = db.begin_transaction()?;
+    TypedDb::<U32Key, Sample>::put_to(&*transaction, &U32Key::with_value(10), &sample(10))?;
+    transaction.commit()?;
+    assert_eq!(db.try_get(&U32Key::with_value(10))?, Some(sample(10)));
+
+    let snapshot = db.snapshot()?;
+    db.delete(&U32Key::with_value(10))?;
+    assert_eq!(TypedDb::<U32Key, Sample>::get_from(&*snapshot, &U32Key::with_value(10))?, sample(10));
+    assert_eq!(db.try_get(&U32Key::with_value(10))?, None);
+
+    let err = db.get(&U32Key::with_value(10)).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::KeyNotFound(_, _))));
+
+    Ok(())
+}
+
+fn check_corrupted_value(db: &TypedDb<U32Key, Sample>) -> Result<()> {
+    // Value is cut in the middle of `name` length
+    KvcWriteable::put(&**db, &U32Key::with_value(1), &[1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF])?;
+
+    let err = db.get(&U32Key::with_value(1)).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::Deserialization(_))));
+    let err = db.try_get(&U32Key::with_value(1)).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::Deserialization(_))));
+
+    // The raw value is kept as is
+    assert_eq!(KvcReadable::get(&**db, &U32Key::with_value(1))?.len(), 8);
+
+    Ok(())
+}
+
+#[test]
+fn test_typed_db_round_trip_in_memory() -> Result<()> {
+    check_round_trip(&TypedDb::in_memory())
+}
+
+#[test]
+fn test_typed_db_round_trip_rocksdb() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    check_round_trip(&TypedDb::with_path(dir.path()))
+}
+
+#[test]
+fn test_typed_db_reports_deserialization_error() -> Result<()> {
+    check_corrupted_value(&TypedDb::in_memory())?;
+    let dir = tempfile::tempdir()?;
+    check_corrupted_value(&TypedDb::with_path(dir.path()))
+}